@@ -4,10 +4,43 @@ import initWasm, {
     Downloader,
     Generator,
     GenerationArguments,
+    LocalImport,
+    RepositoryDownload,
 } from "@/models/pkg/gh_pages_rust";
 
 import { WorkerSendMessageType, WorkerReceiveMessageType } from "./worker_enum";
 
+type LogitsProcessor = (logits: Float32Array, tokens: Uint32Array) => Float32Array | undefined;
+
+interface GenerationSettings {
+    temperature?: number;
+    top_k?: number;
+    top_p?: number;
+    sample_len?: number;
+    frequency_penalty?: number;
+    presence_penalty?: number;
+    logit_bias?: Record<string, number>;
+    banned_sequences?: string[];
+    // Above 1, beam search replaces sampling and the text arrives once it is done
+    num_beams?: number;
+    // Functions cannot be posted to the worker, so processors are ES modules it imports by
+    // URL. The default export of each is called on the logits of every token, see
+    // Generator.generate_with_processors
+    logits_processor_modules?: string[];
+}
+
+async function importLogitsProcessors(urls: string[]): Promise<LogitsProcessor[]> {
+    return Promise.all(
+        urls.map(async (url) => {
+            const module = await import(/* webpackIgnore: true */ /* turbopackIgnore: true */ url);
+            if (typeof module.default !== "function") {
+                throw new Error(`${url} does not export a logits processor as default`);
+            }
+            return module.default as LogitsProcessor;
+        })
+    );
+}
+
 const wasmLocalPath = new URL(
     "@/models/pkg/gh_pages_rust_bg.wasm",
     import.meta.url
@@ -15,14 +48,14 @@ const wasmLocalPath = new URL(
 
 class Worker {
     private repository_name: string;
-    private downloader: Downloader;
+    private download: RepositoryDownload | undefined;
     private generator: Generator | undefined;
     private _isDownloading: boolean;
     private _isDownloaded: boolean;
 
     constructor(repository_name: string) {
         this.repository_name = repository_name;
-        this.downloader = new Downloader(repository_name);
+        this.download = undefined;
         this.generator = undefined;
         this._isDownloading = false;
         this._isDownloaded = false;
@@ -30,8 +63,9 @@ class Worker {
 
     public setRepository(repository_name: string) {
         if (repository_name === this.repository_name) return;
+        this.download?.cancel();
+        this.download = undefined;
         this.repository_name = repository_name;
-        this.downloader = new Downloader(repository_name);
         this.generator = undefined;
         this._isDownloading = false;
         this.setIsDownloaded(false);
@@ -56,12 +90,42 @@ class Worker {
     }
 
     public async clearCache() {
-        const [modelRemoved, tokenizerRemoved] = await Promise.all([
-            Downloader.remove("model"),
-            Downloader.remove("tokenizer"),
-        ]);
+        const removed = await RepositoryDownload.remove_repository(
+            this.repository_name
+        );
+
+        this.setIsDownloaded(!removed);
+    }
+
+    public async importFiles(files: File[]) {
+        const local = new LocalImport();
+        files.forEach((file) => local.add_file(file));
 
-        this.setIsDownloaded(!(modelRemoved && tokenizerRemoved));
+        local.on("skip", (filename) => {
+            console.log(`Not a model file, skipping: ${filename}`);
+        });
+
+        local.on("file_complete", (filename) => {
+            console.log(`Imported ${filename}`);
+        });
+
+        const repository = await local.start();
+        this.setRepository(repository);
+        await this.checkDownloaded();
+
+        return repository;
+    }
+
+    public async exportRepository() {
+        return await Downloader.export_repository(this.repository_name);
+    }
+
+    public async importArchive(archive: Blob) {
+        const repository = await Downloader.import_archive(archive);
+        this.setRepository(repository);
+        await this.checkDownloaded();
+
+        return repository;
     }
 
     public async downloadRepository() {
@@ -77,56 +141,75 @@ class Worker {
             return;
         }
 
-        const modelDownload = this.downloader.save_file(
-            "model.safetensors",
-            "model"
-        );
-
-        const tokenDownload = this.downloader.save_file(
-            "tokenizer.json",
-            "tokenizer"
-        );
-
-        const configDownload = this.downloader.save_file(
-            "config.json",
-            "config"
-        );
-
-        this.setIsDownloading(true);
+        try {
+            const preview = await new Downloader(
+                this.repository_name
+            ).preview_weights();
+            console.log(
+                `${this.repository_name}: ${preview.parameter_count} parameters, ` +
+                    `${preview.file_size} bytes, dtypes ${Object.keys(preview.dtypes).join(", ")}`
+            );
+        } catch (e) {
+            console.log(`Could not preview ${this.repository_name}: ${e}`);
+        }
 
-        const [model, tokenizer, config] = await Promise.all([
-            modelDownload.start(),
-            tokenDownload.start(),
-            configDownload.start(),
-        ]);
+        const download = new RepositoryDownload(this.repository_name);
+        // f32 checkpoints are stored as f16 once, instead of being converted on every load
+        download.set_weights_dtype("f16");
 
-        modelDownload.on(
+        download.on(
             "progress",
-            (filename, bytesReceived, totalBytes, percentage) => {
+            (
+                repository,
+                bytesReceived,
+                totalBytes,
+                percentage,
+                bytesPerSecond,
+                etaSeconds
+            ) => {
                 console.log(
-                    `Downloading ${filename}: ${bytesReceived} / ${totalBytes} (${percentage}%)`
+                    `Downloading ${repository}: ${bytesReceived} / ${totalBytes} (${percentage}%), ` +
+                        `${Math.round(bytesPerSecond / 1024)} KiB/s, ETA ${etaSeconds?.toFixed(0)} s`
                 );
             }
         );
 
-        modelDownload.on("complete", (filename) => {
+        download.on("file_complete", (filename) => {
             console.log(`Download complete: ${filename}`);
         });
 
-        await this.checkDownloaded();
-        this.setIsDownloading(false);
+        download.on("retry", (filename, attempt, delayMs, reason) => {
+            console.log(
+                `Retrying ${filename} in ${delayMs} ms after attempt ${attempt}: ${reason}`
+            );
+        });
 
-        return [model, tokenizer, config];
+        download.on("abort", (repository) => {
+            console.log(`Download cancelled: ${repository}`);
+        });
+
+        this.download = download;
+
+        this.setIsDownloading(true);
+
+        await Downloader.request_persistent_storage();
+
+        try {
+            // Files are loaded from the cache afterwards, the returned copies are not needed
+            await download.start();
+        } finally {
+            if (this.download === download) {
+                this.download = undefined;
+                await this.checkDownloaded();
+                this.setIsDownloading(false);
+            }
+        }
     }
 
     public async checkDownloaded() {
-        const exists = await Promise.all([
-            Downloader.model_exists(),
-            Downloader.tokenizer_exists(),
-            Downloader.config_exists(),
-        ]);
-
-        const downloaded = exists.every((e) => e);
+        const downloaded = await RepositoryDownload.is_complete(
+            this.repository_name
+        );
 
         this.setIsDownloaded(downloaded);
 
@@ -136,37 +219,52 @@ class Worker {
     public async generateText(
         prompt: string,
         callback: (text: string) => void,
-        args?: GenerationArguments
+        settings?: GenerationSettings
     ) {
-        const data = await this.downloadRepository();
-
-        let model = data ? data[0] : undefined;
-        let tokenizer = data ? data[1] : undefined;
-        let config = data ? data[2] : undefined;
-
-        if (model === undefined) {
-            model = await Downloader.get("model");
-        }
-
-        if (tokenizer === undefined) {
-            tokenizer = await Downloader.get("tokenizer");
-        }
-
-        if (config === undefined) {
-            config = await Downloader.get("config");
+        await this.downloadRepository();
+
+        // Defaults come from the repository's generation_config.json when it has one
+        const args = await GenerationArguments.from_cache(this.repository_name);
+        args.temperature = settings?.temperature ?? args.temperature;
+        args.top_k = settings?.top_k ?? args.top_k;
+        args.top_p = settings?.top_p ?? args.top_p;
+        args.sample_len = settings?.sample_len ?? args.sample_len;
+        args.frequency_penalty = settings?.frequency_penalty ?? args.frequency_penalty;
+        args.presence_penalty = settings?.presence_penalty ?? args.presence_penalty;
+        args.banned_sequences = settings?.banned_sequences ?? args.banned_sequences;
+        args.num_beams = settings?.num_beams ?? args.num_beams;
+        if (settings?.logit_bias) args.set_logit_bias(settings.logit_bias);
+
+        // Weights are converted tensor by tensor from the cache, so the whole file never
+        // sits in wasm memory next to the model
+        let generator: Generator;
+        try {
+            generator = await Generator.from_cache(this.repository_name);
+        } catch (e) {
+            console.log(`Could not load the model: ${e}`);
+            return;
         }
 
-        if (!model || !tokenizer || !config) {
-            console.log("Model, tokenizer, or config not found");
+        let processors: LogitsProcessor[];
+        try {
+            processors = await importLogitsProcessors(settings?.logits_processor_modules ?? []);
+        } catch (e) {
+            console.log(`Could not load the logits processors: ${e}`);
             return;
         }
 
-        const generator = new Generator(model, tokenizer, config);
-
         console.log("Model loading done, begin generating...");
 
         const startTime = performance.now();
-        generator.generate(prompt, args, callback);
+        try {
+            if (processors.length) {
+                generator.generate_with_processors(prompt, args, processors, callback);
+            } else {
+                generator.generate(prompt, args, callback);
+            }
+        } catch (e) {
+            console.log(`Generation failed: ${e}`);
+        }
         const endTime = performance.now();
 
         console.log(`Generation took ${endTime - startTime} ms`);
@@ -204,11 +302,6 @@ initWasm(wasmFullPath).then(() => {
                 case WorkerReceiveMessageType.GenerateText:
                     {
                         const { prompt, args } = value;
-                        const genArgs = new GenerationArguments();
-                        genArgs.temperature = args.temperature;
-                        genArgs.top_k = args.top_k;
-                        genArgs.top_p = args.top_p;
-                        genArgs.sample_len = args.sample_len;
 
                         await worker.generateText(
                             prompt,
@@ -218,7 +311,7 @@ initWasm(wasmFullPath).then(() => {
                                     value: token,
                                 });
                             },
-                            genArgs
+                            args
                         );
 
                         postMessage({
@@ -237,6 +330,36 @@ initWasm(wasmFullPath).then(() => {
                     }
                     break;
 
+                case WorkerReceiveMessageType.ImportFiles:
+                    {
+                        const repository = await worker.importFiles(value);
+                        postMessage({
+                            type: WorkerSendMessageType.FilesImported,
+                            value: repository,
+                        });
+                    }
+                    break;
+
+                case WorkerReceiveMessageType.ExportRepository:
+                    {
+                        const archive = await worker.exportRepository();
+                        postMessage({
+                            type: WorkerSendMessageType.RepositoryExported,
+                            value: archive,
+                        });
+                    }
+                    break;
+
+                case WorkerReceiveMessageType.ImportArchive:
+                    {
+                        const repository = await worker.importArchive(value);
+                        postMessage({
+                            type: WorkerSendMessageType.ArchiveImported,
+                            value: repository,
+                        });
+                    }
+                    break;
+
                 case WorkerReceiveMessageType.ClearCache:
                     {
                         await worker.clearCache();
@@ -7,6 +7,9 @@ export enum WorkerSendMessageType {
     TextGenerateDone = "text_generate_done",
     RepositorySet = "repository_set",
     CacheCleared = "cache_cleared",
+    FilesImported = "files_imported",
+    RepositoryExported = "repository_exported",
+    ArchiveImported = "archive_imported",
     WorkerReady = "worker_ready",
 }
 
@@ -16,4 +19,7 @@ export enum WorkerReceiveMessageType {
     GenerateText = "generate_text",
     SetRepository = "set_repository",
     ClearCache = "clear_cache",
+    ImportFiles = "import_files",
+    ExportRepository = "export_repository",
+    ImportArchive = "import_archive",
 }
@@ -0,0 +1,457 @@
+use std::ops::Range;
+
+use js_sys::Uint8Array;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Blob, IdbTransactionMode};
+
+use crate::cache::CacheEntry;
+use crate::downloader::{Downloader, METADATA_STORE_NAME, STORE_NAME};
+use crate::repository::{repository_key, RepositoryDownload, RepositoryManifest};
+
+/// Version of the archive layout, bumped whenever the index changes incompatibly.
+pub const ARCHIVE_VERSION: u32 = 1;
+
+/// First member of every archive, describing the files that follow.
+pub const INDEX_PATH: &str = "index.json";
+
+/// Directory of the archive holding the cached files.
+pub const FILES_DIR: &str = "files";
+
+pub const BLOCK_SIZE: usize = 512;
+
+/// Largest member a plain ustar header can describe: 11 octal digits.
+pub const MAX_MEMBER_SIZE: u64 = 0o77777777777;
+
+/// Bytes hashed per step, so exported files are never copied into the wasm heap whole.
+const HASH_CHUNK_SIZE: u32 = 4 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ArchivedFile {
+    pub filename: String,
+    /// Hex SHA-256 of the contents, checked on import.
+    pub sha256: String,
+    pub entry: CacheEntry,
+}
+
+/// Contents of [`INDEX_PATH`].
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ArchiveIndex {
+    pub version: u32,
+    pub repository: String,
+    pub manifest: Option<RepositoryManifest>,
+    pub files: Vec<ArchivedFile>,
+}
+
+impl ArchiveIndex {
+    pub fn file_path(filename: &str) -> String {
+        format!("{}/{}", FILES_DIR, filename)
+    }
+}
+
+/// A member of a tar archive.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TarHeader {
+    pub path: String,
+    pub size: u64,
+    /// Directories, links and extension headers are not regular files.
+    pub regular: bool,
+}
+
+/// Bytes of zero padding after a member of `size` bytes.
+pub fn padding(size: u64) -> usize {
+    let rest = (size % BLOCK_SIZE as u64) as usize;
+    (BLOCK_SIZE - rest) % BLOCK_SIZE
+}
+
+/// The two zero blocks closing an archive.
+pub fn end_of_archive() -> [u8; 2 * BLOCK_SIZE] {
+    [0; 2 * BLOCK_SIZE]
+}
+
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    let text = format!("{:0width$o}", value, width = digits);
+    field[..digits].copy_from_slice(text.as_bytes());
+    field[digits] = 0;
+}
+
+fn read_octal(field: &[u8]) -> Result<u64, String> {
+    let text: String = field
+        .iter()
+        .take_while(|byte| **byte != 0)
+        .map(|byte| *byte as char)
+        .collect();
+    let text = text.trim();
+
+    if text.is_empty() {
+        return Ok(0);
+    }
+
+    u64::from_str_radix(text, 8).map_err(|_| format!("Invalid number in tar header: {}", text))
+}
+
+fn checksum(block: &[u8; BLOCK_SIZE]) -> u64 {
+    // The checksum field itself counts as spaces
+    block
+        .iter()
+        .enumerate()
+        .map(|(index, byte)| {
+            if (148..156).contains(&index) {
+                b' ' as u64
+            } else {
+                *byte as u64
+            }
+        })
+        .sum()
+}
+
+/// A ustar header for a regular file. Paths longer than 100 bytes are split into the
+/// 155 byte prefix field at a `/`.
+pub fn tar_header(path: &str, size: u64, mtime_seconds: u64) -> Result<[u8; BLOCK_SIZE], String> {
+    if size > MAX_MEMBER_SIZE {
+        return Err(format!("{} is too large for a tar archive", path));
+    }
+
+    let (prefix, name) = if path.len() <= 100 {
+        ("", path)
+    } else {
+        path.char_indices()
+            .filter(|(index, c)| *c == '/' && *index <= 155 && path.len() - index - 1 <= 100)
+            .map(|(index, _)| (&path[..index], &path[index + 1..]))
+            .next()
+            .ok_or_else(|| format!("Path is too long for a tar archive: {}", path))?
+    };
+
+    let mut block = [0u8; BLOCK_SIZE];
+    block[..name.len()].copy_from_slice(name.as_bytes());
+    write_octal(&mut block[100..108], 0o644);
+    write_octal(&mut block[108..116], 0);
+    write_octal(&mut block[116..124], 0);
+    write_octal(&mut block[124..136], size);
+    write_octal(&mut block[136..148], mtime_seconds);
+    block[156] = b'0';
+    block[257..263].copy_from_slice(b"ustar\0");
+    block[263..265].copy_from_slice(b"00");
+    block[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+
+    let sum = checksum(&block);
+    block[148..154].copy_from_slice(format!("{:06o}", sum).as_bytes());
+    block[154] = 0;
+    block[155] = b' ';
+
+    Ok(block)
+}
+
+/// Parses a header block. Resolves to `None` for the zero block that ends the archive.
+pub fn parse_tar_header(block: &[u8]) -> Result<Option<TarHeader>, String> {
+    let block: &[u8; BLOCK_SIZE] = block
+        .try_into()
+        .map_err(|_| "Truncated tar header".to_string())?;
+
+    if block.iter().all(|byte| *byte == 0) {
+        return Ok(None);
+    }
+
+    if read_octal(&block[148..156])? != checksum(block) {
+        return Err("Corrupt tar header".to_string());
+    }
+
+    let text = |field: &[u8]| {
+        let end = field
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(field.len());
+        String::from_utf8_lossy(&field[..end]).into_owned()
+    };
+
+    let name = text(&block[..100]);
+    let prefix = if &block[257..262] == b"ustar" {
+        text(&block[345..500])
+    } else {
+        String::new()
+    };
+
+    Ok(Some(TarHeader {
+        path: if prefix.is_empty() {
+            name
+        } else {
+            format!("{}/{}", prefix, name)
+        },
+        size: read_octal(&block[124..136])?,
+        regular: matches!(block[156], b'0' | 0),
+    }))
+}
+
+/// Writes `members` as a tar archive in memory.
+pub fn write_tar(members: &[(&str, &[u8])], mtime_seconds: u64) -> Result<Vec<u8>, String> {
+    let mut archive = Vec::new();
+
+    for (path, data) in members {
+        archive.extend_from_slice(&tar_header(path, data.len() as u64, mtime_seconds)?);
+        archive.extend_from_slice(data);
+        archive.resize(archive.len() + padding(data.len() as u64), 0);
+    }
+
+    archive.extend_from_slice(&end_of_archive());
+    Ok(archive)
+}
+
+/// Lists the regular files of an in-memory tar archive with the range of their contents.
+pub fn read_tar(archive: &[u8]) -> Result<Vec<(String, Range<usize>)>, String> {
+    let mut members = Vec::new();
+    let mut offset = 0;
+
+    while offset + BLOCK_SIZE <= archive.len() {
+        let Some(header) = parse_tar_header(&archive[offset..offset + BLOCK_SIZE])? else {
+            return Ok(members);
+        };
+
+        let start = offset + BLOCK_SIZE;
+        let end = start + header.size as usize;
+        if end > archive.len() {
+            return Err(format!("{} is truncated", header.path));
+        }
+
+        if header.regular {
+            members.push((header.path, start..end));
+        }
+        offset = end + padding(header.size);
+    }
+
+    Err("Archive ends without an end marker".to_string())
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Checks the index of an archive against the members found after it.
+pub fn check_index(index: &ArchiveIndex, members: &[(String, u64)]) -> Result<(), String> {
+    if index.version != ARCHIVE_VERSION {
+        return Err(format!("Unsupported archive version {}", index.version));
+    }
+
+    for file in &index.files {
+        let path = ArchiveIndex::file_path(&file.filename);
+        let size = members
+            .iter()
+            .find(|(member, _)| *member == path)
+            .map(|(_, size)| *size)
+            .ok_or_else(|| format!("{} is missing from the archive", file.filename))?;
+
+        if size != file.entry.size {
+            return Err(format!(
+                "{} is {} bytes but the index says {}",
+                file.filename, size, file.entry.size
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// SHA-256 of a JS array, copied into the wasm heap a chunk at a time.
+fn sha256_of_array(array: &Uint8Array) -> String {
+    let mut hasher = Sha256::new();
+    let mut chunk = vec![0u8; HASH_CHUNK_SIZE.min(array.length()) as usize];
+
+    let mut offset = 0;
+    while offset < array.length() {
+        let end = (offset + HASH_CHUNK_SIZE).min(array.length());
+        let len = (end - offset) as usize;
+        array.subarray(offset, end).copy_to(&mut chunk[..len]);
+        hasher.update(&chunk[..len]);
+        offset = end;
+    }
+
+    hex(&hasher.finalize())
+}
+
+async fn read_blob(blob: &Blob, start: u64, end: u64) -> Result<Uint8Array, JsValue> {
+    let slice = blob.slice_with_f64_and_f64(start as f64, end as f64)?;
+    let buffer = JsFuture::from(slice.array_buffer()).await?;
+    Ok(Uint8Array::new(&buffer))
+}
+
+fn error(message: String) -> JsValue {
+    JsValue::from_str(&message)
+}
+
+/// Packs every cached file of `repository`, with its metadata and manifest, into a tar blob.
+pub(crate) async fn export_repository(repository: &str) -> Result<Blob, JsValue> {
+    let db = Downloader::open_db().await?;
+    let manifest = RepositoryDownload::read_manifest(repository).await?;
+    let prefix = repository_key(repository, "");
+
+    let entries: Vec<CacheEntry> = Downloader::read_entries(&db)
+        .await?
+        .into_iter()
+        .filter(|entry| entry.repository == repository)
+        .collect();
+
+    if entries.is_empty() {
+        return Err(error(format!("Nothing of {} is cached", repository)));
+    }
+
+    let mut files = Vec::with_capacity(entries.len());
+    let mut contents = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let Some(filename) = entry.key.strip_prefix(&prefix).map(str::to_string) else {
+            continue;
+        };
+        let content = Downloader::get(&entry.key)
+            .await
+            .ok_or_else(|| error(format!("{} is not cached", entry.key)))?;
+
+        files.push(ArchivedFile {
+            filename,
+            sha256: sha256_of_array(&content),
+            entry: CacheEntry {
+                size: content.length() as u64,
+                ..entry
+            },
+        });
+        contents.push(content);
+    }
+
+    let index = ArchiveIndex {
+        version: ARCHIVE_VERSION,
+        repository: repository.to_string(),
+        manifest,
+        files,
+    };
+    let index_json =
+        serde_json::to_vec_pretty(&index).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let mtime = (js_sys::Date::now() / 1000.0) as u64;
+
+    // The blob is assembled from the cached arrays, so file contents never enter the wasm heap
+    let parts = js_sys::Array::new();
+    let push_member = |path: &str, content: &JsValue, size: u64| -> Result<(), JsValue> {
+        let header = tar_header(path, size, mtime).map_err(error)?;
+        parts.push(&Uint8Array::from(&header[..]));
+        parts.push(content);
+        parts.push(&Uint8Array::new_with_length(padding(size) as u32));
+        Ok(())
+    };
+
+    push_member(
+        INDEX_PATH,
+        &Uint8Array::from(&index_json[..]),
+        index_json.len() as u64,
+    )?;
+    for (file, content) in index.files.iter().zip(&contents) {
+        push_member(
+            &ArchiveIndex::file_path(&file.filename),
+            content,
+            file.entry.size,
+        )?;
+    }
+    parts.push(&Uint8Array::from(&end_of_archive()[..]));
+
+    Blob::new_with_u8_array_sequence(&parts)
+}
+
+/// Restores an archive made by [`export_repository`] and resolves to its repository id.
+/// Files are checked against the index and stored one at a time, so only one of them is in
+/// memory. The manifest is written last: an archive that turns out corrupt halfway never
+/// leaves a repository that looks complete.
+pub(crate) async fn import_archive(archive: &Blob) -> Result<String, JsValue> {
+    let size = archive.size() as u64;
+    let mut members: Vec<(String, u64, u64)> = Vec::new();
+
+    let mut offset = 0;
+    loop {
+        if offset + BLOCK_SIZE as u64 > size {
+            return Err(error("Archive ends without an end marker".to_string()));
+        }
+
+        let block = read_blob(archive, offset, offset + BLOCK_SIZE as u64)
+            .await?
+            .to_vec();
+        let Some(header) = parse_tar_header(&block).map_err(error)? else {
+            break;
+        };
+
+        let start = offset + BLOCK_SIZE as u64;
+        if start + header.size > size {
+            return Err(error(format!("{} is truncated", header.path)));
+        }
+
+        if header.regular {
+            members.push((header.path, start, header.size));
+        }
+        offset = start + header.size + padding(header.size) as u64;
+    }
+
+    let (_, index_start, index_size) = members
+        .iter()
+        .find(|(path, _, _)| path == INDEX_PATH)
+        .ok_or_else(|| error(format!("{} is missing from the archive", INDEX_PATH)))?;
+    let index_json = read_blob(archive, *index_start, index_start + index_size)
+        .await?
+        .to_vec();
+    let index: ArchiveIndex =
+        serde_json::from_slice(&index_json).map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+    let sizes: Vec<(String, u64)> = members
+        .iter()
+        .map(|(path, _, size)| (path.clone(), *size))
+        .collect();
+    check_index(&index, &sizes).map_err(error)?;
+
+    let repository = index.repository.clone();
+    let downloader = Downloader::new(&repository);
+    let db = Downloader::open_db().await?;
+    let now = js_sys::Date::now();
+
+    for file in &index.files {
+        let path = ArchiveIndex::file_path(&file.filename);
+        let (_, start, size) = members
+            .iter()
+            .find(|(member, _, _)| *member == path)
+            .ok_or_else(|| error(format!("{} is missing from the archive", path)))?;
+
+        // A standalone copy, since the store would clone the whole buffer behind a view
+        let content = read_blob(archive, *start, start + size).await?;
+        if sha256_of_array(&content) != file.sha256 {
+            return Err(error(format!("{} is corrupt", file.filename)));
+        }
+
+        // Keys are derived again rather than trusted from the archive
+        let key = repository_key(&repository, &file.filename);
+        downloader.ensure_capacity(&key, file.entry.size).await?;
+
+        let entry = CacheEntry {
+            key: key.clone(),
+            repository: repository.clone(),
+            last_accessed: now,
+            ..file.entry.clone()
+        };
+
+        let transaction = Downloader::transaction(
+            &db,
+            &[STORE_NAME, METADATA_STORE_NAME],
+            IdbTransactionMode::Readwrite,
+        )?;
+        let request = transaction
+            .object_store(STORE_NAME)?
+            .put_with_key(&content, &JsValue::from_str(&key))?;
+        Downloader::put_entry(&transaction, &entry)?;
+        Downloader::idbrequest_to_result::<JsValue>(&request).await?;
+    }
+
+    if let Some(manifest) = index.manifest.as_ref() {
+        if manifest.repository == repository {
+            RepositoryDownload::put_manifest(manifest).await?;
+        }
+    }
+
+    Ok(repository)
+}
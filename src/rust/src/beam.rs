@@ -0,0 +1,196 @@
+/// A sequence still being extended, with the sum of the log-probabilities of its tokens.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Beam {
+    pub tokens: Vec<u32>,
+    pub sum_logprobs: f64,
+}
+
+/// A finished sequence, without its EOS token. `score` is the sum of the log-probabilities
+/// divided by the length raised to the length penalty.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hypothesis {
+    pub tokens: Vec<u32>,
+    pub score: f64,
+}
+
+/// Beam search over the tokens of a model, as in `transformers`: every step keeps the
+/// `num_beams` most likely continuations of the current beams, and a beam that picks an EOS
+/// token becomes a hypothesis. Only the `num_beams` best hypotheses are kept.
+pub struct BeamSearch {
+    num_beams: usize,
+    length_penalty: f64,
+    early_stopping: bool,
+    eos_token_ids: Vec<u32>,
+    beams: Vec<Beam>,
+    hypotheses: Vec<Hypothesis>,
+    done: bool,
+}
+
+impl BeamSearch {
+    /// Starts with a single empty beam. A `length_penalty` above 0 favors longer sequences,
+    /// below 0 shorter ones. With `early_stopping`, the search ends as soon as there are
+    /// `num_beams` hypotheses, otherwise once no beam can score better than them.
+    pub fn new(
+        num_beams: usize,
+        length_penalty: f64,
+        early_stopping: bool,
+        eos_token_ids: Vec<u32>,
+    ) -> Self {
+        Self {
+            num_beams: num_beams.max(1),
+            length_penalty,
+            early_stopping,
+            eos_token_ids,
+            beams: vec![Beam {
+                tokens: Vec::new(),
+                sum_logprobs: 0.,
+            }],
+            hypotheses: Vec::new(),
+            done: false,
+        }
+    }
+
+    pub fn beams(&self) -> &[Beam] {
+        &self.beams
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Extends the beams given the log-probabilities of the next token of each of them, in
+    /// the order of `beams()`. Returns, for each of the new beams, the index of the beam it
+    /// continues, so state kept per beam can follow.
+    pub fn step(&mut self, logprobs: &[Vec<f64>]) -> Vec<usize> {
+        let mut candidates = Vec::new();
+        for (parent, (beam, logprobs)) in self.beams.iter().zip(logprobs).enumerate() {
+            for (token, logprob) in top_tokens(logprobs, 2 * self.num_beams) {
+                candidates.push((beam.sum_logprobs + logprob, parent, token));
+            }
+        }
+        candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        let mut beams = Vec::with_capacity(self.num_beams);
+        let mut parents = Vec::with_capacity(self.num_beams);
+        for (rank, (sum_logprobs, parent, token)) in candidates.into_iter().enumerate() {
+            let mut tokens = self.beams[parent].tokens.clone();
+
+            if self.eos_token_ids.contains(&token) {
+                // An EOS token less likely than the beams kept would not be picked by them
+                if rank < self.num_beams {
+                    let len = tokens.len() + 1;
+                    self.add_hypothesis(tokens, sum_logprobs, len);
+                }
+                continue;
+            }
+
+            tokens.push(token);
+            beams.push(Beam {
+                tokens,
+                sum_logprobs,
+            });
+            parents.push(parent);
+            if beams.len() == self.num_beams {
+                break;
+            }
+        }
+
+        self.done = match beams.first() {
+            Some(best) => self.is_finished(best.sum_logprobs, best.tokens.len()),
+            None => true,
+        };
+        self.beams = beams;
+
+        parents
+    }
+
+    /// The `count` best hypotheses, best first. Beams still going are counted as finished.
+    pub fn finish(mut self, count: usize) -> Vec<Hypothesis> {
+        if !self.done {
+            for beam in std::mem::take(&mut self.beams) {
+                let len = beam.tokens.len();
+                self.add_hypothesis(beam.tokens, beam.sum_logprobs, len);
+            }
+        }
+
+        self.hypotheses.sort_by(|a, b| b.score.total_cmp(&a.score));
+        self.hypotheses.truncate(count);
+        self.hypotheses
+    }
+
+    fn score(&self, sum_logprobs: f64, len: usize) -> f64 {
+        sum_logprobs / (len.max(1) as f64).powf(self.length_penalty)
+    }
+
+    fn worst_score(&self) -> Option<f64> {
+        self.hypotheses
+            .iter()
+            .map(|hypothesis| hypothesis.score)
+            .min_by(f64::total_cmp)
+    }
+
+    fn add_hypothesis(&mut self, tokens: Vec<u32>, sum_logprobs: f64, len: usize) {
+        let score = self.score(sum_logprobs, len);
+        if self.hypotheses.len() == self.num_beams {
+            match self.worst_score() {
+                Some(worst) if score > worst => {
+                    let index = self
+                        .hypotheses
+                        .iter()
+                        .position(|hypothesis| hypothesis.score == worst)
+                        .unwrap_or_default();
+                    self.hypotheses.swap_remove(index);
+                }
+                _ => return,
+            }
+        }
+
+        self.hypotheses.push(Hypothesis { tokens, score });
+    }
+
+    fn is_finished(&self, best_sum_logprobs: f64, len: usize) -> bool {
+        if self.hypotheses.len() < self.num_beams {
+            return false;
+        }
+        if self.early_stopping {
+            return true;
+        }
+
+        self.worst_score()
+            .is_some_and(|worst| worst >= self.score(best_sum_logprobs, len))
+    }
+}
+
+/// The log-probabilities of the tokens given their logits.
+pub fn log_softmax(logits: &[f32]) -> Vec<f64> {
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max) as f64;
+    let log_total = logits
+        .iter()
+        .map(|&logit| (logit as f64 - max).exp())
+        .sum::<f64>()
+        .ln();
+
+    logits
+        .iter()
+        .map(|&logit| logit as f64 - max - log_total)
+        .collect()
+}
+
+/// The `count` most likely tokens with their log-probabilities. Tokens that cannot be picked
+/// at all are left out.
+fn top_tokens(logprobs: &[f64], count: usize) -> Vec<(u32, f64)> {
+    let mut tokens: Vec<(u32, f64)> = logprobs
+        .iter()
+        .enumerate()
+        .filter(|(_, logprob)| logprob.is_finite())
+        .map(|(token, &logprob)| (token as u32, logprob))
+        .collect();
+
+    if tokens.len() > count {
+        tokens.select_nth_unstable_by(count, |a, b| b.1.total_cmp(&a.1));
+        tokens.truncate(count);
+    }
+    tokens.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    tokens
+}
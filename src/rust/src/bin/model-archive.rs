@@ -0,0 +1,196 @@
+//! Packs and unpacks model archives natively, in the format `Downloader.export_repository`
+//! writes and `Downloader.import_archive` reads, so a model can move between a browser and a
+//! local directory such as a Hugging Face snapshot.
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Result};
+use clap::{Parser, Subcommand};
+use gh_pages_rust::archive::{
+    check_index, read_tar, sha256_hex, write_tar, ArchiveIndex, ArchivedFile, ARCHIVE_VERSION,
+    INDEX_PATH,
+};
+use gh_pages_rust::cache::CacheEntry;
+use gh_pages_rust::downloader::DEFAULT_REVISION;
+use gh_pages_rust::repository::{repository_key, RepositoryManifest};
+
+#[derive(Parser)]
+#[command(about = "Packs and unpacks cached model archives")]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Packs every file of a directory as the cached files of a repository.
+    Pack {
+        /// Repository id the files are imported under, such as `timinar/baby-llama-58m`.
+        #[arg(long)]
+        repository: String,
+        directory: PathBuf,
+        output: PathBuf,
+    },
+    /// Checks an archive and writes its files to a directory.
+    Unpack {
+        archive: PathBuf,
+        directory: PathBuf,
+    },
+    /// Prints the index of an archive.
+    List { archive: PathBuf },
+}
+
+fn main() -> Result<()> {
+    match Args::parse().command {
+        Command::Pack {
+            repository,
+            directory,
+            output,
+        } => pack(&repository, &directory, &output),
+        Command::Unpack { archive, directory } => unpack(&archive, &directory),
+        Command::List { archive } => {
+            let archive = fs::read(&archive)?;
+            let (index, _) = read_index(&archive)?;
+            println!("{}", serde_json::to_string_pretty(&index)?);
+            Ok(())
+        }
+    }
+}
+
+fn pack(repository: &str, directory: &Path, output: &Path) -> Result<()> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+    let now_ms = now.as_millis() as f64;
+
+    let mut filenames = Vec::new();
+    list_files(directory, directory, &mut filenames)?;
+    filenames.sort();
+    if filenames.is_empty() {
+        bail!("{} has no files", directory.display());
+    }
+
+    let mut files = Vec::with_capacity(filenames.len());
+    let mut contents = Vec::with_capacity(filenames.len());
+    for filename in &filenames {
+        let content = fs::read(directory.join(filename))?;
+        files.push(ArchivedFile {
+            filename: filename.clone(),
+            sha256: sha256_hex(&content),
+            entry: CacheEntry::new(
+                &repository_key(repository, filename),
+                content.len() as u64,
+                repository,
+                DEFAULT_REVISION,
+                now_ms,
+            ),
+        });
+        contents.push(content);
+    }
+
+    let index = ArchiveIndex {
+        version: ARCHIVE_VERSION,
+        repository: repository.to_string(),
+        manifest: Some(RepositoryManifest {
+            repository: repository.to_string(),
+            revision: DEFAULT_REVISION.to_string(),
+            files: filenames.clone(),
+            completed_at: now_ms,
+        }),
+        files,
+    };
+    let index_json = serde_json::to_vec_pretty(&index)?;
+
+    let paths: Vec<String> = filenames
+        .iter()
+        .map(|filename| ArchiveIndex::file_path(filename))
+        .collect();
+    let mut members: Vec<(&str, &[u8])> = vec![(INDEX_PATH, &index_json)];
+    members.extend(
+        paths
+            .iter()
+            .map(String::as_str)
+            .zip(contents.iter().map(Vec::as_slice)),
+    );
+
+    fs::write(
+        output,
+        write_tar(&members, now.as_secs()).map_err(|e| anyhow!(e))?,
+    )?;
+    Ok(())
+}
+
+fn unpack(archive: &Path, directory: &Path) -> Result<()> {
+    let archive = fs::read(archive)?;
+    let (index, members) = read_index(&archive)?;
+
+    for file in &index.files {
+        // Filenames come from the archive, so they must not climb out of the directory
+        let relative = Path::new(&file.filename);
+        if !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            bail!("Refusing to unpack {}", file.filename);
+        }
+
+        let path = ArchiveIndex::file_path(&file.filename);
+        let content = members
+            .iter()
+            .find(|(member, _)| *member == path)
+            .map(|(_, range)| &archive[range.clone()])
+            .ok_or_else(|| anyhow!("{} is missing from the archive", file.filename))?;
+        if sha256_hex(content) != file.sha256 {
+            bail!("{} is corrupt", file.filename);
+        }
+
+        let target = directory.join(relative);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(target, content)?;
+    }
+
+    Ok(())
+}
+
+type Members = Vec<(String, std::ops::Range<usize>)>;
+
+/// Parses the index of an in-memory archive and checks it against the members.
+fn read_index(archive: &[u8]) -> Result<(ArchiveIndex, Members)> {
+    let members = read_tar(archive).map_err(|e| anyhow!(e))?;
+    let index_range = members
+        .iter()
+        .find(|(path, _)| path == INDEX_PATH)
+        .map(|(_, range)| range.clone())
+        .ok_or_else(|| anyhow!("{} is missing from the archive", INDEX_PATH))?;
+    let index: ArchiveIndex = serde_json::from_slice(&archive[index_range])?;
+
+    let sizes: Vec<(String, u64)> = members
+        .iter()
+        .map(|(path, range)| (path.clone(), range.len() as u64))
+        .collect();
+    check_index(&index, &sizes).map_err(|e| anyhow!(e))?;
+
+    Ok((index, members))
+}
+
+/// Collects the paths of the regular files below `directory`, relative to `root` and
+/// separated by `/` like repository filenames.
+fn list_files(root: &Path, directory: &Path, files: &mut Vec<String>) -> Result<()> {
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            list_files(root, &path, files)?;
+        } else if path.is_file() {
+            let relative = path.strip_prefix(root)?;
+            let components: Vec<String> = relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            files.push(components.join("/"));
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,78 @@
+use js_sys::Uint8Array;
+use wasm_bindgen::prelude::*;
+
+/// Bytes owned by the wasm heap.
+///
+/// A download is copied into a `ByteBuffer` once and handed to `Generator` by value, so the
+/// model is not copied between JS and wasm at every step.
+#[wasm_bindgen]
+#[derive(Default, Debug)]
+pub struct ByteBuffer {
+    bytes: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl ByteBuffer {
+    /// Copies `array` into the wasm heap.
+    pub fn from_uint8array(array: &Uint8Array) -> Result<ByteBuffer, JsValue> {
+        let mut buffer = ByteBuffer::default();
+        buffer.reserve(array.length() as usize)?;
+        buffer.extend_from_uint8array(array);
+
+        Ok(buffer)
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn length(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Copies the bytes into a new JS-owned array. The buffer stays usable.
+    pub fn to_uint8array(&self) -> Uint8Array {
+        Uint8Array::from(&self.bytes[..])
+    }
+}
+
+impl ByteBuffer {
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    /// Makes room for `additional` more bytes up front, so appending never reallocates and
+    /// the contents are never moved. Fails instead of aborting when the heap cannot grow.
+    pub fn reserve(&mut self, additional: usize) -> Result<(), JsValue> {
+        self.bytes.try_reserve_exact(additional).map_err(|_| {
+            JsValue::from_str(&format!("Not enough memory for {} more bytes", additional))
+        })
+    }
+
+    /// Copies `chunk` straight from JS memory to the end of the buffer.
+    pub fn extend_from_uint8array(&mut self, chunk: &Uint8Array) {
+        let start = self.bytes.len();
+        self.bytes.resize(start + chunk.length() as usize, 0);
+        chunk.copy_to(&mut self.bytes[start..]);
+    }
+
+    /// Drops the contents but keeps the allocation for a download that starts over.
+    pub fn clear(&mut self) {
+        self.bytes.clear();
+    }
+}
+
+impl From<Vec<u8>> for ByteBuffer {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self { bytes }
+    }
+}
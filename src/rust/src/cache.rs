@@ -0,0 +1,186 @@
+use std::cell::RefCell;
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::hub::FileRevision;
+
+thread_local! {
+    /// Repository of the model this context loaded last. Its files are kept from eviction.
+    static LOADED_REPOSITORY: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+pub fn set_loaded_repository(repository: &str) {
+    LOADED_REPOSITORY.with(|loaded| *loaded.borrow_mut() = Some(repository.to_string()));
+}
+
+pub fn loaded_repository() -> Option<String> {
+    LOADED_REPOSITORY.with(|loaded| loaded.borrow().clone())
+}
+
+/// Keys eviction must keep while `key` is stored: the key itself and every cached file of
+/// `repositories`. Storing one file of a repository then never evicts the files stored
+/// before it by the same download or import.
+pub fn protected_keys<'a>(
+    entries: &'a [CacheEntry],
+    key: &'a str,
+    repositories: &[&str],
+) -> Vec<&'a str> {
+    std::iter::once(key)
+        .chain(
+            entries
+                .iter()
+                .filter(|entry| repositories.contains(&entry.repository.as_str()))
+                .map(|entry| entry.key.as_str()),
+        )
+        .collect()
+}
+
+/// Bookkeeping stored next to every cached file so the cache can be sized and pruned.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CacheEntry {
+    pub key: String,
+    pub size: u64,
+    pub repository: String,
+    pub revision: String,
+    pub downloaded_at: f64,
+    pub last_accessed: f64,
+    /// Content hash reported when the file was downloaded. Missing for entries written by
+    /// versions that did not record it.
+    #[serde(default)]
+    pub etag: Option<String>,
+    /// Commit `revision` resolved to at download time.
+    #[serde(default)]
+    pub commit: Option<String>,
+    /// Safetensors dtype the floating point tensors were transcoded to after download. The
+    /// etag and commit still describe the upstream file.
+    #[serde(default)]
+    pub dtype: Option<String>,
+}
+
+impl CacheEntry {
+    pub fn new(key: &str, size: u64, repository: &str, revision: &str, now: f64) -> Self {
+        Self {
+            key: key.to_string(),
+            size,
+            repository: repository.to_string(),
+            revision: revision.to_string(),
+            downloaded_at: now,
+            last_accessed: now,
+            etag: None,
+            commit: None,
+            dtype: None,
+        }
+    }
+
+    pub fn with_file_revision(mut self, revision: FileRevision) -> Self {
+        self.etag = revision.etag;
+        self.commit = revision.commit;
+        self
+    }
+
+    pub fn file_revision(&self) -> FileRevision {
+        FileRevision {
+            etag: self.etag.clone(),
+            commit: self.commit.clone(),
+        }
+    }
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug)]
+pub struct EvictionPolicy {
+    /// Evict least recently used models when a download would not fit.
+    pub enabled: bool,
+    /// Upper bound for the model cache in bytes, on top of the browser quota.
+    pub max_bytes: Option<f64>,
+    /// Fraction of the browser quota that is kept free for the rest of the site.
+    pub headroom: f64,
+}
+
+#[wasm_bindgen]
+impl EvictionPolicy {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> EvictionPolicy {
+        EvictionPolicy {
+            enabled: true,
+            max_bytes: None,
+            headroom: 0.1,
+        }
+    }
+}
+
+impl Default for EvictionPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EvictionPolicy {
+    /// Returns the keys to remove, oldest access first, so that `required` more bytes fit.
+    ///
+    /// `usage` and `quota` come from `navigator.storage.estimate()`. Entries whose key is
+    /// in `protected` (for example the file being replaced) are never selected. If evicting
+    /// everything still would not make room, nothing is selected.
+    pub fn select_evictions(
+        &self,
+        entries: &[CacheEntry],
+        required: u64,
+        usage: u64,
+        quota: u64,
+        protected: &[&str],
+    ) -> Vec<String> {
+        if !self.enabled {
+            return Vec::new();
+        }
+
+        let cached: u64 = entries.iter().map(|entry| entry.size).sum();
+        let quota_limit = (quota as f64 * (1.0 - self.headroom.clamp(0.0, 1.0))) as u64;
+
+        let mut overshoot = (usage + required).saturating_sub(quota_limit);
+
+        if let Some(max_bytes) = self.max_bytes {
+            overshoot = overshoot.max((cached + required).saturating_sub(max_bytes as u64));
+        }
+
+        if overshoot == 0 {
+            return Vec::new();
+        }
+
+        let mut candidates: Vec<&CacheEntry> = entries
+            .iter()
+            .filter(|entry| !protected.contains(&entry.key.as_str()))
+            .collect();
+        candidates.sort_by(|a, b| a.last_accessed.total_cmp(&b.last_accessed));
+
+        let mut freed = 0;
+        let mut evicted = Vec::new();
+
+        for entry in candidates {
+            if freed >= overshoot {
+                break;
+            }
+
+            freed += entry.size;
+            evicted.push(entry.key.clone());
+        }
+
+        if freed < overshoot {
+            return Vec::new();
+        }
+
+        evicted
+    }
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug)]
+pub struct StorageUsage {
+    /// Bytes used by this origin, as reported by the browser.
+    pub usage: f64,
+    /// Bytes this origin may use, as reported by the browser.
+    pub quota: f64,
+    /// Bytes taken by cached models according to their metadata.
+    pub cached: f64,
+    pub persisted: bool,
+}
@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use tokenizers::decoders::DecoderWrapper;
+use tokenizers::Tokenizer;
+
+use crate::grammar::Grammar;
+use crate::pattern::Pattern;
+
+/// Masks kept per constraint before the cache is cleared, about 4 KiB each for a vocabulary
+/// of 32000 tokens.
+const MASK_CACHE_LEN: usize = 1024;
+
+/// An automaton over the bytes of the generated text. Token masks are computed per state
+/// and reused whenever generation returns to a state, so states should compare equal
+/// whenever they accept the same continuations.
+pub trait Constraint {
+    type State: Clone + Eq + Hash;
+
+    fn start(&self) -> Self::State;
+
+    /// The state after `byte`, or `None` when no text matching the constraint continues
+    /// with it.
+    fn advance(&self, state: &Self::State, byte: u8) -> Option<Self::State>;
+
+    /// Whether the text so far matches as a whole, so generation may end.
+    fn is_accepting(&self, state: &Self::State) -> bool;
+}
+
+/// A set of token ids.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TokenMask {
+    bits: Vec<u64>,
+}
+
+impl TokenMask {
+    pub fn new(len: usize) -> Self {
+        Self {
+            bits: vec![0; len.div_ceil(64)],
+        }
+    }
+
+    pub fn insert(&mut self, token: u32) {
+        self.bits[token as usize / 64] |= 1 << (token % 64);
+    }
+
+    pub fn contains(&self, token: u32) -> bool {
+        self.bits
+            .get(token as usize / 64)
+            .is_some_and(|bits| bits & (1 << (token % 64)) != 0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits.iter().all(|bits| *bits == 0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.bits
+            .iter()
+            .map(|bits| bits.count_ones() as usize)
+            .sum()
+    }
+}
+
+#[derive(Default)]
+struct TrieNode {
+    children: Vec<(u8, usize)>,
+    tokens: Vec<u32>,
+}
+
+/// The text of every token as bytes, arranged in a prefix tree so tokens sharing a prefix
+/// are checked against a constraint together.
+pub struct Vocabulary {
+    tokens: Vec<Vec<u8>>,
+    nodes: Vec<TrieNode>,
+}
+
+impl Vocabulary {
+    /// Special tokens have no text; they are never allowed by a constraint.
+    pub fn new(tokenizer: &Tokenizer) -> Self {
+        let byte_level = tokenizer
+            .get_decoder()
+            .is_some_and(is_byte_level)
+            .then(byte_level_chars);
+        let specials: Vec<u32> = tokenizer
+            .get_added_tokens_decoder()
+            .into_iter()
+            .filter(|(_, token)| token.special)
+            .map(|(id, _)| id)
+            .collect();
+
+        let len = tokenizer.get_vocab_size(true);
+        let tokens = (0..len as u32)
+            .map(|id| match tokenizer.id_to_token(id) {
+                Some(_) if specials.contains(&id) => Vec::new(),
+                Some(token) => match &byte_level {
+                    Some(chars) => byte_level_bytes(&token, chars),
+                    None => sentencepiece_bytes(&token),
+                },
+                None => Vec::new(),
+            })
+            .collect();
+
+        Self::from_tokens(tokens)
+    }
+
+    /// Builds the vocabulary from the bytes of each token, indexed by id.
+    pub fn from_tokens(tokens: Vec<Vec<u8>>) -> Self {
+        let mut nodes = vec![TrieNode::default()];
+
+        for (id, bytes) in tokens.iter().enumerate() {
+            if bytes.is_empty() {
+                continue;
+            }
+
+            let mut node = 0;
+            for &byte in bytes {
+                node = match nodes[node].children.iter().find(|(b, _)| *b == byte) {
+                    Some(&(_, child)) => child,
+                    None => {
+                        nodes.push(TrieNode::default());
+                        let child = nodes.len() - 1;
+                        nodes[node].children.push((byte, child));
+                        child
+                    }
+                };
+            }
+            nodes[node].tokens.push(id as u32);
+        }
+
+        Self { tokens, nodes }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    /// The text of `token`, empty for special tokens.
+    pub fn token_bytes(&self, token: u32) -> &[u8] {
+        self.tokens
+            .get(token as usize)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Every token whose bytes the constraint accepts from `state`.
+    pub fn allowed<C: Constraint>(&self, constraint: &C, state: &C::State) -> TokenMask {
+        let mut mask = TokenMask::new(self.len());
+        for &(byte, child) in &self.nodes[0].children {
+            if let Some(next) = constraint.advance(state, byte) {
+                self.collect(constraint, child, &next, &mut mask);
+            }
+        }
+
+        mask
+    }
+
+    fn collect<C: Constraint>(
+        &self,
+        constraint: &C,
+        node: usize,
+        state: &C::State,
+        mask: &mut TokenMask,
+    ) {
+        for &token in &self.nodes[node].tokens {
+            mask.insert(token);
+        }
+        for &(byte, child) in &self.nodes[node].children {
+            if let Some(next) = constraint.advance(state, byte) {
+                self.collect(constraint, child, &next, mask);
+            }
+        }
+    }
+}
+
+fn is_byte_level(decoder: &DecoderWrapper) -> bool {
+    match decoder {
+        DecoderWrapper::ByteLevel(_) => true,
+        DecoderWrapper::Sequence(sequence) => sequence.get_decoders().iter().any(is_byte_level),
+        _ => false,
+    }
+}
+
+/// Bytes of a SentencePiece token: `▁` stands for a space and `<0xNN>` for a raw byte.
+fn sentencepiece_bytes(token: &str) -> Vec<u8> {
+    let byte = token
+        .strip_prefix("<0x")
+        .and_then(|hex| hex.strip_suffix('>'))
+        .filter(|hex| hex.len() == 2)
+        .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+    match byte {
+        Some(byte) => vec![byte],
+        None => token.replace('\u{2581}', " ").into_bytes(),
+    }
+}
+
+/// The byte each character of byte-level BPE tokens stands for, as in GPT-2: printable
+/// bytes are themselves, the others are shifted past 255 in order.
+fn byte_level_chars() -> HashMap<char, u8> {
+    let mut chars = HashMap::new();
+    let mut shifted = 0;
+    for byte in 0..=255u8 {
+        let printable = matches!(byte, b'!'..=b'~' | 0xA1..=0xAC | 0xAE..=0xFF);
+        let c = if printable {
+            byte as u32
+        } else {
+            shifted += 1;
+            255 + shifted
+        };
+        chars.insert(char::from_u32(c).unwrap_or_default(), byte);
+    }
+
+    chars
+}
+
+fn byte_level_bytes(token: &str, chars: &HashMap<char, u8>) -> Vec<u8> {
+    token
+        .chars()
+        .map(|c| chars.get(&c).copied())
+        .collect::<Option<Vec<u8>>>()
+        .unwrap_or_else(|| token.as_bytes().to_vec())
+}
+
+/// A constraint applied token by token during generation.
+pub trait TokenConstraint {
+    /// The tokens that may be generated next, EOS tokens aside.
+    fn allowed(&mut self) -> &TokenMask;
+
+    /// Whether the text so far is complete, so an EOS token may end it.
+    fn is_complete(&self) -> bool;
+
+    /// Advances past a generated token, which must have been allowed.
+    fn accept(&mut self, token: u32) -> Result<(), String>;
+}
+
+/// Follows a `Constraint` through the tokens of `vocabulary`, caching the mask of every
+/// state it reaches.
+pub struct Constrained<'a, C: Constraint> {
+    constraint: C,
+    vocabulary: &'a Vocabulary,
+    state: C::State,
+    masks: HashMap<C::State, TokenMask>,
+}
+
+impl<'a, C: Constraint> Constrained<'a, C> {
+    pub fn new(constraint: C, vocabulary: &'a Vocabulary) -> Self {
+        Self {
+            state: constraint.start(),
+            constraint,
+            vocabulary,
+            masks: HashMap::new(),
+        }
+    }
+}
+
+impl<C: Constraint> TokenConstraint for Constrained<'_, C> {
+    fn allowed(&mut self) -> &TokenMask {
+        if !self.masks.contains_key(&self.state) {
+            if self.masks.len() >= MASK_CACHE_LEN {
+                self.masks.clear();
+            }
+            let mask = self.vocabulary.allowed(&self.constraint, &self.state);
+            self.masks.insert(self.state.clone(), mask);
+        }
+
+        &self.masks[&self.state]
+    }
+
+    fn is_complete(&self) -> bool {
+        self.constraint.is_accepting(&self.state)
+    }
+
+    fn accept(&mut self, token: u32) -> Result<(), String> {
+        let bytes = self.vocabulary.token_bytes(token);
+        if bytes.is_empty() {
+            return Err(format!("Token {} has no text to constrain", token));
+        }
+
+        let mut state = self.state.clone();
+        for &byte in bytes {
+            state = self
+                .constraint
+                .advance(&state, byte)
+                .ok_or_else(|| format!("Token {} does not match the constraint", token))?;
+        }
+        self.state = state;
+
+        Ok(())
+    }
+}
+
+/// The constraints generation can be asked to follow.
+#[derive(Clone, Debug)]
+pub enum OutputConstraint {
+    Grammar(Grammar),
+    /// Boxed, the DFA of a pattern is large next to a grammar.
+    Pattern(Box<Pattern>),
+}
+
+impl OutputConstraint {
+    pub fn follow(self, vocabulary: &Vocabulary) -> Box<dyn TokenConstraint + '_> {
+        match self {
+            OutputConstraint::Grammar(grammar) => Box::new(Constrained::new(grammar, vocabulary)),
+            OutputConstraint::Pattern(pattern) => Box::new(Constrained::new(*pattern, vocabulary)),
+        }
+    }
+
+    /// Whether `text` as a whole satisfies the constraint.
+    pub fn matches(&self, text: &str) -> bool {
+        match self {
+            OutputConstraint::Grammar(grammar) => grammar.matches(text),
+            OutputConstraint::Pattern(pattern) => pattern.matches(text),
+        }
+    }
+}
+
+/// Sets the logits of tokens `constraint` rejects to minus infinity. EOS tokens are only
+/// allowed once the constraint is complete. Resolves to `false` when generation has to stop
+/// because the constraint is complete and nothing can follow.
+pub fn mask_values(
+    values: &mut [f32],
+    constraint: &mut dyn TokenConstraint,
+    eos_token_ids: &[u32],
+) -> Result<bool, String> {
+    let complete = constraint.is_complete();
+    let allowed = constraint.allowed();
+
+    if allowed.is_empty() {
+        if !complete {
+            return Err("The constraint allows no token".to_string());
+        }
+        if eos_token_ids.is_empty() {
+            return Ok(false);
+        }
+    }
+
+    for (token, value) in values.iter_mut().enumerate() {
+        let token = token as u32;
+        let end = complete && eos_token_ids.contains(&token);
+        if !end && !allowed.contains(token) {
+            *value = f32::NEG_INFINITY;
+        }
+    }
+
+    Ok(true)
+}
@@ -0,0 +1,166 @@
+use js_sys::{global, Function, Promise, Reflect};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+use web_sys::{AbortSignal, BroadcastChannel, DedicatedWorkerGlobalScope, MessageEvent};
+
+use crate::progress::ProgressSnapshot;
+
+/// Name of both the Web Lock and the `BroadcastChannel` of a cache key. Every tab and worker
+/// of the origin derives the same name, which is what lets them find each other.
+pub fn coordination_name(key: &str) -> String {
+    format!("model_store:{}", key)
+}
+
+/// The `navigator.locks` of the worker, if the browser has the Web Locks API.
+fn lock_manager() -> Option<JsValue> {
+    let scope = global().dyn_into::<DedicatedWorkerGlobalScope>().ok()?;
+    let locks = Reflect::get(&scope.navigator(), &JsValue::from_str("locks")).ok()?;
+
+    (!locks.is_undefined()).then_some(locks)
+}
+
+/// An exclusive Web Lock, held until dropped.
+pub(crate) struct KeyLock {
+    release: Option<Function>,
+}
+
+impl KeyLock {
+    /// Takes the lock unless another context holds it. Without Web Locks there is nothing to
+    /// coordinate with, so this always succeeds.
+    pub(crate) async fn try_acquire(name: &str) -> Result<Option<KeyLock>, JsValue> {
+        Self::request(name, true, None).await
+    }
+
+    /// Waits until the lock is free, or rejects once `signal` aborts.
+    pub(crate) async fn acquire(name: &str, signal: &AbortSignal) -> Result<KeyLock, JsValue> {
+        Ok(Self::request(name, false, Some(signal))
+            .await?
+            .unwrap_or(KeyLock { release: None }))
+    }
+
+    async fn request(
+        name: &str,
+        if_available: bool,
+        signal: Option<&AbortSignal>,
+    ) -> Result<Option<KeyLock>, JsValue> {
+        let Some(locks) = lock_manager() else {
+            return Ok(Some(KeyLock { release: None }));
+        };
+
+        // The lock is held until the promise returned from the callback settles
+        let mut release = None;
+        let held = Promise::new(&mut |resolve, _| release = Some(resolve));
+
+        let options = js_sys::Object::new();
+        Reflect::set(
+            &options,
+            &JsValue::from_str("ifAvailable"),
+            &JsValue::from_bool(if_available),
+        )?;
+        if let Some(signal) = signal {
+            Reflect::set(&options, &JsValue::from_str("signal"), signal)?;
+        }
+
+        let request: Function = Reflect::get(&locks, &JsValue::from_str("request"))?.dyn_into()?;
+
+        let granted = Promise::new(&mut |resolve, reject| {
+            let held = held.clone();
+            let callback = Closure::once_into_js(move |lock: JsValue| -> JsValue {
+                let _ = resolve.call1(&JsValue::NULL, &JsValue::from_bool(!lock.is_null()));
+
+                if lock.is_null() {
+                    JsValue::UNDEFINED
+                } else {
+                    held.into()
+                }
+            });
+
+            match request.call3(&locks, &JsValue::from_str(name), &options, &callback) {
+                Ok(promise) => spawn_local(async move {
+                    // Only rejects when the request itself fails, e.g. through `signal`
+                    if let Err(error) = JsFuture::from(Promise::from(promise)).await {
+                        let _ = reject.call1(&JsValue::NULL, &error);
+                    }
+                }),
+                Err(error) => {
+                    let _ = reject.call1(&JsValue::NULL, &error);
+                }
+            }
+        });
+
+        let granted = JsFuture::from(granted).await?.as_bool().unwrap_or(false);
+
+        Ok(granted.then_some(KeyLock { release }))
+    }
+}
+
+impl Drop for KeyLock {
+    fn drop(&mut self) {
+        if let Some(release) = self.release.take() {
+            let _ = release.call0(&JsValue::NULL);
+        }
+    }
+}
+
+/// Progress of a download shared with the other contexts waiting for the same file.
+pub(crate) struct DownloadChannel {
+    channel: BroadcastChannel,
+}
+
+impl DownloadChannel {
+    pub(crate) fn open(name: &str) -> Result<Self, JsValue> {
+        Ok(Self {
+            channel: BroadcastChannel::new(name)?,
+        })
+    }
+
+    /// Best effort: a listener missing one snapshot only sees a coarser progress.
+    pub(crate) fn post_progress(&self, snapshot: &ProgressSnapshot) {
+        if let Ok(json) = serde_json::to_string(snapshot) {
+            let _ = self.channel.post_message(&JsValue::from_str(&json));
+        }
+    }
+
+    /// Calls `on_progress` for every snapshot another context posts, until the subscription
+    /// is dropped.
+    pub(crate) fn subscribe(
+        &self,
+        mut on_progress: impl FnMut(ProgressSnapshot) + 'static,
+    ) -> Subscription<'_> {
+        let closure = Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+            let snapshot = event
+                .data()
+                .as_string()
+                .and_then(|json| serde_json::from_str::<ProgressSnapshot>(&json).ok());
+
+            if let Some(snapshot) = snapshot {
+                on_progress(snapshot);
+            }
+        });
+        self.channel
+            .set_onmessage(Some(closure.as_ref().unchecked_ref()));
+
+        Subscription {
+            channel: &self.channel,
+            _closure: closure,
+        }
+    }
+}
+
+impl Drop for DownloadChannel {
+    fn drop(&mut self) {
+        self.channel.close();
+    }
+}
+
+pub(crate) struct Subscription<'a> {
+    channel: &'a BroadcastChannel,
+    _closure: Closure<dyn FnMut(MessageEvent)>,
+}
+
+impl Drop for Subscription<'_> {
+    fn drop(&mut self) {
+        self.channel.set_onmessage(None);
+    }
+}
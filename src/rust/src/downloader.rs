@@ -1,3 +1,15 @@
+use crate::archive;
+use crate::buffer::ByteBuffer;
+use crate::cache::{self, CacheEntry, EvictionPolicy, StorageUsage};
+use crate::coordination::{coordination_name, DownloadChannel, KeyLock};
+use crate::hub::{self, FileRevision};
+use crate::migrations::{self, DB_VERSION};
+use crate::progress::{
+    GroupMember, ProgressSnapshot, ProgressTracker, DEFAULT_PROGRESS_INTERVAL_MS,
+};
+use crate::repository::{is_weights_file, repository_key, RepositoryDownload};
+use crate::retry::{self, RetryPolicy};
+use crate::tensor_header;
 use js_sys::global;
 use js_sys::{Promise, Uint8Array};
 use wasm_bindgen::prelude::*;
@@ -5,16 +17,20 @@ use wasm_bindgen::JsCast;
 use wasm_bindgen_futures::JsFuture;
 use web_sys::DedicatedWorkerGlobalScope;
 use web_sys::{
-    Event, IdbDatabase, IdbFactory, IdbOpenDbRequest, ReadableStreamDefaultReader, Request,
-    RequestInit, RequestMode, Response,
+    AbortController, AbortSignal, Blob, Event, Headers, IdbDatabase, IdbFactory, IdbOpenDbRequest,
+    IdbTransaction, IdbTransactionMode, IdbVersionChangeEvent, ReadableStreamDefaultReader,
+    Request, RequestInit, RequestMode, Response,
 };
 
 #[wasm_bindgen(typescript_custom_section)]
 const TS_APPEND_CONTENT: &'static str = r#"
 export interface DownloadTask {
   on(event: 'begin', callback: (filename: string) => void): DownloadTask;
-  on(event: 'progress', callback: (filename: string, bytesReceived: number, totalBytes?: number, percentage?: number) => void): DownloadTask;
+  on(event: 'progress', callback: (filename: string, bytesReceived: number, totalBytes: number | undefined, percentage: number | undefined, bytesPerSecond: number, etaSeconds?: number) => void): DownloadTask;
   on(event: 'complete', callback: (filename: string) => void): DownloadTask;
+  on(event: 'abort', callback: (filename: string) => void): DownloadTask;
+  on(event: 'retry', callback: (filename: string, attempt: number, delayMs: number, reason: string) => void): DownloadTask;
+  on(event: 'error', callback: (filename: string, reason: string) => void): DownloadTask;
 }
 "#;
 
@@ -23,6 +39,10 @@ pub struct DownloadTask {
     downloader: Downloader,
     filename: String,
     key: String,
+    abort_controller: AbortController,
+    /// Revision of the file upstream, once resolved by whoever created the task. `None` has
+    /// the task look it up itself.
+    upstream: Option<Option<FileRevision>>,
 }
 
 #[wasm_bindgen]
@@ -34,32 +54,166 @@ impl DownloadTask {
                 "begin" => self.downloader.begin_callback = Some(cb),
                 "progress" => self.downloader.progress_callback = Some(cb),
                 "complete" => self.downloader.complete_callback = Some(cb),
+                "abort" => self.downloader.abort_callback = Some(cb),
+                "retry" => self.downloader.retry_callback = Some(cb),
+                "error" => self.downloader.error_callback = Some(cb),
                 _ => {}
             }
         }
     }
 
+    /// Aborts the request and the body stream. Nothing is written to the cache for a
+    /// cancelled download and `start()` rejects.
+    pub fn cancel(&self) {
+        self.abort_controller.abort();
+    }
+
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.downloader.retry_policy = policy;
+    }
+
+    /// Minimum time between two progress events, so large files do not flood the caller.
+    pub fn set_progress_interval(&mut self, interval_ms: f64) {
+        self.downloader.progress_interval_ms = interval_ms;
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn cancelled(&self) -> bool {
+        self.abort_controller.signal().aborted()
+    }
+
+    /// Resolves to the array the file was downloaded into, which is also what got stored.
     pub async fn start(&self) -> Result<Uint8Array, JsValue> {
+        self.fetch_and_store().await
+    }
+
+    /// Like `start()`, but resolves to the bytes in the wasm heap, ready to be moved into
+    /// `Generator.from_buffers`. They are copied there once, and the JS array is released.
+    pub async fn start_buffer(&self) -> Result<ByteBuffer, JsValue> {
+        let content = self.fetch_and_store().await?;
+        ByteBuffer::from_uint8array(&content)
+    }
+}
+
+impl DownloadTask {
+    /// Shares `controller` with other tasks so they can be cancelled together.
+    pub(crate) fn with_abort_controller(mut self, controller: AbortController) -> Self {
+        self.abort_controller = controller;
+        self
+    }
+
+    /// Uses `upstream`, resolved once for every file of a repository, instead of looking up
+    /// the revision of the repository for this file alone.
+    pub(crate) fn with_upstream_revision(mut self, upstream: Option<FileRevision>) -> Self {
+        self.upstream = Some(upstream);
+        self
+    }
+
+    pub(crate) fn join_group(&mut self, member: GroupMember) {
+        self.downloader.group = Some(member);
+    }
+
+    /// Downloads the file and writes it to the cache. The array the chunks were written into
+    /// is stored as is and returned, so storing costs no copy of the file.
+    ///
+    /// Only one context of the origin downloads a given key at a time. The others relay its
+    /// progress and, once it releases the lock, resolve from the cache, or download the file
+    /// themselves if it did not get stored.
+    async fn fetch_and_store(&self) -> Result<Uint8Array, JsValue> {
         let db = Downloader::open_db().await?;
+        let signal = self.abort_controller.signal();
+        let name = coordination_name(&self.key);
+
+        let _lock = match KeyLock::try_acquire(&name).await? {
+            Some(lock) => lock,
+            None => {
+                let waiting_since = js_sys::Date::now();
+                let lock = self.follow(&name, &signal).await?;
+
+                // An older copy may still be cached when the other context failed to update it
+                let stored = Downloader::read_entry(&db, &self.key).await?;
+                if stored.is_some_and(|entry| entry.downloaded_at >= waiting_since) {
+                    if let Some(content) = Downloader::get(&self.key).await {
+                        self.downloader.finished(&self.filename)?;
+                        return Ok(content);
+                    }
+                }
 
-        match self
-            .downloader
-            .fetch_file_with_callbacks(&self.filename)
-            .await
-        {
-            Ok(content) => {
-                let store_names: js_sys::Array = js_sys::Array::of1(&JsValue::from_str(STORE_NAME));
-                let transaction = db.transaction_with_str_sequence_and_mode(
-                    &store_names,
-                    web_sys::IdbTransactionMode::Readwrite,
-                )?;
-                let store = transaction.object_store(STORE_NAME)?;
-                store.put_with_key(&content, &JsValue::from_str(&self.key))?;
-
-                Ok(content)
+                lock
             }
-            Err(e) => Err(e),
+        };
+
+        // Looked up first, so a file that changes during the download is recorded as
+        // outdated rather than current. Without it, only files served without a redirect
+        // to the CDN know their revision.
+        let upstream = match &self.upstream {
+            Some(upstream) => upstream.clone(),
+            None => hub::repository_revision(&self.downloader.repository_url, DEFAULT_REVISION)
+                .await
+                .ok()
+                .and_then(|upstream| upstream.file(&self.filename)),
+        };
+
+        let (content, revision) = self
+            .downloader
+            .fetch_file_with_callbacks(&self.filename, &self.key, &signal)
+            .await?;
+
+        if signal.aborted() {
+            return Err(self.downloader.aborted(&self.filename));
         }
+
+        let entry = CacheEntry::new(
+            &self.key,
+            content.length() as u64,
+            &self.downloader.repository_url,
+            DEFAULT_REVISION,
+            js_sys::Date::now(),
+        )
+        .with_file_revision(upstream.unwrap_or(revision));
+
+        let transaction = Downloader::transaction(
+            &db,
+            &[STORE_NAME, METADATA_STORE_NAME],
+            IdbTransactionMode::Readwrite,
+        )?;
+        let store = transaction.object_store(STORE_NAME)?;
+        let request = store.put_with_key(&content, &JsValue::from_str(&self.key))?;
+        Downloader::put_entry(&transaction, &entry)?;
+
+        // Waiting contexts read the file as soon as the lock is released
+        Downloader::idbrequest_to_result::<JsValue>(&request).await?;
+
+        Ok(content)
+    }
+
+    /// Relays the progress of the context holding the lock until it releases it.
+    async fn follow(&self, name: &str, signal: &AbortSignal) -> Result<KeyLock, JsValue> {
+        let progress_callback = self.downloader.progress_callback.clone();
+        let group = self.downloader.group.clone();
+        let filename = JsValue::from_str(&self.filename);
+
+        let _subscription = self.downloader.channel.as_ref().map(|channel| {
+            channel.subscribe(move |snapshot| {
+                if let Some(group) = group.as_ref() {
+                    group.report(snapshot.received, snapshot.total);
+                }
+
+                if let Some(cb) = progress_callback.as_ref() {
+                    let args = js_sys::Array::of1(&filename);
+                    snapshot.push_args(&args);
+                    let _ = cb.apply(&JsValue::NULL, &args);
+                }
+            })
+        });
+
+        KeyLock::acquire(name, signal).await.map_err(|error| {
+            if signal.aborted() {
+                self.downloader.aborted(&self.filename)
+            } else {
+                self.downloader.failed(&self.filename, error)
+            }
+        })
     }
 }
 
@@ -71,7 +225,7 @@ extern "C" {
 
     #[wasm_bindgen(extends = js_sys::Function)]
     #[wasm_bindgen(
-        typescript_type = "(filename: string, bytesReceived: number, totalBytes?: number, percentage?: number) => void"
+        typescript_type = "(filename: string, bytesReceived: number, totalBytes: number | undefined, percentage: number | undefined, bytesPerSecond: number, etaSeconds?: number) => void"
     )]
     pub type ProgressCallback;
 
@@ -80,17 +234,135 @@ extern "C" {
     pub type CompleteCallback;
 }
 
+/// Bytes of a download received so far, kept across attempts so retries can resume.
+///
+/// They are written into a JS array rather than the wasm heap: a view into wasm memory
+/// cannot be stored without structured cloning the whole heap, while this array is stored
+/// as is. It is sized from `content-length` up front, so every chunk is written once.
+struct PartialDownload {
+    content: Uint8Array,
+    received: u32,
+    total: Option<f64>,
+    etag: Option<String>,
+    revision: FileRevision,
+    began: bool,
+}
+
+impl Default for PartialDownload {
+    fn default() -> Self {
+        Self {
+            content: Uint8Array::new_with_length(0),
+            received: 0,
+            total: None,
+            etag: None,
+            revision: FileRevision::default(),
+            began: false,
+        }
+    }
+}
+
+impl PartialDownload {
+    fn received(&self) -> f64 {
+        self.received as f64
+    }
+
+    /// Drops the bytes but keeps the array for a download that starts over.
+    fn reset(&mut self) {
+        self.received = 0;
+    }
+
+    /// Makes room for `additional` more bytes, so they are written in place. Fails instead
+    /// of aborting when the browser cannot allocate the array.
+    fn reserve(&mut self, additional: u64) -> Result<(), JsValue> {
+        let required = self.received as u64 + additional;
+        if required <= self.content.length() as u64 {
+            return Ok(());
+        }
+
+        let length = u32::try_from(required)
+            .map_err(|_| JsValue::from_str(&format!("{} bytes do not fit an array", required)))?;
+        let constructor = self.content.constructor();
+        let content: Uint8Array =
+            js_sys::Reflect::construct(&constructor, &js_sys::Array::of1(&length.into()))
+                .map_err(|_| JsValue::from_str(&format!("Not enough memory for {} bytes", length)))?
+                .unchecked_into();
+
+        content.set(&self.content.subarray(0, self.received), 0);
+        self.content = content;
+        Ok(())
+    }
+
+    fn push(&mut self, chunk: &Uint8Array) -> Result<(), JsValue> {
+        let available = self.content.length() - self.received;
+        if chunk.length() > available {
+            // Without a known total, the array doubles so chunks are rarely moved
+            let grown = (self.content.length() as u64 * 2).saturating_sub(self.received as u64);
+            self.reserve(grown.max(chunk.length() as u64))?;
+        }
+
+        self.content.set(chunk, self.received);
+        self.received += chunk.length();
+        Ok(())
+    }
+
+    /// The downloaded file: the array itself when it was sized exactly, which it is
+    /// whenever the server sent the length.
+    fn into_content(self) -> Uint8Array {
+        if self.received == self.content.length() {
+            self.content
+        } else {
+            self.content.slice(0, self.received)
+        }
+    }
+}
+
+enum AttemptError {
+    Aborted,
+    Retryable {
+        error: JsValue,
+        retry_after: Option<f64>,
+    },
+    Fatal(JsValue),
+}
+
+impl From<JsValue> for AttemptError {
+    fn from(error: JsValue) -> Self {
+        AttemptError::Fatal(error)
+    }
+}
+
+fn error_message(error: &JsValue) -> String {
+    error
+        .as_string()
+        .or_else(|| {
+            error
+                .dyn_ref::<js_sys::Error>()
+                .map(|e| String::from(e.message()))
+        })
+        .unwrap_or_else(|| format!("{:?}", error))
+}
+
 const DB_NAME: &str = "model_store";
-const STORE_NAME: &str = "models";
-const DB_VERSION: u32 = 1;
+pub(crate) const STORE_NAME: &str = "models";
+pub(crate) const METADATA_STORE_NAME: &str = "metadata";
+pub(crate) const MANIFEST_STORE_NAME: &str = "manifests";
+pub const DEFAULT_REVISION: &str = "main";
 
 #[wasm_bindgen]
 pub struct Downloader {
     repository_url: String,
+    eviction_policy: EvictionPolicy,
+    retry_policy: RetryPolicy,
+    progress_interval_ms: f64,
+    group: Option<GroupMember>,
+    channel: Option<DownloadChannel>,
 
     begin_callback: Option<js_sys::Function>,
     progress_callback: Option<js_sys::Function>,
     complete_callback: Option<js_sys::Function>,
+    abort_callback: Option<js_sys::Function>,
+    retry_callback: Option<js_sys::Function>,
+    error_callback: Option<js_sys::Function>,
 }
 
 #[wasm_bindgen]
@@ -99,13 +371,30 @@ impl Downloader {
     pub fn new(repository_url: &str) -> Self {
         Self {
             repository_url: repository_url.to_string(),
+            eviction_policy: EvictionPolicy::new(),
+            retry_policy: RetryPolicy::new(),
+            progress_interval_ms: DEFAULT_PROGRESS_INTERVAL_MS,
+            group: None,
+            channel: None,
             begin_callback: None,
             progress_callback: None,
             complete_callback: None,
+            abort_callback: None,
+            retry_callback: None,
+            error_callback: None,
         }
     }
 
-    async fn open_db() -> Result<IdbDatabase, JsValue> {
+    pub fn set_eviction_policy(&mut self, policy: EvictionPolicy) {
+        self.eviction_policy = policy;
+    }
+
+    /// Retry policy for tasks created by `save_file` from now on.
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    pub(crate) async fn open_db() -> Result<IdbDatabase, JsValue> {
         let window = global().dyn_into::<DedicatedWorkerGlobalScope>()?;
         let indexed_db: IdbFactory = window
             .indexed_db()?
@@ -129,18 +418,22 @@ impl Downloader {
                 reject.call1(&JsValue::NULL, &JsValue::NULL).unwrap();
             });
 
-            let on_upgrade_needed = Closure::once(move |event: Event| {
-                let db = event
+            let on_upgrade_needed = Closure::once(move |event: IdbVersionChangeEvent| {
+                let request = event
                     .target()
                     .unwrap()
                     .dyn_into::<IdbOpenDbRequest>()
-                    .unwrap()
-                    .result()
-                    .unwrap()
-                    .dyn_into::<IdbDatabase>()
                     .unwrap();
+                let db = request.result().unwrap().dyn_into::<IdbDatabase>().unwrap();
+                let transaction = request.transaction().unwrap();
+
+                let old_version = event.old_version() as u32;
+                let new_version = event.new_version().map_or(DB_VERSION, |v| v as u32);
 
-                db.create_object_store(STORE_NAME).unwrap();
+                // Aborting makes the open request fail instead of leaving a half-migrated schema
+                if migrations::apply(&db, &transaction, old_version, new_version).is_err() {
+                    let _ = transaction.abort();
+                }
             });
 
             open_request.set_onsuccess(Some(on_success.as_ref().unchecked_ref()));
@@ -156,69 +449,262 @@ impl Downloader {
         Ok(db)
     }
 
+    /// Lists the files of the repository with their sizes and LFS hashes.
+    pub async fn list_files(&self) -> Result<JsValue, JsValue> {
+        let files = hub::list_files(&self.repository_url, DEFAULT_REVISION).await?;
+        hub::to_js(&files)
+    }
+
+    /// Picks the safetensors weights to download.
+    pub async fn select_weights(&self) -> Result<JsValue, JsValue> {
+        let files = hub::list_files(&self.repository_url, DEFAULT_REVISION).await?;
+        let variant = hub::select_weights(&files)
+            .map_err(|e| JsValue::from_str(&format!("{}: {}", self.repository_url, e)))?;
+
+        hub::to_js(&variant)
+    }
+
+    /// Reads only the header of a safetensors file, `model.safetensors` by default, with two
+    /// range requests and resolves to its tensors, parameter count, dtypes and file size.
+    pub async fn preview_weights(&self, filename: Option<String>) -> Result<JsValue, JsValue> {
+        let filename = filename.as_deref().unwrap_or("model.safetensors");
+        let url = hub::resolve_url(&self.repository_url, DEFAULT_REVISION, filename);
+        let message = |e: String| JsValue::from_str(&format!("{}: {}", filename, e));
+
+        let (prefix, file_size) =
+            hub::fetch_range(&url, 0, tensor_header::HEADER_PREFIX_LEN).await?;
+        let file_size = file_size.ok_or_else(|| message("File size is unknown".to_string()))?;
+        let header_end = tensor_header::HEADER_PREFIX_LEN
+            + tensor_header::header_len(&prefix).map_err(message)?;
+
+        if header_end > file_size {
+            return Err(message(
+                "Safetensors header extends past the end of the file".to_string(),
+            ));
+        }
+
+        let (header, _) =
+            hub::fetch_range(&url, tensor_header::HEADER_PREFIX_LEN, header_end).await?;
+        let summary = tensor_header::summarize_header(&header, file_size).map_err(message)?;
+
+        hub::to_js(&summary)
+    }
+
     pub fn save_file(&self, filename: &str, key: &str) -> DownloadTask {
         DownloadTask {
             downloader: Downloader {
                 repository_url: self.repository_url.clone(),
+                eviction_policy: self.eviction_policy,
+                retry_policy: self.retry_policy.clone(),
+                progress_interval_ms: self.progress_interval_ms,
+                group: None,
+                channel: DownloadChannel::open(&coordination_name(key)).ok(),
                 begin_callback: None,
                 progress_callback: None,
                 complete_callback: None,
+                abort_callback: None,
+                retry_callback: None,
+                error_callback: None,
             },
             filename: filename.to_string(),
             key: key.to_string(),
+            abort_controller: AbortController::new().unwrap(),
+            upstream: None,
         }
     }
 
-    async fn fetch_file_with_callbacks(&self, filename: &str) -> Result<Uint8Array, JsValue> {
-        let url = format!(
-            "https://huggingface.co/{}/resolve/main/{}",
-            self.repository_url, filename
-        );
+    async fn fetch_file_with_callbacks(
+        &self,
+        filename: &str,
+        key: &str,
+        signal: &AbortSignal,
+    ) -> Result<(Uint8Array, FileRevision), JsValue> {
+        let mut partial = PartialDownload::default();
+        let mut tracker = ProgressTracker::new(self.progress_interval_ms);
+        let mut attempt = 1;
+
+        loop {
+            let (error, retry_after) = match self
+                .fetch_attempt(filename, key, signal, &mut partial, &mut tracker)
+                .await
+            {
+                Ok(()) => break,
+                Err(AttemptError::Aborted) => return Err(self.aborted(filename)),
+                Err(AttemptError::Fatal(error)) => return Err(self.failed(filename, error)),
+                Err(AttemptError::Retryable { error, retry_after }) => {
+                    if !self.retry_policy.allows_retry(attempt) {
+                        return Err(self.failed(filename, error));
+                    }
+
+                    (error, retry_after)
+                }
+            };
+
+            let delay = self.retry_policy.delay_ms(attempt, retry_after);
+
+            // Send retry event
+            if let Some(cb) = self.retry_callback.as_ref() {
+                let args = js_sys::Array::new();
+                args.push(&JsValue::from_str(filename));
+                args.push(&JsValue::from(attempt));
+                args.push(&JsValue::from_f64(delay));
+                args.push(&JsValue::from_str(&error_message(&error)));
+                cb.apply(&JsValue::NULL, &args)?;
+            }
+
+            Self::sleep(delay, signal).await?;
+
+            if signal.aborted() {
+                return Err(self.aborted(filename));
+            }
+
+            attempt += 1;
+        }
+
+        // Progress without a known total is never finished, so flush the last throttled bytes
+        if let Some(snapshot) =
+            tracker.update(js_sys::Date::now(), partial.received(), partial.total, true)
+        {
+            self.send_progress(filename, &snapshot)?;
+        }
+
+        self.finished(filename)?;
+
+        let revision = partial.revision.clone();
+        Ok((partial.into_content(), revision))
+    }
+
+    /// Runs one request, resuming after the bytes in `partial` when it holds any.
+    async fn fetch_attempt(
+        &self,
+        filename: &str,
+        key: &str,
+        signal: &AbortSignal,
+        partial: &mut PartialDownload,
+        tracker: &mut ProgressTracker,
+    ) -> Result<(), AttemptError> {
+        let url = hub::resolve_url(&self.repository_url, DEFAULT_REVISION, filename);
 
         let opts = RequestInit::new();
         opts.set_method("GET");
         opts.set_mode(RequestMode::Cors);
+        opts.set_signal(Some(signal));
+
+        if partial.received() > 0.0 {
+            let headers = Headers::new()?;
+            headers.set("Range", &format!("bytes={}-", partial.received() as u64))?;
+            opts.set_headers(&headers);
+        }
 
         let request = Request::new_with_str_and_init(&url, &opts)?;
-        let window = global().dyn_into::<DedicatedWorkerGlobalScope>()?;
-        let resp_value = JsFuture::from(window.fetch_with_request(&request)).await?;
+        let window = global()
+            .dyn_into::<DedicatedWorkerGlobalScope>()
+            .map_err(JsValue::from)?;
+        let resp_value = match JsFuture::from(window.fetch_with_request(&request)).await {
+            Ok(value) => value,
+            Err(_) if signal.aborted() => return Err(AttemptError::Aborted),
+            // fetch only rejects on network failures, which are worth another try
+            Err(error) => {
+                return Err(AttemptError::Retryable {
+                    error,
+                    retry_after: None,
+                })
+            }
+        };
         let resp: Response = resp_value.dyn_into()?;
 
         if !resp.ok() {
-            return Err(JsValue::from_str(&format!(
-                "Failed to fetch {}: {}",
-                filename,
-                resp.status()
-            )));
+            let error =
+                JsValue::from_str(&format!("Failed to fetch {}: {}", filename, resp.status()));
+
+            if !self.retry_policy.is_retryable(resp.status()) {
+                return Err(AttemptError::Fatal(error));
+            }
+
+            let retry_after = match resp.headers().get("retry-after") {
+                Ok(Some(value)) => retry::parse_retry_after(&value),
+                _ => None,
+            };
+            return Err(AttemptError::Retryable { error, retry_after });
+        }
+
+        let etag = resp.headers().get("etag").ok().flatten();
+
+        // A partial response only continues our bytes if the file did not change meanwhile
+        if resp.status() == 206 && etag != partial.etag {
+            partial.reset();
+            return Err(AttemptError::Retryable {
+                error: JsValue::from_str(&format!("{} changed while resuming", filename)),
+                retry_after: None,
+            });
+        }
+
+        if resp.status() != 206 {
+            partial.reset();
         }
+        partial.etag = etag;
+        partial.revision = FileRevision::from_response(&resp);
 
         // Get content length for progress calculation (optional)
         let content_length = match resp.headers().get("content-length") {
             Ok(Some(value)) => value.parse::<f64>().ok(),
             _ => None,
         };
+        let content_range_total = match resp.headers().get("content-range") {
+            Ok(Some(value)) => retry::parse_content_range_total(&value),
+            _ => None,
+        };
+        partial.total =
+            content_range_total.or(content_length.map(|length| partial.received() + length));
 
-        // Send begin event
-        if let Some(cb) = self.begin_callback.as_ref() {
-            cb.call1(&JsValue::NULL, &JsValue::from_str(filename))?;
+        // Chunks are then written once, in place, without the buffer ever being reallocated
+        if let Some(total) = partial.total {
+            let remaining = (total - partial.received()).max(0.0) as u64;
+            partial.reserve(remaining)?;
+        }
+
+        if !partial.began {
+            if let Some(total) = partial.total {
+                self.ensure_capacity(key, total as u64).await?;
+            }
+
+            // Send begin event
+            if let Some(cb) = self.begin_callback.as_ref() {
+                cb.call1(&JsValue::NULL, &JsValue::from_str(filename))?;
+            }
+
+            partial.began = true;
         }
 
         // Get the response body as a ReadableStream
         let body = match resp.body() {
             Some(b) => b,
-            None => return Err(JsValue::from_str("No response body")),
+            None => return Err(JsValue::from_str("No response body").into()),
         };
 
         let reader = match body.get_reader().dyn_into::<ReadableStreamDefaultReader>() {
             Ok(r) => r,
-            Err(_) => return Err(JsValue::from_str("Failed to get reader")),
+            Err(_) => return Err(JsValue::from_str("Failed to get reader").into()),
         };
 
-        let mut received = 0.0;
-        let mut chunks = Vec::new();
-
         loop {
-            let result = JsFuture::from(reader.read()).await?;
+            let result = match JsFuture::from(reader.read()).await {
+                Ok(result) => result,
+                Err(error) => {
+                    // Release the stream so the connection is dropped. The chunks read so far
+                    // are kept for the next attempt, and discarded with `partial` otherwise.
+                    let _ = reader.cancel();
+
+                    return Err(if signal.aborted() {
+                        AttemptError::Aborted
+                    } else {
+                        AttemptError::Retryable {
+                            error,
+                            retry_after: None,
+                        }
+                    });
+                }
+            };
             let done = js_sys::Reflect::get(&result, &JsValue::from_str("done"))?
                 .as_bool()
                 .unwrap();
@@ -229,36 +715,31 @@ impl Downloader {
             }
 
             if let Some(chunk) = value.dyn_ref::<Uint8Array>() {
-                received += chunk.length() as f64;
-                chunks.push(chunk.to_vec());
+                partial.push(chunk)?;
+
+                if let Some(group) = self.group.as_ref() {
+                    group.report(partial.received(), partial.total);
+                }
 
                 // Send progress event
-                if let Some(cb) = self.progress_callback.as_ref() {
-                    if let Some(total) = content_length {
-                        let percentage = (received / total * 100.0) as i32;
-                        let args = js_sys::Array::new();
-                        args.push(&JsValue::from_str(filename));
-                        args.push(&JsValue::from_f64(received));
-                        args.push(&JsValue::from_f64(total));
-                        args.push(&JsValue::from(percentage));
-                        cb.apply(&JsValue::NULL, &args)?;
-                    } else {
-                        // No total size, so call with 2 arguments
-                        cb.call2(
-                            &JsValue::NULL,
-                            &JsValue::from_str(filename),
-                            &JsValue::from_f64(received),
-                        )?;
-                    }
+                if let Some(snapshot) = tracker.update(
+                    js_sys::Date::now(),
+                    partial.received(),
+                    partial.total,
+                    false,
+                ) {
+                    self.send_progress(filename, &snapshot)?;
                 }
             }
         }
 
-        // Combine all chunks into one array
-        let total_length = chunks.iter().map(|chunk| chunk.len()).sum();
-        let mut combined = Vec::with_capacity(total_length);
-        for chunk in chunks {
-            combined.extend(chunk);
+        Ok(())
+    }
+
+    /// Marks the file as done for the group and sends the complete event.
+    fn finished(&self, filename: &str) -> Result<(), JsValue> {
+        if let Some(group) = self.group.as_ref() {
+            group.complete();
         }
 
         // Send complete event
@@ -266,19 +747,86 @@ impl Downloader {
             cb.call1(&JsValue::NULL, &JsValue::from_str(filename))?;
         }
 
-        Ok(Uint8Array::from(&combined[..]))
+        Ok(())
     }
 
-    pub async fn model_exists() -> bool {
-        Self::exists("model").await
+    fn send_progress(&self, filename: &str, snapshot: &ProgressSnapshot) -> Result<(), JsValue> {
+        if let Some(channel) = self.channel.as_ref() {
+            channel.post_progress(snapshot);
+        }
+
+        if let Some(cb) = self.progress_callback.as_ref() {
+            let args = js_sys::Array::of1(&JsValue::from_str(filename));
+            snapshot.push_args(&args);
+            cb.apply(&JsValue::NULL, &args)?;
+        }
+
+        Ok(())
     }
 
-    pub async fn tokenizer_exists() -> bool {
-        Self::exists("tokenizer").await
+    /// Resolves after `delay_ms`, or as soon as `signal` aborts.
+    async fn sleep(delay_ms: f64, signal: &AbortSignal) -> Result<(), JsValue> {
+        let scope = global().dyn_into::<DedicatedWorkerGlobalScope>()?;
+
+        let promise = Promise::new(&mut |resolve, _| {
+            let _ = scope
+                .set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, delay_ms as i32);
+            let _ = signal.add_event_listener_with_callback("abort", &resolve);
+        });
+
+        JsFuture::from(promise).await?;
+        Ok(())
     }
 
-    pub async fn config_exists() -> bool {
-        Self::exists("config").await
+    /// Sends the error event for a download that gave up, passing the error through.
+    fn failed(&self, filename: &str, error: JsValue) -> JsValue {
+        if let Some(cb) = self.error_callback.as_ref() {
+            let _ = cb.call2(
+                &JsValue::NULL,
+                &JsValue::from_str(filename),
+                &JsValue::from_str(&error_message(&error)),
+            );
+        }
+
+        error
+    }
+
+    /// Sends the abort event and builds the error a cancelled download rejects with.
+    fn aborted(&self, filename: &str) -> JsValue {
+        if let Some(cb) = self.abort_callback.as_ref() {
+            let _ = cb.call1(&JsValue::NULL, &JsValue::from_str(filename));
+        }
+
+        JsValue::from_str(&format!("Download of {} was cancelled", filename))
+    }
+
+    /// Whether every weights file of `repository` is cached: those of its manifest, or
+    /// `model.safetensors` before a download completed.
+    pub async fn model_exists(repository: &str) -> bool {
+        let files = match RepositoryDownload::read_manifest(repository).await {
+            Ok(Some(manifest)) => manifest.files,
+            _ => vec!["model.safetensors".to_string()],
+        };
+
+        let mut weights = files.iter().filter(|file| is_weights_file(file)).peekable();
+        if weights.peek().is_none() {
+            return false;
+        }
+        for filename in weights {
+            if !Self::exists(&repository_key(repository, filename)).await {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    pub async fn tokenizer_exists(repository: &str) -> bool {
+        Self::exists(&repository_key(repository, "tokenizer.json")).await
+    }
+
+    pub async fn config_exists(repository: &str) -> bool {
+        Self::exists(&repository_key(repository, "config.json")).await
     }
 
     async fn exists(key: &str) -> bool {
@@ -365,11 +913,23 @@ impl Downloader {
             return None;
         }
 
+        // Access time only feeds eviction, so a failed update must not fail the read
+        let _ = Self::touch(&db, key).await;
+
         data.ok()
     }
 
+    /// Reads a cached file straight into the wasm heap.
+    pub async fn get_buffer(key: &str) -> Option<ByteBuffer> {
+        let data = Self::get(key).await?;
+        ByteBuffer::from_uint8array(&data).ok()
+    }
+
     pub async fn remove(key: &str) -> bool {
-        let store_names: js_sys::Array = js_sys::Array::of1(&JsValue::from_str(STORE_NAME));
+        let store_names: js_sys::Array = js_sys::Array::of2(
+            &JsValue::from_str(STORE_NAME),
+            &JsValue::from_str(METADATA_STORE_NAME),
+        );
 
         let db = Self::open_db().await;
 
@@ -405,11 +965,206 @@ impl Downloader {
 
         let request = request.unwrap();
 
+        let metadata_request = transaction
+            .object_store(METADATA_STORE_NAME)
+            .and_then(|store| store.delete(&JsValue::from_str(key)));
+
+        if metadata_request.is_err() {
+            return false;
+        }
+
         let result = Self::idbrequest_to_result::<JsValue>(&request).await;
         result.is_ok()
     }
 
-    async fn idbrequest_to_result<T: JsCast>(request: &web_sys::IdbRequest) -> Result<T, JsValue> {
+    /// Packs the cached files of `repository` with their metadata into a tar archive that
+    /// `import_archive` restores, in this or another browser.
+    pub async fn export_repository(repository: &str) -> Result<Blob, JsValue> {
+        archive::export_repository(repository).await
+    }
+
+    /// Restores an archive made by `export_repository` after checking every file against its
+    /// recorded hash. Resolves to the repository id.
+    pub async fn import_archive(archive: Blob) -> Result<String, JsValue> {
+        archive::import_archive(&archive).await
+    }
+
+    pub async fn storage_usage() -> Result<StorageUsage, JsValue> {
+        let (usage, quota) = Self::estimate().await?;
+
+        let db = Self::open_db().await?;
+        let cached = Self::read_entries(&db)
+            .await?
+            .iter()
+            .map(|entry| entry.size as f64)
+            .sum();
+
+        let storage = global()
+            .dyn_into::<DedicatedWorkerGlobalScope>()?
+            .navigator()
+            .storage();
+        let persisted = JsFuture::from(storage.persisted()?)
+            .await?
+            .as_bool()
+            .unwrap_or(false);
+
+        Ok(StorageUsage {
+            usage: usage as f64,
+            quota: quota as f64,
+            cached,
+            persisted,
+        })
+    }
+
+    /// Asks the browser not to clear the model cache under storage pressure.
+    pub async fn request_persistent_storage() -> bool {
+        let scope = match global().dyn_into::<DedicatedWorkerGlobalScope>() {
+            Ok(scope) => scope,
+            Err(_) => return false,
+        };
+
+        let promise = match scope.navigator().storage().persist() {
+            Ok(promise) => promise,
+            Err(_) => return false,
+        };
+
+        match JsFuture::from(promise).await {
+            Ok(granted) => granted.as_bool().unwrap_or(false),
+            Err(_) => false,
+        }
+    }
+
+    async fn estimate() -> Result<(u64, u64), JsValue> {
+        let storage = global()
+            .dyn_into::<DedicatedWorkerGlobalScope>()?
+            .navigator()
+            .storage();
+        let estimate = JsFuture::from(storage.estimate()?).await?;
+
+        let usage = js_sys::Reflect::get(&estimate, &JsValue::from_str("usage"))?
+            .as_f64()
+            .unwrap_or(0.0);
+        let quota = js_sys::Reflect::get(&estimate, &JsValue::from_str("quota"))?
+            .as_f64()
+            .unwrap_or(f64::INFINITY);
+
+        Ok((usage as u64, quota as u64))
+    }
+
+    /// Evicts least recently used files until `required` more bytes fit the policy. Files
+    /// of this repository and of the loaded model are never evicted.
+    pub(crate) async fn ensure_capacity(&self, key: &str, required: u64) -> Result<(), JsValue> {
+        if !self.eviction_policy.enabled {
+            return Ok(());
+        }
+
+        let (usage, quota) = Self::estimate().await?;
+        let db = Self::open_db().await?;
+        let entries = Self::read_entries(&db).await?;
+
+        let loaded = cache::loaded_repository();
+        let mut repositories = vec![self.repository_url.as_str()];
+        repositories.extend(loaded.as_deref());
+        let protected = cache::protected_keys(&entries, key, &repositories);
+
+        let evictions = self
+            .eviction_policy
+            .select_evictions(&entries, required, usage, quota, &protected);
+
+        if evictions.is_empty() {
+            return Ok(());
+        }
+
+        let transaction = Self::transaction(
+            &db,
+            &[STORE_NAME, METADATA_STORE_NAME],
+            IdbTransactionMode::Readwrite,
+        )?;
+        let store = transaction.object_store(STORE_NAME)?;
+        let metadata = transaction.object_store(METADATA_STORE_NAME)?;
+
+        let mut last_request = None;
+        for evicted in &evictions {
+            store.delete(&JsValue::from_str(evicted))?;
+            last_request = Some(metadata.delete(&JsValue::from_str(evicted))?);
+        }
+
+        if let Some(request) = last_request {
+            Self::idbrequest_to_result::<JsValue>(&request).await?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn transaction(
+        db: &IdbDatabase,
+        stores: &[&str],
+        mode: IdbTransactionMode,
+    ) -> Result<IdbTransaction, JsValue> {
+        let store_names: js_sys::Array = stores.iter().map(|s| JsValue::from_str(s)).collect();
+        db.transaction_with_str_sequence_and_mode(&store_names, mode)
+    }
+
+    pub(crate) fn put_entry(
+        transaction: &IdbTransaction,
+        entry: &CacheEntry,
+    ) -> Result<(), JsValue> {
+        let json = serde_json::to_string(entry).map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+        transaction
+            .object_store(METADATA_STORE_NAME)?
+            .put_with_key(&JsValue::from_str(&json), &JsValue::from_str(&entry.key))?;
+
+        Ok(())
+    }
+
+    pub(crate) async fn read_entries(db: &IdbDatabase) -> Result<Vec<CacheEntry>, JsValue> {
+        let transaction =
+            Self::transaction(db, &[METADATA_STORE_NAME], IdbTransactionMode::Readonly)?;
+        let request = transaction.object_store(METADATA_STORE_NAME)?.get_all()?;
+        let values = Self::idbrequest_to_result::<js_sys::Array>(&request).await?;
+
+        Ok(values
+            .iter()
+            .filter_map(|value| value.as_string())
+            .filter_map(|json| serde_json::from_str(&json).ok())
+            .collect())
+    }
+
+    pub(crate) async fn read_entry(
+        db: &IdbDatabase,
+        key: &str,
+    ) -> Result<Option<CacheEntry>, JsValue> {
+        let transaction =
+            Self::transaction(db, &[METADATA_STORE_NAME], IdbTransactionMode::Readonly)?;
+        let request = transaction
+            .object_store(METADATA_STORE_NAME)?
+            .get(&JsValue::from_str(key))?;
+        let value = Self::idbrequest_to_result::<JsValue>(&request).await?;
+
+        Ok(value
+            .as_string()
+            .and_then(|json| serde_json::from_str(&json).ok()))
+    }
+
+    async fn touch(db: &IdbDatabase, key: &str) -> Result<(), JsValue> {
+        let transaction =
+            Self::transaction(db, &[METADATA_STORE_NAME], IdbTransactionMode::Readwrite)?;
+        let request = transaction
+            .object_store(METADATA_STORE_NAME)?
+            .get(&JsValue::from_str(key))?;
+        let json = Self::idbrequest_to_result::<js_sys::JsString>(&request).await?;
+
+        let mut entry: CacheEntry = serde_json::from_str(&String::from(json))
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        entry.last_accessed = js_sys::Date::now();
+
+        Self::put_entry(&transaction, &entry)
+    }
+
+    pub(crate) async fn idbrequest_to_result<T: JsCast>(
+        request: &web_sys::IdbRequest,
+    ) -> Result<T, JsValue> {
         let promise = Promise::new(&mut |resolve, reject| {
             let on_success = Closure::once(move |event: Event| {
                 let target = event
@@ -0,0 +1,47 @@
+use candle_transformers::models::llama::LlamaEosToks;
+use serde::Deserialize;
+
+/// The sampling defaults a repository ships in `generation_config.json`. Only the fields
+/// that map onto `GenerationArguments` are read; absent or `null` ones are `None`.
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct GenerationConfig {
+    pub do_sample: Option<bool>,
+    pub temperature: Option<f64>,
+    pub top_k: Option<usize>,
+    pub top_p: Option<f64>,
+    pub max_new_tokens: Option<usize>,
+    pub repetition_penalty: Option<f32>,
+    pub eos_token_id: Option<LlamaEosToks>,
+}
+
+impl GenerationConfig {
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, String> {
+        serde_json::from_slice(bytes).map_err(|e| format!("Invalid generation_config.json: {}", e))
+    }
+
+    /// The temperature to sample with. `do_sample: false` asks for greedy decoding, which
+    /// the generator does at temperature 0.
+    pub fn temperature(&self) -> Option<f64> {
+        match self.do_sample {
+            Some(false) => Some(0.),
+            _ => self.temperature,
+        }
+    }
+
+    /// `top_k`, where 0 means disabled as in `transformers`.
+    pub fn top_k(&self) -> Option<Option<usize>> {
+        self.top_k.map(|k| (k > 0).then_some(k))
+    }
+
+    /// `top_p`, where 1 keeps every token and so means disabled.
+    pub fn top_p(&self) -> Option<Option<f64>> {
+        self.top_p.map(|p| (p < 1.).then_some(p))
+    }
+
+    pub fn eos_token_ids(&self) -> Option<Vec<u32>> {
+        self.eos_token_id.as_ref().map(|ids| match ids {
+            LlamaEosToks::Single(id) => vec![*id],
+            LlamaEosToks::Multiple(ids) => ids.clone(),
+        })
+    }
+}
@@ -1,16 +1,45 @@
+use std::cell::{OnceCell, RefCell};
+use std::collections::BTreeMap;
+
+use candle_core::quantized::GgmlDType;
 use candle_core::{DType, Device, Tensor};
 use candle_nn::VarBuilder;
 use candle_transformers::{
-    generation::{LogitsProcessor, Sampling},
+    generation::{LogitsProcessor as CandleLogitsProcessor, Sampling},
     models::llama::{self as model, Config},
+    models::quantized_llama::ModelWeights,
 };
 
 use model::{Llama, LlamaConfig};
+use serde::{Deserialize, Serialize};
 use tokenizers::Tokenizer;
 use wasm_bindgen::prelude::*;
 
+use crate::beam::{self, BeamSearch};
+use crate::buffer::ByteBuffer;
+use crate::cache;
+use crate::constraint::{OutputConstraint, Vocabulary};
+use crate::generation_config::GenerationConfig;
+use crate::grammar::Grammar;
+use crate::hub;
+use crate::json_schema;
+use crate::loader::{WeightsLoader, LOAD_CHUNK_LEN};
+use crate::logits::{
+    Constrain, JsLogitsProcessor, LogitsPipeline, LogitsProcessor, LogitsProcessorCallback,
+    RepeatPenalty,
+};
+use crate::memory::{self, PREFLIGHT_CONTEXT_LEN};
+use crate::pattern::Pattern;
+use crate::penalties::{self, Penalties, LOGIT_BIAS_RANGE, PENALTY_RANGE};
+use crate::quantize::{self, Quantizer};
+use crate::repository::RepositoryDownload;
+use crate::sampling::{self, Sampler, SamplerChain};
+
 const EOS_TOKEN: &str = "</s>";
 
+/// Names accepted by `GenerationArguments::from_preset`.
+pub const PRESETS: &[&str] = &["greedy", "balanced", "creative", "deterministic-eval"];
+
 #[wasm_bindgen]
 extern "C" {
     #[wasm_bindgen(extends = js_sys::Function)]
@@ -18,7 +47,11 @@ extern "C" {
     pub type GeneratorCallback;
 }
 
+/// Serialized as a JSON object with these field names. Fields missing from the JSON take
+/// the values of `new()`, unknown fields are rejected.
 #[wasm_bindgen(getter_with_clone)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
 pub struct GenerationArguments {
     pub seed: u64,
     pub temperature: Option<f64>,
@@ -28,6 +61,40 @@ pub struct GenerationArguments {
     pub repeat_penalty: Option<f32>,
     pub repeat_last_n: Option<usize>,
     pub no_kv_cache: bool,
+    /// Subtracted from the logit of a token once per time it was generated, in [-2, 2].
+    pub frequency_penalty: Option<f32>,
+    /// Subtracted from the logit of every token generated at least once, in [-2, 2].
+    pub presence_penalty: Option<f32>,
+    /// Added to the logits of tokens, keyed by token id or token text, in [-100, 100]. Set
+    /// from JS with `set_logit_bias`.
+    #[wasm_bindgen(skip)]
+    pub logit_bias: Option<BTreeMap<String, f32>>,
+    /// Texts whose tokens are never generated in sequence.
+    pub banned_sequences: Option<Vec<String>>,
+    /// Tokens that end generation in addition to the EOS token of the model.
+    pub eos_token_ids: Option<Vec<u32>>,
+    /// A GBNF grammar the output has to match.
+    pub grammar: Option<String>,
+    /// A JSON Schema, as JSON, the output has to be a document of.
+    pub json_schema: Option<String>,
+    /// A regular expression the whole output has to match. At most one of `grammar`,
+    /// `json_schema` and `regex` can be set.
+    pub regex: Option<String>,
+    /// Samplers applied in order instead of `temperature`, `top_k` and `top_p`. Set from JS
+    /// with `set_samplers`.
+    #[wasm_bindgen(skip)]
+    pub samplers: Option<Vec<Sampler>>,
+    /// Beams kept by beam search, which replaces sampling when above 1. Beam search ignores
+    /// the sampling arguments and cannot follow a constraint.
+    pub num_beams: Option<usize>,
+    /// The exponent of the length that beam scores are divided by; above 0 favors longer
+    /// sequences.
+    pub length_penalty: Option<f64>,
+    /// Ends beam search once `num_beams` sequences are finished, rather than once no beam
+    /// can score better than them.
+    pub early_stopping: bool,
+    /// Sequences returned by `Generator.generate_beams`, at most `num_beams`.
+    pub num_return_sequences: Option<usize>,
 }
 
 pub struct GenerationArgumentsInternal {
@@ -39,6 +106,17 @@ pub struct GenerationArgumentsInternal {
     pub repeat_penalty: f32,
     pub repeat_last_n: usize,
     pub no_kv_cache: bool,
+    pub frequency_penalty: f32,
+    pub presence_penalty: f32,
+    pub logit_bias: BTreeMap<String, f32>,
+    pub banned_sequences: Vec<String>,
+    pub eos_token_ids: Vec<u32>,
+    pub constraint: Option<OutputConstraint>,
+    pub samplers: Option<Vec<Sampler>>,
+    pub num_beams: usize,
+    pub length_penalty: f64,
+    pub early_stopping: bool,
+    pub num_return_sequences: usize,
 }
 
 // Provide a constructor so JS can create an Arguments object and then mutate fields.
@@ -55,99 +133,619 @@ impl GenerationArguments {
             repeat_penalty: Some(1.1),
             repeat_last_n: Some(64),
             no_kv_cache: false,
+            frequency_penalty: None,
+            presence_penalty: None,
+            logit_bias: None,
+            banned_sequences: None,
+            eos_token_ids: None,
+            grammar: None,
+            json_schema: None,
+            regex: None,
+            samplers: None,
+            num_beams: None,
+            length_penalty: None,
+            early_stopping: false,
+            num_return_sequences: None,
+        }
+    }
+
+    /// Starts from the defaults of `new()` and overrides those that `generation_config.json`
+    /// sets, including its EOS token ids.
+    pub fn from_generation_config(bytes: &[u8]) -> Result<GenerationArguments, JsValue> {
+        let config = GenerationConfig::from_slice(bytes).map_err(|e| JsValue::from_str(&e))?;
+        let arguments = Self::new().with_generation_config(&config);
+        arguments.validate()?;
+
+        Ok(arguments)
+    }
+
+    /// Like `from_generation_config` with the file cached for `repository`, or the defaults
+    /// of `new()` when the repository has none.
+    pub async fn from_cache(repository: &str) -> Result<GenerationArguments, JsValue> {
+        match RepositoryDownload::get_file_buffer(repository, "generation_config.json").await {
+            Some(bytes) => Self::from_generation_config(bytes.as_slice()),
+            None => Ok(Self::new()),
+        }
+    }
+
+    /// One of the built-in presets: `greedy`, `balanced`, `creative` or
+    /// `deterministic-eval`.
+    pub fn from_preset(name: &str) -> Result<GenerationArguments, JsValue> {
+        Self::preset(name).map_err(|e| JsValue::from_str(&e))
+    }
+
+    pub fn preset_names() -> Vec<String> {
+        PRESETS.iter().map(|name| name.to_string()).collect()
+    }
+
+    /// Parses arguments written by `to_json`, failing on values `validate()` rejects.
+    pub fn from_json(json: &str) -> Result<GenerationArguments, JsValue> {
+        let arguments: GenerationArguments = serde_json::from_str(json)
+            .map_err(|e| JsValue::from_str(&format!("Invalid generation arguments: {}", e)))?;
+        arguments.validate()?;
+
+        Ok(arguments)
+    }
+
+    /// The sampler chain as an array of objects like `{"type": "min_p", "p": 0.05}`, or
+    /// `undefined` when sampling follows `temperature`, `top_k` and `top_p`.
+    pub fn samplers(&self) -> Result<JsValue, JsValue> {
+        match &self.samplers {
+            Some(samplers) => hub::to_js(samplers),
+            None => Ok(JsValue::UNDEFINED),
+        }
+    }
+
+    /// Sets the sampler chain from an array of samplers, applied in order, or clears it with
+    /// `undefined`. The types are `top_k`, `top_p`, `min_p`, `typical`, `tail_free`,
+    /// `top_a`, `temperature` and `mirostat_v2`.
+    pub fn set_samplers(&mut self, samplers: JsValue) -> Result<(), JsValue> {
+        if samplers.is_undefined() || samplers.is_null() {
+            self.samplers = None;
+            return Ok(());
+        }
+
+        let json = String::from(js_sys::JSON::stringify(&samplers)?);
+        let samplers = serde_json::from_str(&json)
+            .map_err(|e| JsValue::from_str(&format!("Invalid samplers: {}", e)))?;
+        self.samplers = Some(samplers);
+
+        Ok(())
+    }
+
+    /// The logit bias as an object from token ids or texts to biases, or `undefined`.
+    pub fn logit_bias(&self) -> Result<JsValue, JsValue> {
+        match &self.logit_bias {
+            Some(logit_bias) => hub::to_js(logit_bias),
+            None => Ok(JsValue::UNDEFINED),
         }
     }
+
+    /// Sets the logit bias from an object like `{"13": -100, "Hello": 5}`, or clears it with
+    /// `undefined`. Keys that parse as numbers are token ids.
+    pub fn set_logit_bias(&mut self, logit_bias: JsValue) -> Result<(), JsValue> {
+        if logit_bias.is_undefined() || logit_bias.is_null() {
+            self.logit_bias = None;
+            return Ok(());
+        }
+
+        let json = String::from(js_sys::JSON::stringify(&logit_bias)?);
+        let logit_bias = serde_json::from_str(&json)
+            .map_err(|e| JsValue::from_str(&format!("Invalid logit_bias: {}", e)))?;
+        self.logit_bias = Some(logit_bias);
+
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> Result<String, JsValue> {
+        serde_json::to_string(self).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Fails with every out-of-range value, such as a zero `top_k` or a negative `top_p`.
+    pub fn validate(&self) -> Result<(), JsValue> {
+        self.check().map_err(|e| JsValue::from_str(&e))
+    }
+}
+
+impl Default for GenerationArguments {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl GenerationArguments {
-    pub fn get_internal(&self) -> GenerationArgumentsInternal {
-        GenerationArgumentsInternal {
+    pub fn preset(name: &str) -> Result<Self, String> {
+        let defaults = Self::new();
+
+        Ok(match name {
+            // Always the most likely token, with the default penalty against loops
+            "greedy" => Self {
+                temperature: Some(0.),
+                top_k: None,
+                top_p: None,
+                ..defaults
+            },
+            "balanced" => Self {
+                temperature: Some(0.7),
+                top_k: Some(40),
+                top_p: Some(0.9),
+                ..defaults
+            },
+            "creative" => Self {
+                temperature: Some(1.1),
+                top_k: Some(100),
+                top_p: Some(0.95),
+                repeat_penalty: Some(1.15),
+                ..defaults
+            },
+            // Plain argmax decoding, so runs can be compared across models and versions
+            "deterministic-eval" => Self {
+                seed: 0,
+                temperature: Some(0.),
+                top_k: None,
+                top_p: None,
+                repeat_penalty: Some(1.),
+                ..defaults
+            },
+            _ => {
+                return Err(format!(
+                    "Unknown preset {}, expected one of {}",
+                    name,
+                    PRESETS.join(", ")
+                ))
+            }
+        })
+    }
+
+    /// Lists every out-of-range value and constraint that does not compile, separated by
+    /// `; `. Generation checks the arguments through `get_internal` instead, which keeps the
+    /// compiled constraint.
+    pub fn check(&self) -> Result<(), String> {
+        self.get_internal().map(|_| ())
+    }
+
+    fn value_errors(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if let Some(temperature) = self.temperature {
+            if !(temperature >= 0. && temperature.is_finite()) {
+                errors.push(format!(
+                    "temperature must be 0 or more, got {}",
+                    temperature
+                ));
+            }
+        }
+        if self.top_k == Some(0) {
+            errors.push("top_k must be at least 1, or null to disable it".to_string());
+        }
+        if let Some(top_p) = self.top_p {
+            if !(top_p > 0. && top_p <= 1.) {
+                errors.push(format!("top_p must be in (0, 1], got {}", top_p));
+            }
+        }
+        if self.sample_len == Some(0) {
+            errors.push("sample_len must be at least 1".to_string());
+        }
+        if let Some(repeat_penalty) = self.repeat_penalty {
+            if !(repeat_penalty > 0. && repeat_penalty.is_finite()) {
+                errors.push(format!(
+                    "repeat_penalty must be more than 0, got {}",
+                    repeat_penalty
+                ));
+            }
+        }
+
+        for (name, penalty) in [
+            ("frequency_penalty", self.frequency_penalty),
+            ("presence_penalty", self.presence_penalty),
+        ] {
+            if let Some(penalty) = penalty {
+                if !(PENALTY_RANGE.0..=PENALTY_RANGE.1).contains(&penalty) {
+                    errors.push(format!("{} must be in [-2, 2], got {}", name, penalty));
+                }
+            }
+        }
+        for (key, bias) in self.logit_bias.iter().flatten() {
+            if !(LOGIT_BIAS_RANGE.0..=LOGIT_BIAS_RANGE.1).contains(bias) {
+                errors.push(format!(
+                    "logit_bias of {:?} must be in [-100, 100], got {}",
+                    key, bias
+                ));
+            }
+        }
+        if self.banned_sequences.iter().flatten().any(String::is_empty) {
+            errors.push("banned_sequences cannot contain an empty text".to_string());
+        }
+
+        let num_beams = self.num_beams.unwrap_or(1);
+        if num_beams == 0 {
+            errors.push("num_beams must be at least 1".to_string());
+        }
+        match self.num_return_sequences {
+            Some(0) => errors.push("num_return_sequences must be at least 1".to_string()),
+            Some(count) if count > num_beams.max(1) => errors.push(format!(
+                "num_return_sequences must be at most num_beams, got {}",
+                count
+            )),
+            _ => {}
+        }
+        if let Some(length_penalty) = self.length_penalty {
+            if !length_penalty.is_finite() {
+                errors.push(format!(
+                    "length_penalty must be finite, got {}",
+                    length_penalty
+                ));
+            }
+        }
+        let constrained =
+            self.grammar.is_some() || self.json_schema.is_some() || self.regex.is_some();
+        if num_beams > 1 && constrained {
+            errors.push("Beam search cannot follow a grammar, json_schema or regex".to_string());
+        }
+
+        if let Some(Err(e)) = self.samplers.as_deref().map(sampling::check_samplers) {
+            errors.push(e);
+        }
+
+        errors
+    }
+
+    pub fn with_generation_config(self, config: &GenerationConfig) -> Self {
+        Self {
+            temperature: config.temperature().or(self.temperature),
+            top_k: config.top_k().unwrap_or(self.top_k),
+            top_p: config.top_p().unwrap_or(self.top_p),
+            sample_len: config.max_new_tokens.or(self.sample_len),
+            repeat_penalty: config.repetition_penalty.or(self.repeat_penalty),
+            eos_token_ids: config.eos_token_ids().or(self.eos_token_ids),
+            ..self
+        }
+    }
+
+    /// The constraint of `grammar`, `json_schema` or `regex`, if one is set.
+    pub fn compile_constraint(&self) -> Result<Option<OutputConstraint>, String> {
+        let constraint = match (&self.grammar, &self.json_schema, &self.regex) {
+            (None, None, None) => None,
+            (Some(grammar), None, None) => {
+                Some(OutputConstraint::Grammar(Grammar::parse(grammar)?))
+            }
+            (None, Some(schema), None) => {
+                let schema = serde_json::from_str(schema)
+                    .map_err(|e| format!("Invalid json_schema: {}", e))?;
+                let grammar = Grammar::parse(&json_schema::json_schema_to_gbnf(&schema)?)?;
+                Some(OutputConstraint::Grammar(grammar))
+            }
+            (None, None, Some(regex)) => {
+                Some(OutputConstraint::Pattern(Box::new(Pattern::new(regex)?)))
+            }
+            _ => return Err("Only one of grammar, json_schema and regex can be set".to_string()),
+        };
+
+        Ok(constraint)
+    }
+
+    /// Checks the arguments like `check` and fills in the defaults. The constraint is
+    /// compiled here and only here, since building a DFA or grammar can be expensive.
+    pub fn get_internal(&self) -> Result<GenerationArgumentsInternal, String> {
+        let mut errors = self.value_errors();
+        let constraint = self.compile_constraint().unwrap_or_else(|e| {
+            errors.push(e);
+            None
+        });
+        if !errors.is_empty() {
+            return Err(errors.join("; "));
+        }
+
+        Ok(GenerationArgumentsInternal {
             seed: self.seed,
             temperature: self.temperature.unwrap_or(1.0),
             top_k: self.top_k,
             top_p: self.top_p,
             sample_len: self.sample_len.unwrap_or(128),
-            repeat_penalty: self.repeat_penalty.unwrap_or(1.0) as f32,
+            repeat_penalty: self.repeat_penalty.unwrap_or(1.0),
             repeat_last_n: self.repeat_last_n.unwrap_or(64),
             no_kv_cache: self.no_kv_cache,
+            frequency_penalty: self.frequency_penalty.unwrap_or(0.),
+            presence_penalty: self.presence_penalty.unwrap_or(0.),
+            logit_bias: self.logit_bias.clone().unwrap_or_default(),
+            banned_sequences: self.banned_sequences.clone().unwrap_or_default(),
+            eos_token_ids: self.eos_token_ids.clone().unwrap_or_default(),
+            constraint,
+            samplers: self.samplers.clone(),
+            num_beams: self.num_beams.unwrap_or(1),
+            length_penalty: self.length_penalty.unwrap_or(1.),
+            early_stopping: self.early_stopping,
+            num_return_sequences: self.num_return_sequences.unwrap_or(1),
+        })
+    }
+}
+
+/// The loaded Llama, with float or quantized weights.
+enum Model {
+    Full(Llama),
+    /// Quantized weights keep their KV cache inside the model, so `forward` needs `&mut`.
+    Quantized(RefCell<ModelWeights>),
+}
+
+impl Model {
+    fn forward(
+        &self,
+        input: &Tensor,
+        index_pos: usize,
+        cache: &mut model::Cache,
+    ) -> candle_core::Result<Tensor> {
+        match self {
+            Model::Full(model) => model.forward(input, index_pos, cache),
+            // Starting again at position 0 replaces the cache of an earlier generation
+            Model::Quantized(model) => model.borrow_mut().forward(input, index_pos),
+        }
+    }
+
+    /// A cache for one beam, starting from `cache`.
+    fn beam_cache(&self, cache: model::Cache) -> BeamCache {
+        match self {
+            Model::Full(_) => BeamCache::Full(cache),
+            Model::Quantized(model) => BeamCache::Quantized(model.borrow().clone()),
+        }
+    }
+
+    fn forward_beam(
+        &self,
+        input: &Tensor,
+        index_pos: usize,
+        cache: &mut BeamCache,
+    ) -> candle_core::Result<Tensor> {
+        match (self, cache) {
+            (Model::Full(model), BeamCache::Full(cache)) => model.forward(input, index_pos, cache),
+            (_, BeamCache::Quantized(model)) => model.forward(input, index_pos),
+            (Model::Quantized(_), BeamCache::Full(_)) => {
+                candle_core::bail!("The beam cache does not belong to the model")
+            }
         }
     }
 }
 
+/// The KV cache of one beam. The quantized model keeps its cache inside, so every beam gets
+/// a copy of the model, which shares the weights.
+#[derive(Clone)]
+enum BeamCache {
+    Full(model::Cache),
+    Quantized(ModelWeights),
+}
+
+/// A sequence found by beam search.
+#[derive(Serialize, Clone, Debug)]
+pub struct BeamOutput {
+    pub text: String,
+    pub tokens: Vec<u32>,
+    /// The sum of the log-probabilities of the tokens, divided by the length raised to
+    /// `length_penalty`.
+    pub score: f64,
+}
+
+/// Where the weights of a model being loaded come from.
+enum Weights<'a> {
+    Tensors(VarBuilder<'a>),
+    Quantized(Quantizer),
+}
+
 #[wasm_bindgen]
 pub struct Generator {
-    model: Llama,
+    model: Model,
     tokenizer: Tokenizer,
     config: Config,
     dtype: DType,
     device: Device,
+    /// Built on the first constrained generation.
+    vocabulary: OnceCell<Vocabulary>,
 }
 
 #[wasm_bindgen]
 impl Generator {
+    /// Loads the model, failing early when it would not fit into wasm32 memory together with
+    /// the weights buffer, its KV cache and activations.
     #[wasm_bindgen(constructor)]
     pub fn new(
         model_bytes: Vec<u8>,
         tokenizer_bytes: Vec<u8>,
         config_bytes: Vec<u8>,
         dtype: Option<String>,
-    ) -> Self {
-        let tokenizer = Tokenizer::from_bytes(tokenizer_bytes).unwrap();
+    ) -> Result<Generator, JsValue> {
+        let dtype = memory::parse_dtype(dtype.as_deref()).map_err(|e| JsValue::from_str(&e))?;
 
-        let config: LlamaConfig = serde_json::from_slice(&config_bytes).unwrap();
-        let config = config.into_config(false);
+        // The weights buffer stays alive until every tensor has been converted
+        let (tokenizer, config) = Self::prepare(
+            tokenizer_bytes,
+            &config_bytes,
+            dtype,
+            None,
+            model_bytes.len() as u64,
+        )?;
+        let device = Device::cuda_if_available(0).unwrap_or(Device::Cpu);
+
+        let vb = VarBuilder::from_buffered_safetensors(model_bytes, dtype, &device)
+            .map_err(|e| JsValue::from_str(&format!("Invalid model weights: {}", e)))?;
+
+        Self::load(Weights::Tensors(vb), tokenizer, config, dtype, device)
+    }
 
+    /// Takes ownership of buffers from `DownloadTask.start_buffer` or
+    /// `RepositoryDownload.get_file_buffer`, so the weights are not copied again. The buffers
+    /// cannot be used afterwards.
+    pub fn from_buffers(
+        model: ByteBuffer,
+        tokenizer: ByteBuffer,
+        config: ByteBuffer,
+        dtype: Option<String>,
+    ) -> Result<Generator, JsValue> {
+        Self::new(
+            model.into_inner(),
+            tokenizer.into_inner(),
+            config.into_inner(),
+            dtype,
+        )
+    }
+
+    /// Builds the model from the tensors of a complete `WeightsLoader`, which cannot be used
+    /// afterwards. Weights are loaded in the dtype of the loader, or run through the
+    /// quantized model when the loader quantized them.
+    pub fn from_weights(
+        weights: WeightsLoader,
+        tokenizer: ByteBuffer,
+        config: ByteBuffer,
+    ) -> Result<Generator, JsValue> {
+        let quantization = weights.quantization();
+        let dtype = match quantization {
+            Some(_) => DType::F32,
+            None => weights.dtype(),
+        };
+        let (tokenizer, config) = Self::prepare(
+            tokenizer.into_inner(),
+            config.as_slice(),
+            dtype,
+            quantization,
+            0,
+        )?;
         let device = Device::cuda_if_available(0).unwrap_or(Device::Cpu);
-        let dtype = match dtype.as_deref() {
-            Some("f16") => DType::F16,
-            Some("bf16") => DType::BF16,
-            Some("f32") => DType::F32,
-            Some(dtype) => {
-                println!("Error: wrong dtype {dtype}");
 
-                DType::F16
-            }
-            None => DType::F16,
+        let weights = Self::weights(weights, dtype, &device)?;
+        Self::load(weights, tokenizer, config, dtype, device)
+    }
+
+    /// Loads a cached repository with peak memory close to the size of the model: the
+    /// memory check runs before any weights are read, and the weights are converted tensor
+    /// by tensor from the cached file instead of being copied into the wasm heap first.
+    ///
+    /// With `quantization`, `q8_0` or `q4_k`, the linear layers are quantized while loading
+    /// and the model runs quantized matmuls in f32, for models too large in float weights.
+    pub async fn from_cache(
+        repository: &str,
+        dtype: Option<String>,
+        quantization: Option<String>,
+    ) -> Result<Generator, JsValue> {
+        let error = |e: String| JsValue::from_str(&e);
+        let dtype_name = dtype.clone();
+        let ggml_dtype = quantize::parse_quantization(quantization.as_deref()).map_err(error)?;
+        let dtype = match ggml_dtype {
+            Some(_) => DType::F32,
+            None => memory::parse_dtype(dtype.as_deref()).map_err(error)?,
         };
 
-        let vb = VarBuilder::from_buffered_safetensors(model_bytes, dtype, &device).unwrap();
-        let model = Llama::load(vb, &config).unwrap();
+        let read = |filename: &'static str| async move {
+            RepositoryDownload::get_file_buffer(repository, filename)
+                .await
+                .ok_or_else(|| JsValue::from_str(&format!("{} is not cached", filename)))
+        };
+        let tokenizer = read("tokenizer.json").await?;
+        let config = read("config.json").await?;
 
-        Self {
-            model,
-            tokenizer,
-            config,
+        let (tokenizer, config) = Self::prepare(
+            tokenizer.into_inner(),
+            config.as_slice(),
             dtype,
-            device,
-        }
+            ggml_dtype,
+            LOAD_CHUNK_LEN as u64,
+        )?;
+        let device = Device::cuda_if_available(0).unwrap_or(Device::Cpu);
+
+        let weights = WeightsLoader::from_cache(repository, None, dtype_name, quantization).await?;
+        let weights = Self::weights(weights, dtype, &device)?;
+
+        let generator = Self::load(weights, tokenizer, config, dtype, device)?;
+        cache::set_loaded_repository(repository);
+        Ok(generator)
     }
 
+    /// Generates a completion of `input`, passing every piece of text to `callback` as it is
+    /// decoded, and returns the whole of it. Fails when the arguments are invalid or
+    /// generation fails.
     pub fn generate(
         &self,
         input: &str,
         arguments: Option<GenerationArguments>,
         callback: Option<GeneratorCallback>,
-    ) -> String {
-        self.generate_inner(input, arguments, |output| {
+    ) -> Result<String, JsValue> {
+        self.generate_inner(input, arguments, Vec::new(), |output| {
+            if let Some(callback) = &callback {
+                callback
+                    .call1(&JsValue::NULL, &JsValue::from_str(output))
+                    .unwrap();
+            }
+        })
+        .map(|(generated, _)| generated)
+        .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Runs beam search and returns the `num_return_sequences` best sequences, best first, as
+    /// objects with the `text`, `tokens` and `score` of each.
+    pub fn generate_beams(
+        &self,
+        input: &str,
+        arguments: Option<GenerationArguments>,
+    ) -> Result<JsValue, JsValue> {
+        let args = arguments
+            .unwrap_or_default()
+            .get_internal()
+            .map_err(|e| JsValue::from_str(&e))?;
+
+        let beams = self
+            .beam_search(input, args)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        hub::to_js(&beams)
+    }
+
+    /// Like `generate`, with every function of `processors` called in order on the logits
+    /// of each token after the penalties and before any constraint. A function receives the
+    /// logits as a `Float32Array` with the prompt and generated tokens as a `Uint32Array`,
+    /// and changes the logits in place or returns new ones. Fails when generation does.
+    pub fn generate_with_processors(
+        &self,
+        input: &str,
+        arguments: Option<GenerationArguments>,
+        processors: Vec<LogitsProcessorCallback>,
+        callback: Option<GeneratorCallback>,
+    ) -> Result<String, JsValue> {
+        let processors = processors
+            .into_iter()
+            .map(|processor| Box::new(JsLogitsProcessor(processor)) as Box<dyn LogitsProcessor>)
+            .collect();
+
+        self.generate_inner(input, arguments, processors, |output| {
             if let Some(callback) = &callback {
                 callback
                     .call1(&JsValue::NULL, &JsValue::from_str(output))
                     .unwrap();
             }
         })
-        .unwrap_or_default()
-        .0
+        .map(|(generated, _)| generated)
+        .map_err(|e| JsValue::from_str(&e.to_string()))
     }
 
     fn generate_inner(
         &self,
         input: &str,
         arguments: Option<GenerationArguments>,
+        processors: Vec<Box<dyn LogitsProcessor + '_>>,
         callback: impl Fn(&str),
     ) -> anyhow::Result<(String, i32)> {
         let args = arguments
-            .unwrap_or_else(|| GenerationArguments::new())
-            .get_internal();
+            .unwrap_or_default()
+            .get_internal()
+            .map_err(anyhow::Error::msg)?;
+
+        // The best beam comes out whole, once the search is over
+        if args.num_beams > 1 {
+            if !processors.is_empty() {
+                anyhow::bail!("Logits processors cannot be combined with beam search");
+            }
+            let best = self
+                .beam_search(input, args)?
+                .into_iter()
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("Beam search returned no sequence"))?;
+            callback(&best.text);
+            return Ok((best.text, best.tokens.len() as i32));
+        }
 
         let mut tokenizer =
             crate::token_output_stream::TokenOutputStream::new(self.tokenizer.clone());
@@ -161,37 +759,17 @@ impl Generator {
 
         let mut cache =
             model::Cache::new(!args.no_kv_cache, self.dtype, &self.config, &self.device)?;
+        let sample_len = args.sample_len;
+        let eos_token_ids = self.eos_token_ids(&args);
 
-        let mut logits_processor = {
-            let temperature = args.temperature;
-
-            let sampling = if temperature <= 0. {
-                Sampling::ArgMax
-            } else {
-                match (args.top_k, args.top_p) {
-                    (None, None) => Sampling::All { temperature },
-                    (Some(k), None) => Sampling::TopK { k, temperature },
-                    (None, Some(p)) => Sampling::TopP { p, temperature },
-                    (Some(k), Some(p)) => Sampling::TopKThenTopP { k, p, temperature },
-                }
-            };
-            LogitsProcessor::from_sampling(args.seed, sampling)
-        };
-
-        let eos_token_id = self.config.eos_token_id.clone().or_else(|| {
-            self.tokenizer
-                .token_to_id(EOS_TOKEN)
-                .map(model::LlamaEosToks::Single)
-        });
+        let mut pipeline = self
+            .pipeline(args, &eos_token_ids, processors)
+            .map_err(anyhow::Error::msg)?;
 
         let mut index_pos = 0;
         let mut token_generated = 0;
         let mut all_generated = String::new();
 
-        let sample_len = args.sample_len;
-        let repeat_penalty = args.repeat_penalty;
-        let repeat_last_n = args.repeat_last_n;
-
         for index in 0..sample_len {
             let (context_size, context_index) = if cache.use_kv_cache && index > 0 {
                 (1, index_pos)
@@ -202,34 +780,24 @@ impl Generator {
             let ctxt = &tokens[tokens.len().saturating_sub(context_size)..];
             let input = Tensor::new(ctxt, &self.device)?.unsqueeze(0)?;
             let logits = self.model.forward(&input, context_index, &mut cache)?;
-            let logits = logits.squeeze(0)?;
-            let logits = if repeat_penalty == 1. {
-                logits
-            } else {
-                let start_at = tokens.len().saturating_sub(repeat_last_n);
-                candle_transformers::utils::apply_repeat_penalty(
-                    &logits,
-                    repeat_penalty,
-                    &tokens[start_at..],
-                )?
-            };
+            let logits: Vec<f32> = logits.squeeze(0)?.to_dtype(DType::F32)?.to_vec1()?;
             index_pos += ctxt.len();
 
-            let next_token = logits_processor.sample(&logits)?;
+            let next_token = match pipeline
+                .next_token(&tokens, logits)
+                .map_err(anyhow::Error::msg)?
+            {
+                Some(token) => token,
+                // A constraint is complete, and there is no EOS token to end with
+                None => break,
+            };
             token_generated += 1;
             tokens.push(next_token);
 
-            match eos_token_id {
-                Some(model::LlamaEosToks::Single(eos_tok_id)) if next_token == eos_tok_id => {
-                    break;
-                }
-                Some(model::LlamaEosToks::Multiple(ref eos_ids))
-                    if eos_ids.contains(&next_token) =>
-                {
-                    break;
-                }
-                _ => (),
+            if eos_token_ids.contains(&next_token) {
+                break;
             }
+            pipeline.accept(next_token).map_err(anyhow::Error::msg)?;
             if let Some(t) = tokenizer.next_token(next_token)? {
                 callback(&t);
                 all_generated.push_str(&t);
@@ -244,3 +812,258 @@ impl Generator {
         Ok((all_generated, token_generated))
     }
 }
+
+impl Generator {
+    /// The processors the arguments ask for, in order: the repeat penalty, the frequency and
+    /// presence penalties with the logit bias and banned sequences, `processors`, and the
+    /// constraint last so nothing unmasks what it rejects. Samples with the sampler chain of
+    /// the arguments, or else with `temperature`, `top_k` and `top_p`.
+    pub fn pipeline<'a>(
+        &'a self,
+        args: GenerationArgumentsInternal,
+        eos_token_ids: &[u32],
+        processors: Vec<Box<dyn LogitsProcessor + 'a>>,
+    ) -> Result<LogitsPipeline<'a>, String> {
+        let penalties = self.penalties(&args)?;
+        let mut pipeline = match args.samplers {
+            Some(samplers) => LogitsPipeline::new(SamplerChain::new(samplers, args.seed)),
+            None => {
+                let temperature = args.temperature;
+                let sampling = if temperature <= 0. {
+                    Sampling::ArgMax
+                } else {
+                    match (args.top_k, args.top_p) {
+                        (None, None) => Sampling::All { temperature },
+                        (Some(k), None) => Sampling::TopK { k, temperature },
+                        (None, Some(p)) => Sampling::TopP { p, temperature },
+                        (Some(k), Some(p)) => Sampling::TopKThenTopP { k, p, temperature },
+                    }
+                };
+                LogitsPipeline::new(CandleLogitsProcessor::from_sampling(args.seed, sampling))
+            }
+        };
+
+        if args.repeat_penalty != 1. {
+            pipeline.push(RepeatPenalty {
+                penalty: args.repeat_penalty,
+                last_n: args.repeat_last_n,
+            });
+        }
+
+        if !penalties.is_empty() {
+            pipeline.push(penalties);
+        }
+
+        pipeline.extend(processors);
+
+        if let Some(constraint) = args.constraint {
+            let vocabulary = self
+                .vocabulary
+                .get_or_init(|| Vocabulary::new(&self.tokenizer));
+            pipeline.push(Constrain {
+                constraint: constraint.follow(vocabulary),
+                eos_token_ids: eos_token_ids.to_vec(),
+            });
+        }
+
+        Ok(pipeline)
+    }
+
+    /// The EOS tokens of the arguments, with that of the config or else the tokenizer.
+    fn eos_token_ids(&self, args: &GenerationArgumentsInternal) -> Vec<u32> {
+        let eos_token_id = self.config.eos_token_id.clone().or_else(|| {
+            self.tokenizer
+                .token_to_id(EOS_TOKEN)
+                .map(model::LlamaEosToks::Single)
+        });
+        let mut eos_token_ids = args.eos_token_ids.clone();
+        match eos_token_id {
+            Some(model::LlamaEosToks::Single(id)) => eos_token_ids.push(id),
+            Some(model::LlamaEosToks::Multiple(ids)) => eos_token_ids.extend(ids),
+            None => {}
+        }
+
+        eos_token_ids
+    }
+
+    fn penalties(&self, args: &GenerationArgumentsInternal) -> Result<Penalties, String> {
+        Ok(Penalties::new(
+            args.frequency_penalty,
+            args.presence_penalty,
+            penalties::resolve_logit_bias(&args.logit_bias, &self.tokenizer)?,
+            penalties::encode_banned_sequences(&args.banned_sequences, &self.tokenizer)?,
+        ))
+    }
+
+    /// Beam search with a KV cache and penalties per beam. The repeat penalty looks at the
+    /// prompt and the beam, the other penalties at the beam only. Fails rather than
+    /// returning nothing when no token could be picked, as with NaN logits.
+    pub fn beam_search(
+        &self,
+        input: &str,
+        args: GenerationArgumentsInternal,
+    ) -> anyhow::Result<Vec<BeamOutput>> {
+        let prompt = self
+            .tokenizer
+            .encode(input, true)
+            .map_err(anyhow::Error::msg)?
+            .get_ids()
+            .to_vec();
+
+        let use_kv_cache = !args.no_kv_cache;
+        let cache = model::Cache::new(use_kv_cache, self.dtype, &self.config, &self.device)?;
+        let mut caches = vec![self.model.beam_cache(cache)];
+        let mut penalties = vec![self.penalties(&args).map_err(anyhow::Error::msg)?];
+        let repeat_penalty = RepeatPenalty {
+            penalty: args.repeat_penalty,
+            last_n: args.repeat_last_n,
+        };
+
+        let mut search = BeamSearch::new(
+            args.num_beams,
+            args.length_penalty,
+            args.early_stopping,
+            self.eos_token_ids(&args),
+        );
+
+        for index in 0..args.sample_len {
+            let mut logprobs = Vec::with_capacity(caches.len());
+            for ((beam, cache), penalties) in search.beams().iter().zip(&mut caches).zip(&penalties)
+            {
+                let tokens = [prompt.as_slice(), &beam.tokens].concat();
+                let (context_size, context_index) = if use_kv_cache && index > 0 {
+                    (1, tokens.len() - 1)
+                } else {
+                    (tokens.len(), 0)
+                };
+
+                let ctxt = &tokens[tokens.len() - context_size..];
+                let input = Tensor::new(ctxt, &self.device)?.unsqueeze(0)?;
+                let logits = self.model.forward_beam(&input, context_index, cache)?;
+                let mut logits: Vec<f32> = logits.squeeze(0)?.to_dtype(DType::F32)?.to_vec1()?;
+
+                if args.repeat_penalty != 1. {
+                    repeat_penalty.apply(&tokens, &mut logits);
+                }
+                penalties.apply(&mut logits);
+                logprobs.push(beam::log_softmax(&logits));
+            }
+
+            let parents = search.step(&logprobs);
+            caches = parents
+                .iter()
+                .map(|&parent| caches[parent].clone())
+                .collect();
+            penalties = parents
+                .iter()
+                .zip(search.beams())
+                .map(|(&parent, beam)| {
+                    let mut penalties = penalties[parent].clone();
+                    penalties.accept(beam.tokens[beam.tokens.len() - 1]);
+                    penalties
+                })
+                .collect();
+
+            if search.is_done() {
+                break;
+            }
+        }
+
+        let hypotheses = search.finish(args.num_return_sequences);
+        if hypotheses.is_empty() {
+            anyhow::bail!("Beam search found no sequence: no token had a finite log-probability");
+        }
+
+        hypotheses
+            .into_iter()
+            .map(|hypothesis| {
+                let text = self
+                    .tokenizer
+                    .decode(&hypothesis.tokens, true)
+                    .map_err(anyhow::Error::msg)?;
+                Ok(BeamOutput {
+                    text,
+                    tokens: hypothesis.tokens,
+                    score: hypothesis.score,
+                })
+            })
+            .collect()
+    }
+
+    /// Parses the tokenizer and config and checks that the model fits into wasm32 memory
+    /// with `resident_bytes` held next to it while loading.
+    fn prepare(
+        tokenizer_bytes: Vec<u8>,
+        config_bytes: &[u8],
+        dtype: DType,
+        quantization: Option<GgmlDType>,
+        resident_bytes: u64,
+    ) -> Result<(Tokenizer, LlamaConfig), JsValue> {
+        let error = |e: String| JsValue::from_str(&e);
+
+        let tokenizer = Tokenizer::from_bytes(tokenizer_bytes)
+            .map_err(|e| error(format!("Invalid tokenizer.json: {}", e)))?;
+
+        let config: LlamaConfig = serde_json::from_slice(config_bytes)
+            .map_err(|e| error(format!("Invalid config.json: {}", e)))?;
+
+        let context_len = config.max_position_embeddings.min(PREFLIGHT_CONTEXT_LEN);
+        let estimate = match quantization {
+            Some(quantization) => memory::estimate_quantized(&config, quantization, context_len, 1),
+            None => memory::estimate(&config, dtype, context_len, 1),
+        }
+        .map_err(error)?
+        .with_resident_bytes(resident_bytes);
+        if !estimate.fits {
+            return Err(error(format!(
+                "Model does not fit into memory: {}",
+                estimate.describe()
+            )));
+        }
+
+        Ok((tokenizer, config))
+    }
+
+    fn weights(
+        weights: WeightsLoader,
+        dtype: DType,
+        device: &Device,
+    ) -> Result<Weights<'static>, JsValue> {
+        let error = |e: String| JsValue::from_str(&e);
+
+        Ok(if weights.is_quantized() {
+            Weights::Quantized(weights.finish_quantized().map_err(error)?)
+        } else {
+            let tensors = weights.finish().map_err(error)?;
+            Weights::Tensors(VarBuilder::from_tensors(tensors, dtype, device))
+        })
+    }
+
+    fn load(
+        weights: Weights,
+        tokenizer: Tokenizer,
+        config: LlamaConfig,
+        dtype: DType,
+        device: Device,
+    ) -> Result<Generator, JsValue> {
+        let config = config.into_config(false);
+        let model = match weights {
+            Weights::Tensors(vb) => Model::Full(
+                Llama::load(vb, &config)
+                    .map_err(|e| JsValue::from_str(&format!("Failed to load model: {}", e)))?,
+            ),
+            Weights::Quantized(quantizer) => Model::Quantized(RefCell::new(
+                quantizer.load(&device).map_err(|e| JsValue::from_str(&e))?,
+            )),
+        };
+
+        Ok(Self {
+            model,
+            tokenizer,
+            config,
+            dtype,
+            device,
+            vocabulary: OnceCell::new(),
+        })
+    }
+}
@@ -0,0 +1,709 @@
+use std::collections::HashMap;
+
+use crate::constraint::Constraint;
+
+/// Largest count a `{m,n}` repetition may give. Every optional repetition becomes a rule, so
+/// larger counts would let a short grammar allocate without bound.
+pub const MAX_REPETITIONS: usize = 4096;
+
+/// Matches one character against a set of inclusive ranges.
+#[derive(Clone, Debug, PartialEq)]
+struct CharClass {
+    ranges: Vec<(char, char)>,
+    negated: bool,
+}
+
+impl CharClass {
+    fn single(c: char) -> Self {
+        Self {
+            ranges: vec![(c, c)],
+            negated: false,
+        }
+    }
+
+    fn matches(&self, c: char) -> bool {
+        let found = self
+            .ranges
+            .iter()
+            .any(|&(start, end)| start <= c && c <= end);
+
+        found != self.negated
+    }
+
+    /// Whether any code point from `first` to `last` matches.
+    fn intersects(&self, first: u32, last: u32) -> bool {
+        if !self.negated {
+            return self
+                .ranges
+                .iter()
+                .any(|&(start, end)| start as u32 <= last && first <= end as u32);
+        }
+
+        // Some code point of the span is not covered by the ranges
+        let mut ranges = self.ranges.clone();
+        ranges.sort();
+        let mut next = first;
+        for (start, end) in ranges {
+            if start as u32 > next {
+                break;
+            }
+            next = next.max(end as u32 + 1);
+        }
+        next <= last
+    }
+}
+
+/// The first and last code point of the `len` byte UTF-8 characters starting with `partial`.
+fn utf8_range(partial: &[u8], len: usize) -> (u32, u32) {
+    let lead = match len {
+        2 => partial[0] & 0x1F,
+        3 => partial[0] & 0x0F,
+        _ => partial[0] & 0x07,
+    } as u32;
+    let known = partial[1..]
+        .iter()
+        .fold(lead, |value, byte| value << 6 | (byte & 0x3F) as u32);
+    let unknown = 6 * (len - partial.len()) as u32;
+
+    (known << unknown, (known << unknown) | ((1 << unknown) - 1))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Element {
+    Char(CharClass),
+    Rule(usize),
+}
+
+/// The next element to match: element `element` of alternative `alternative` of `rule`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct Position {
+    rule: u32,
+    alternative: u32,
+    element: u32,
+}
+
+/// Where a grammar can be after the bytes matched so far. Every stack holds the positions
+/// still to match, innermost last; an empty stack has matched the whole root rule.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct GrammarState {
+    stacks: Vec<Vec<Position>>,
+    /// Leading bytes of a UTF-8 character split across tokens.
+    partial: Vec<u8>,
+}
+
+/// A context-free grammar in the GBNF format of llama.cpp: rules like `name ::= ...` with
+/// string literals, character classes, `.`, groups, alternatives and the `*`, `+`, `?` and
+/// `{m,n}` repetitions. Generation starts at the `root` rule. Left recursion is rejected.
+#[derive(Clone, Debug)]
+pub struct Grammar {
+    names: Vec<String>,
+    rules: Vec<Vec<Vec<Element>>>,
+    root: usize,
+}
+
+impl Grammar {
+    pub fn parse(source: &str) -> Result<Grammar, String> {
+        let mut parser = Parser {
+            chars: source.chars().collect(),
+            offset: 0,
+            names: Vec::new(),
+            ids: HashMap::new(),
+            rules: Vec::new(),
+        };
+        parser.parse()?;
+
+        let undefined = parser
+            .rules
+            .iter()
+            .position(Option::is_none)
+            .map(|rule| parser.names[rule].clone());
+        if let Some(name) = undefined {
+            return Err(format!("Grammar rule {} is not defined", name));
+        }
+
+        let root = *parser
+            .ids
+            .get("root")
+            .ok_or_else(|| "Grammar has no root rule".to_string())?;
+        let grammar = Grammar {
+            names: parser.names,
+            rules: parser.rules.into_iter().flatten().collect(),
+            root,
+        };
+        grammar.check_left_recursion()?;
+
+        Ok(grammar)
+    }
+
+    /// Whether `text` as a whole matches the root rule.
+    pub fn matches(&self, text: &str) -> bool {
+        let mut state = self.start();
+        for byte in text.bytes() {
+            match self.advance(&state, byte) {
+                Some(next) => state = next,
+                None => return false,
+            }
+        }
+
+        self.is_accepting(&state)
+    }
+
+    fn nullable(&self) -> Vec<bool> {
+        let mut nullable = vec![false; self.rules.len()];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (rule, alternatives) in self.rules.iter().enumerate() {
+                if nullable[rule] {
+                    continue;
+                }
+                let empty = alternatives.iter().any(|alternative| {
+                    alternative.iter().all(|element| match element {
+                        Element::Char(_) => false,
+                        Element::Rule(rule) => nullable[*rule],
+                    })
+                });
+                if empty {
+                    nullable[rule] = true;
+                    changed = true;
+                }
+            }
+        }
+
+        nullable
+    }
+
+    /// Matching would expand a left recursive rule forever, so it is an error like in
+    /// llama.cpp.
+    fn check_left_recursion(&self) -> Result<(), String> {
+        let nullable = self.nullable();
+        // The rules each rule can start with, through nullable prefixes
+        let leftmost: Vec<Vec<usize>> = self
+            .rules
+            .iter()
+            .map(|alternatives| {
+                let mut rules = Vec::new();
+                for alternative in alternatives {
+                    for element in alternative {
+                        match element {
+                            Element::Char(_) => break,
+                            Element::Rule(rule) => {
+                                rules.push(*rule);
+                                if !nullable[*rule] {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                }
+                rules
+            })
+            .collect();
+
+        // 0 unvisited, 1 on the current path, 2 done
+        let mut marks = vec![0u8; self.rules.len()];
+        for start in 0..self.rules.len() {
+            let mut path = vec![(start, 0)];
+            while let Some((rule, next)) = path.pop() {
+                if next == 0 {
+                    if marks[rule] == 2 {
+                        continue;
+                    }
+                    marks[rule] = 1;
+                }
+                match leftmost[rule].get(next) {
+                    Some(&child) => {
+                        path.push((rule, next + 1));
+                        match marks[child] {
+                            1 => {
+                                return Err(format!(
+                                    "Grammar rule {} is left recursive",
+                                    self.names[child]
+                                ))
+                            }
+                            0 => path.push((child, 0)),
+                            _ => {}
+                        }
+                    }
+                    None => marks[rule] = 2,
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Expands rule references at the top of `stack` until every resulting stack starts
+    /// with a character to match or is empty.
+    fn expand(&self, mut stack: Vec<Position>, stacks: &mut Vec<Vec<Position>>) {
+        let Some(&top) = stack.last() else {
+            stacks.push(stack);
+            return;
+        };
+
+        let alternative = &self.rules[top.rule as usize][top.alternative as usize];
+        match &alternative[top.element as usize] {
+            Element::Char(_) => stacks.push(stack),
+            Element::Rule(rule) => {
+                stack.pop();
+                if top.element as usize + 1 < alternative.len() {
+                    stack.push(Position {
+                        element: top.element + 1,
+                        ..top
+                    });
+                }
+
+                for (index, alternative) in self.rules[*rule].iter().enumerate() {
+                    let mut expanded = stack.clone();
+                    if !alternative.is_empty() {
+                        expanded.push(Position {
+                            rule: *rule as u32,
+                            alternative: index as u32,
+                            element: 0,
+                        });
+                    }
+                    self.expand(expanded, stacks);
+                }
+            }
+        }
+    }
+
+    fn element(&self, position: Position) -> &Element {
+        &self.rules[position.rule as usize][position.alternative as usize]
+            [position.element as usize]
+    }
+
+    fn accept_char(&self, stacks: &[Vec<Position>], c: char) -> Vec<Vec<Position>> {
+        let mut next = Vec::new();
+        for stack in stacks {
+            let Some(&top) = stack.last() else {
+                continue;
+            };
+            let alternative = &self.rules[top.rule as usize][top.alternative as usize];
+            let Element::Char(class) = &alternative[top.element as usize] else {
+                continue;
+            };
+            if !class.matches(c) {
+                continue;
+            }
+
+            let mut stack = stack.clone();
+            stack.pop();
+            if top.element as usize + 1 < alternative.len() {
+                stack.push(Position {
+                    element: top.element + 1,
+                    ..top
+                });
+            }
+            self.expand(stack, &mut next);
+        }
+
+        next.sort();
+        next.dedup();
+        next
+    }
+}
+
+impl Constraint for Grammar {
+    type State = GrammarState;
+
+    fn start(&self) -> GrammarState {
+        let mut stacks = Vec::new();
+        for (index, alternative) in self.rules[self.root].iter().enumerate() {
+            let stack = if alternative.is_empty() {
+                Vec::new()
+            } else {
+                vec![Position {
+                    rule: self.root as u32,
+                    alternative: index as u32,
+                    element: 0,
+                }]
+            };
+            self.expand(stack, &mut stacks);
+        }
+        stacks.sort();
+        stacks.dedup();
+
+        GrammarState {
+            stacks,
+            partial: Vec::new(),
+        }
+    }
+
+    fn advance(&self, state: &GrammarState, byte: u8) -> Option<GrammarState> {
+        let mut partial = state.partial.clone();
+        partial.push(byte);
+
+        let len = match partial[0] {
+            0x00..=0x7F => 1,
+            0xC2..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF4 => 4,
+            _ => return None,
+        };
+        if partial.len() > 1 && byte & 0xC0 != 0x80 {
+            return None;
+        }
+        if partial.len() < len {
+            // Kept while some character starting with these bytes can still match
+            let (first, last) = utf8_range(&partial, len);
+            let possible = state.stacks.iter().any(|stack| {
+                stack.last().is_some_and(|top| match self.element(*top) {
+                    Element::Char(class) => class.intersects(first, last),
+                    Element::Rule(_) => false,
+                })
+            });
+            return possible.then(|| GrammarState {
+                stacks: state.stacks.clone(),
+                partial,
+            });
+        }
+
+        let c = std::str::from_utf8(&partial).ok()?.chars().next()?;
+        let stacks = self.accept_char(&state.stacks, c);
+        if stacks.is_empty() {
+            return None;
+        }
+
+        Some(GrammarState {
+            stacks,
+            partial: Vec::new(),
+        })
+    }
+
+    fn is_accepting(&self, state: &GrammarState) -> bool {
+        state.partial.is_empty() && state.stacks.iter().any(Vec::is_empty)
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    offset: usize,
+    names: Vec<String>,
+    ids: HashMap<String, usize>,
+    /// Alternatives of every rule, `None` while a rule is only referenced.
+    rules: Vec<Option<Vec<Vec<Element>>>>,
+}
+
+impl Parser {
+    fn error(&self, message: &str) -> String {
+        let line = self.chars[..self.offset.min(self.chars.len())]
+            .iter()
+            .filter(|&&c| c == '\n')
+            .count();
+
+        format!("Invalid grammar at line {}: {}", line + 1, message)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.offset).copied()
+    }
+
+    fn next(&mut self) -> Result<char, String> {
+        let c = self
+            .peek()
+            .ok_or_else(|| self.error("unexpected end of grammar"))?;
+        self.offset += 1;
+        Ok(c)
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        for c in expected.chars() {
+            if self.peek() != Some(c) {
+                return Err(self.error(&format!("expected {}", expected)));
+            }
+            self.offset += 1;
+        }
+        Ok(())
+    }
+
+    /// Skips whitespace, including newlines, and `#` comments.
+    fn skip_space(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == '#' {
+                while self.peek().is_some_and(|c| c != '\n') {
+                    self.offset += 1;
+                }
+            } else if c.is_whitespace() {
+                self.offset += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn is_name_char(c: char) -> bool {
+        c.is_ascii_alphanumeric() || c == '-' || c == '_'
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let start = self.offset;
+        while self.peek().is_some_and(Self::is_name_char) {
+            self.offset += 1;
+        }
+        if start == self.offset {
+            return Err(self.error("expected a rule name"));
+        }
+
+        Ok(self.chars[start..self.offset].iter().collect())
+    }
+
+    /// Whether a rule definition, `name ::=`, starts at the current offset.
+    fn at_definition(&self) -> bool {
+        let mut offset = self.offset;
+        while self
+            .chars
+            .get(offset)
+            .copied()
+            .is_some_and(Self::is_name_char)
+        {
+            offset += 1;
+        }
+        while self.chars.get(offset).is_some_and(|c| c.is_whitespace()) {
+            offset += 1;
+        }
+
+        offset > self.offset && self.chars[offset..].starts_with(&[':', ':', '='])
+    }
+
+    fn rule_id(&mut self, name: &str) -> usize {
+        if let Some(&id) = self.ids.get(name) {
+            return id;
+        }
+
+        let id = self.names.len();
+        self.names.push(name.to_string());
+        self.ids.insert(name.to_string(), id);
+        self.rules.push(None);
+        id
+    }
+
+    /// A rule for a group or repetition inside `parent`.
+    fn anonymous_rule(&mut self, parent: &str, alternatives: Vec<Vec<Element>>) -> usize {
+        let id = self.names.len();
+        self.names.push(format!("{}-{}", parent, id));
+        self.rules.push(Some(alternatives));
+        id
+    }
+
+    fn parse(&mut self) -> Result<(), String> {
+        self.skip_space();
+        while self.peek().is_some() {
+            let name = self.name()?;
+            self.skip_space();
+            self.expect("::=")?;
+
+            let id = self.rule_id(&name);
+            if self.rules[id].is_some() {
+                return Err(self.error(&format!("rule {} is defined twice", name)));
+            }
+            let alternatives = self.alternatives(&name)?;
+            self.rules[id] = Some(alternatives);
+            self.skip_space();
+        }
+
+        Ok(())
+    }
+
+    fn alternatives(&mut self, rule: &str) -> Result<Vec<Vec<Element>>, String> {
+        let mut alternatives = vec![self.sequence(rule)?];
+        while self.peek() == Some('|') {
+            self.offset += 1;
+            alternatives.push(self.sequence(rule)?);
+        }
+
+        Ok(alternatives)
+    }
+
+    fn sequence(&mut self, rule: &str) -> Result<Vec<Element>, String> {
+        let mut sequence = Vec::new();
+
+        loop {
+            self.skip_space();
+            let item = match self.peek() {
+                None | Some('|') | Some(')') => break,
+                Some('"') => {
+                    self.offset += 1;
+                    let mut literal = Vec::new();
+                    while self.peek() != Some('"') {
+                        literal.push(Element::Char(CharClass::single(self.char()?)));
+                    }
+                    self.offset += 1;
+                    literal
+                }
+                Some('[') => vec![Element::Char(self.class()?)],
+                Some('.') => {
+                    self.offset += 1;
+                    vec![Element::Char(CharClass {
+                        ranges: Vec::new(),
+                        negated: true,
+                    })]
+                }
+                Some('(') => {
+                    self.offset += 1;
+                    let alternatives = self.alternatives(rule)?;
+                    self.skip_space();
+                    self.expect(")")?;
+                    vec![Element::Rule(self.anonymous_rule(rule, alternatives))]
+                }
+                Some(c) if Self::is_name_char(c) => {
+                    if self.at_definition() {
+                        break;
+                    }
+                    let name = self.name()?;
+                    vec![Element::Rule(self.rule_id(&name))]
+                }
+                Some(c) => return Err(self.error(&format!("unexpected {:?}", c))),
+            };
+
+            let item = self.repetitions(rule, item)?;
+            sequence.extend(item);
+        }
+
+        Ok(sequence)
+    }
+
+    /// Applies the postfix operators following `item`.
+    fn repetitions(&mut self, rule: &str, mut item: Vec<Element>) -> Result<Vec<Element>, String> {
+        loop {
+            let (min, max) = match self.peek() {
+                Some('*') => (0, None),
+                Some('+') => (1, None),
+                Some('?') => (0, Some(1)),
+                Some('{') => {
+                    self.offset += 1;
+                    self.skip_space();
+                    let min = self.number()?;
+                    self.skip_space();
+                    let max = if self.peek() == Some(',') {
+                        self.offset += 1;
+                        self.skip_space();
+                        if self.peek() == Some('}') {
+                            None
+                        } else {
+                            Some(self.number()?)
+                        }
+                    } else {
+                        Some(min)
+                    };
+                    self.skip_space();
+                    if self.peek() != Some('}') {
+                        return Err(self.error("expected }"));
+                    }
+                    if max.is_some_and(|max| max < min) {
+                        return Err(self.error("repetition maximum is below its minimum"));
+                    }
+                    if max.unwrap_or(min) > MAX_REPETITIONS {
+                        return Err(
+                            self.error(&format!("repetition count is above {}", MAX_REPETITIONS))
+                        );
+                    }
+                    (min, max)
+                }
+                _ => return Ok(item),
+            };
+            self.offset += 1;
+            item = self.repeat(rule, item, min, max);
+        }
+    }
+
+    fn number(&mut self) -> Result<usize, String> {
+        let start = self.offset;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.offset += 1;
+        }
+
+        self.chars[start..self.offset]
+            .iter()
+            .collect::<String>()
+            .parse()
+            .map_err(|_| self.error("expected a number"))
+    }
+
+    /// `item` repeated `min` to `max` times, or any number of times more than `min` without
+    /// a maximum. Repetitions are right recursive, so matching never needs left recursion.
+    fn repeat(
+        &mut self,
+        rule: &str,
+        item: Vec<Element>,
+        min: usize,
+        max: Option<usize>,
+    ) -> Vec<Element> {
+        let mut sequence: Vec<Element> = (0..min).flat_map(|_| item.clone()).collect();
+
+        match max {
+            None => {
+                let id = self.anonymous_rule(rule, Vec::new());
+                let mut repeated = item;
+                repeated.push(Element::Rule(id));
+                self.rules[id] = Some(vec![repeated, Vec::new()]);
+                sequence.push(Element::Rule(id));
+            }
+            Some(max) if max > min => {
+                // item (item (item)?)? for the optional repetitions
+                let mut optional: Option<usize> = None;
+                for _ in min..max {
+                    let mut repeated = item.clone();
+                    repeated.extend(optional.map(Element::Rule));
+                    optional = Some(self.anonymous_rule(rule, vec![repeated, Vec::new()]));
+                }
+                sequence.extend(optional.map(Element::Rule));
+            }
+            Some(_) => {}
+        }
+
+        sequence
+    }
+
+    /// One character of a literal or class, with escapes.
+    fn char(&mut self) -> Result<char, String> {
+        let c = self.next()?;
+        if c != '\\' {
+            return Ok(c);
+        }
+
+        let escaped = self.next()?;
+        let digits = match escaped {
+            'n' => return Ok('\n'),
+            'r' => return Ok('\r'),
+            't' => return Ok('\t'),
+            'x' => 2,
+            'u' => 4,
+            'U' => 8,
+            _ => return Ok(escaped),
+        };
+
+        let mut value = 0;
+        for _ in 0..digits {
+            let digit = self.next()?;
+            value = value * 16
+                + digit
+                    .to_digit(16)
+                    .ok_or_else(|| self.error("expected a hexadecimal digit"))?;
+        }
+        char::from_u32(value).ok_or_else(|| self.error("escape is not a character"))
+    }
+
+    fn class(&mut self) -> Result<CharClass, String> {
+        self.expect("[")?;
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.offset += 1;
+        }
+
+        let mut ranges = Vec::new();
+        while self.peek() != Some(']') {
+            let start = self.char()?;
+            let end = if self.peek() == Some('-') && self.chars.get(self.offset + 1) != Some(&']') {
+                self.offset += 1;
+                self.char()?
+            } else {
+                start
+            };
+            ranges.push((start, end));
+        }
+        self.offset += 1;
+
+        Ok(CharClass { ranges, negated })
+    }
+}
@@ -0,0 +1,336 @@
+use js_sys::global;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{DedicatedWorkerGlobalScope, Headers, Request, RequestInit, RequestMode, Response};
+
+use crate::retry;
+
+pub const HUB_URL: &str = "https://huggingface.co";
+
+/// GGUF quantization levels recognized in filenames, most commonly published first.
+pub const GGUF_QUANTIZATIONS: &[&str] = &[
+    "Q4_K_M", "Q4_K_S", "Q5_K_M", "Q5_K_S", "Q6_K", "Q8_0", "Q4_0", "Q3_K_M", "Q2_K", "F16",
+    "BF16", "F32",
+];
+
+/// LFS pointer details of a file stored outside of git.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LfsInfo {
+    /// SHA-256 of the file contents.
+    pub oid: String,
+    pub size: u64,
+}
+
+/// One entry of `/api/models/{repo}/tree/{revision}`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HubFile {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub path: String,
+    /// Git blob id.
+    pub oid: String,
+    pub size: u64,
+    #[serde(default)]
+    pub lfs: Option<LfsInfo>,
+}
+
+impl HubFile {
+    /// Size of the file contents, which for LFS files is not the pointer size.
+    pub fn content_size(&self) -> u64 {
+        self.lfs.as_ref().map_or(self.size, |lfs| lfs.size)
+    }
+}
+
+/// Subset of `/api/models/{repo}/revision/{revision}?blobs=true`: the commit the revision
+/// resolves to and the content hash of every file.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RepositoryRevision {
+    pub sha: String,
+    #[serde(default)]
+    pub siblings: Vec<Sibling>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Sibling {
+    pub rfilename: String,
+    /// Git blob id.
+    #[serde(rename = "blobId")]
+    pub blob_id: Option<String>,
+    #[serde(default)]
+    pub lfs: Option<SiblingLfs>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SiblingLfs {
+    pub sha256: String,
+}
+
+impl RepositoryRevision {
+    /// The revision of `filename`, or `None` when the repository has no such file. Matches
+    /// what the resolve endpoint reports: the SHA-256 of LFS files, the blob id otherwise.
+    pub fn file(&self, filename: &str) -> Option<FileRevision> {
+        let sibling = self
+            .siblings
+            .iter()
+            .find(|sibling| sibling.rfilename == filename)?;
+        let etag = match &sibling.lfs {
+            Some(lfs) => Some(lfs.sha256.clone()),
+            None => sibling.blob_id.clone(),
+        };
+
+        Some(FileRevision {
+            etag,
+            commit: Some(self.sha.clone()),
+        })
+    }
+}
+
+/// Identifies the contents of a file at a revision.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct FileRevision {
+    /// `X-Linked-Etag` (the SHA-256 of LFS files) when exposed, the `ETag` otherwise.
+    pub etag: Option<String>,
+    /// Commit the revision resolved to.
+    pub commit: Option<String>,
+}
+
+impl FileRevision {
+    pub fn from_header_values(
+        linked_etag: Option<&str>,
+        etag: Option<&str>,
+        commit: Option<&str>,
+    ) -> Self {
+        Self {
+            etag: linked_etag.or(etag).map(normalize_etag),
+            commit: commit.map(|commit| commit.trim().to_string()),
+        }
+    }
+
+    /// Reads the revision from a response of the resolve endpoint. LFS files redirect to a
+    /// CDN and `fetch` only exposes the headers of the final response, which identify
+    /// neither the file nor the commit, so redirected responses give an empty revision.
+    pub(crate) fn from_response(resp: &Response) -> Self {
+        if resp.redirected() {
+            return Self::default();
+        }
+
+        Self::from_headers(&resp.headers())
+    }
+
+    fn from_headers(headers: &Headers) -> Self {
+        let header = |name: &str| headers.get(name).ok().flatten();
+
+        Self::from_header_values(
+            header("x-linked-etag").as_deref(),
+            header("etag").as_deref(),
+            header("x-repo-commit").as_deref(),
+        )
+    }
+}
+
+/// Strips the weak marker and quotes, so `W/"abc"` and `"abc"` compare equal.
+pub fn normalize_etag(value: &str) -> String {
+    value
+        .trim()
+        .trim_start_matches("W/")
+        .trim_matches('"')
+        .to_string()
+}
+
+/// Which of the published weight layouts to download.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum WeightsVariant {
+    Single { file: String },
+    Sharded { index: String, shards: Vec<String> },
+}
+
+impl WeightsVariant {
+    /// Every file that has to be downloaded for this variant.
+    pub fn files(&self) -> Vec<String> {
+        match self {
+            WeightsVariant::Single { file } => vec![file.clone()],
+            WeightsVariant::Sharded { index, shards } => std::iter::once(index.clone())
+                .chain(shards.iter().cloned())
+                .collect(),
+        }
+    }
+}
+
+/// The quantization level encoded in a GGUF filename such as `model.Q4_K_M.gguf`.
+pub fn gguf_quantization(path: &str) -> Option<&'static str> {
+    let name = path.rsplit('/').next()?.to_uppercase();
+    let stem = name.strip_suffix(".GGUF")?;
+
+    // Longer names first so that a shorter level never matches inside a longer one
+    let mut candidates: Vec<&&str> = GGUF_QUANTIZATIONS.iter().collect();
+    candidates.sort_by_key(|q| std::cmp::Reverse(q.len()));
+
+    candidates
+        .into_iter()
+        .find(|q| {
+            stem.split(['.', '-', '_'])
+                .collect::<Vec<_>>()
+                .windows(q.split('_').count())
+                .any(|w| w.join("_") == **q)
+        })
+        .copied()
+}
+
+/// Picks the weights to download from a repository listing.
+///
+/// Only safetensors can be loaded by `Generator`: a single `model.safetensors` first, then a
+/// sharded checkpoint. Repositories that only publish GGUF files are rejected, naming the
+/// quantization levels they have; a quantized model is made from safetensors while loading.
+pub fn select_weights(files: &[HubFile]) -> Result<WeightsVariant, String> {
+    let paths: Vec<&str> = files
+        .iter()
+        .filter(|f| f.kind == "file")
+        .map(|f| f.path.as_str())
+        .collect();
+
+    if paths.contains(&"model.safetensors") {
+        return Ok(WeightsVariant::Single {
+            file: "model.safetensors".to_string(),
+        });
+    }
+
+    if paths.contains(&"model.safetensors.index.json") {
+        let mut shards: Vec<String> = paths
+            .iter()
+            .filter(|p| {
+                p.starts_with("model-") && p.ends_with(".safetensors") && p.contains("-of-")
+            })
+            .map(|p| p.to_string())
+            .collect();
+        shards.sort();
+
+        if !shards.is_empty() {
+            return Ok(WeightsVariant::Sharded {
+                index: "model.safetensors.index.json".to_string(),
+                shards,
+            });
+        }
+    }
+
+    let gguf: Vec<&str> = GGUF_QUANTIZATIONS
+        .iter()
+        .filter(|q| paths.iter().any(|p| gguf_quantization(p) == Some(**q)))
+        .copied()
+        .collect();
+    if gguf.is_empty() {
+        Err("No safetensors weights".to_string())
+    } else {
+        Err(format!(
+            "Only GGUF weights ({}), which cannot be loaded; use a repository with safetensors \
+             and quantize them while loading instead",
+            gguf.join(", ")
+        ))
+    }
+}
+
+pub(crate) async fn fetch_json<T: serde::de::DeserializeOwned>(url: &str) -> Result<T, JsValue> {
+    let opts = RequestInit::new();
+    opts.set_method("GET");
+    opts.set_mode(RequestMode::Cors);
+
+    let request = Request::new_with_str_and_init(url, &opts)?;
+    let scope = global().dyn_into::<DedicatedWorkerGlobalScope>()?;
+    let resp: Response = JsFuture::from(scope.fetch_with_request(&request))
+        .await?
+        .dyn_into()?;
+
+    if !resp.ok() {
+        return Err(JsValue::from_str(&format!(
+            "Failed to fetch {}: {}",
+            url,
+            resp.status()
+        )));
+    }
+
+    let text = JsFuture::from(resp.text()?)
+        .await?
+        .as_string()
+        .unwrap_or_default();
+
+    serde_json::from_str(&text).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+/// Download URL of a file at `revision`.
+pub fn resolve_url(repository: &str, revision: &str, filename: &str) -> String {
+    format!(
+        "{}/{}/resolve/{}/{}",
+        HUB_URL, repository, revision, filename
+    )
+}
+
+/// Fetches bytes `start..end` of a file and the total size of the file from the
+/// `Content-Range` of the response. Fails if the server ignores the range.
+pub(crate) async fn fetch_range(
+    url: &str,
+    start: u64,
+    end: u64,
+) -> Result<(Vec<u8>, Option<u64>), JsValue> {
+    let headers = Headers::new()?;
+    headers.set(
+        "Range",
+        &format!("bytes={}-{}", start, end.saturating_sub(1)),
+    )?;
+
+    let opts = RequestInit::new();
+    opts.set_method("GET");
+    opts.set_mode(RequestMode::Cors);
+    opts.set_headers(&headers);
+
+    let request = Request::new_with_str_and_init(url, &opts)?;
+    let scope = global().dyn_into::<DedicatedWorkerGlobalScope>()?;
+    let resp: Response = JsFuture::from(scope.fetch_with_request(&request))
+        .await?
+        .dyn_into()?;
+
+    if resp.status() != 206 {
+        return Err(JsValue::from_str(&format!(
+            "Range request to {} failed: {}",
+            url,
+            resp.status()
+        )));
+    }
+
+    let total = match resp.headers().get("content-range") {
+        Ok(Some(value)) => retry::parse_content_range_total(&value).map(|total| total as u64),
+        _ => None,
+    };
+    let body = JsFuture::from(resp.array_buffer()?).await?;
+
+    Ok((js_sys::Uint8Array::new(&body).to_vec(), total))
+}
+
+/// Lists every file of `repository` at `revision`, including nested directories.
+pub async fn list_files(repository: &str, revision: &str) -> Result<Vec<HubFile>, JsValue> {
+    fetch_json(&format!(
+        "{}/api/models/{}/tree/{}?recursive=true",
+        HUB_URL, repository, revision
+    ))
+    .await
+}
+
+/// Resolves `revision` to a commit and lists the content hash of every file, in one request
+/// instead of one per file.
+pub(crate) async fn repository_revision(
+    repository: &str,
+    revision: &str,
+) -> Result<RepositoryRevision, JsValue> {
+    fetch_json(&format!(
+        "{}/api/models/{}/revision/{}?blobs=true",
+        HUB_URL, repository, revision
+    ))
+    .await
+}
+
+/// Converts a serializable value into a plain JS object.
+pub(crate) fn to_js<T: Serialize>(value: &T) -> Result<JsValue, JsValue> {
+    let json = serde_json::to_string(value).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    js_sys::JSON::parse(&json)
+}
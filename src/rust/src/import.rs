@@ -0,0 +1,307 @@
+use candle_transformers::models::llama::LlamaConfig;
+use js_sys::{Reflect, Uint8Array};
+use tokenizers::Tokenizer;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Blob, File, IdbTransactionMode};
+
+use crate::cache::CacheEntry;
+use crate::downloader::{Downloader, METADATA_STORE_NAME, STORE_NAME};
+use crate::repository::{repository_key, RepositoryDownload, RepositoryManifest};
+use crate::tensor_header;
+
+#[wasm_bindgen(typescript_custom_section)]
+const TS_APPEND_CONTENT: &'static str = r#"
+export interface LocalImport {
+  on(event: 'file_complete', callback: (filename: string) => void): LocalImport;
+  on(event: 'skip', callback: (filename: string) => void): LocalImport;
+  on(event: 'complete', callback: (repository: string) => void): LocalImport;
+}
+"#;
+
+/// Owner of the synthetic repository ids given to imported models.
+pub const LOCAL_OWNER: &str = "local";
+
+/// Revision recorded for imported files, which have no upstream commit.
+pub const LOCAL_REVISION: &str = "local";
+
+/// Repository id for a model imported under `name`. Ids always have exactly one `/`, so any
+/// other slash in the name is replaced.
+pub fn local_repository_id(name: &str) -> String {
+    let name = name.trim().replace('/', "-");
+    let name = if name.is_empty() { "model" } else { &name };
+
+    format!("{}/{}", LOCAL_OWNER, name)
+}
+
+/// How an imported file is checked before it is stored.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImportKind {
+    Weights,
+    Tokenizer,
+    Config,
+    Json,
+}
+
+impl ImportKind {
+    /// Files that are none of these, such as a README, are skipped.
+    pub fn of(filename: &str) -> Option<ImportKind> {
+        let name = filename.rsplit('/').next().unwrap_or(filename);
+
+        match name {
+            "tokenizer.json" => Some(ImportKind::Tokenizer),
+            "config.json" => Some(ImportKind::Config),
+            _ if name.ends_with(".safetensors") => Some(ImportKind::Weights),
+            _ if name.ends_with(".json") => Some(ImportKind::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Path of a picked file relative to the picked directory, or its name when a single file
+/// was picked.
+pub fn relative_path(name: &str, webkit_relative_path: &str) -> String {
+    match webkit_relative_path.split_once('/') {
+        Some((_, path)) if !path.is_empty() => path.to_string(),
+        _ => name.to_string(),
+    }
+}
+
+/// A file that will be stored, by its position among the added files.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlannedFile {
+    pub index: usize,
+    pub filename: String,
+    pub kind: ImportKind,
+}
+
+/// The files to store and their names in the repository, or an error naming what is
+/// missing. A single weights file is stored as `model.safetensors`, which is what loaders
+/// look up.
+pub fn plan_import(filenames: &[String]) -> Result<Vec<PlannedFile>, String> {
+    let mut files: Vec<PlannedFile> = filenames
+        .iter()
+        .enumerate()
+        .filter_map(|(index, filename)| {
+            ImportKind::of(filename).map(|kind| PlannedFile {
+                index,
+                filename: filename.clone(),
+                kind,
+            })
+        })
+        .collect();
+
+    let weights: Vec<usize> = (0..files.len())
+        .filter(|index| files[*index].kind == ImportKind::Weights)
+        .collect();
+
+    if weights.is_empty() {
+        return Err("No .safetensors weights among the imported files".to_string());
+    }
+
+    for (required, kind) in [
+        ("tokenizer.json", ImportKind::Tokenizer),
+        ("config.json", ImportKind::Config),
+    ] {
+        if !files.iter().any(|file| file.kind == kind) {
+            return Err(format!("{} is missing from the imported files", required));
+        }
+    }
+
+    if let [index] = weights[..] {
+        files[index].filename = "model.safetensors".to_string();
+    }
+
+    Ok(files)
+}
+
+/// Stores model files the user already has, e.g. from a file picker or drag and drop, in
+/// the model cache under a `local/...` repository, so they load like downloaded models.
+#[wasm_bindgen]
+pub struct LocalImport {
+    name: Option<String>,
+    files: Vec<(String, Blob)>,
+
+    file_complete_callback: Option<js_sys::Function>,
+    skip_callback: Option<js_sys::Function>,
+    complete_callback: Option<js_sys::Function>,
+}
+
+#[wasm_bindgen]
+impl LocalImport {
+    /// `name` defaults to the picked directory, or `model` for loose files.
+    #[wasm_bindgen(constructor)]
+    pub fn new(name: Option<String>) -> Self {
+        Self {
+            name,
+            files: Vec::new(),
+            file_complete_callback: None,
+            skip_callback: None,
+            complete_callback: None,
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn on(&mut self, event: &str, callback: Option<js_sys::Function>) {
+        if let Some(cb) = callback {
+            match event {
+                "file_complete" => self.file_complete_callback = Some(cb),
+                "skip" => self.skip_callback = Some(cb),
+                "complete" => self.complete_callback = Some(cb),
+                _ => {}
+            }
+        }
+    }
+
+    /// Adds a picked file. Files of a picked directory keep their path inside it.
+    pub fn add_file(&mut self, file: &File) {
+        let webkit_relative_path = Reflect::get(file, &JsValue::from_str("webkitRelativePath"))
+            .ok()
+            .and_then(|path| path.as_string())
+            .unwrap_or_default();
+
+        if self.name.is_none() {
+            if let Some((directory, _)) = webkit_relative_path.split_once('/') {
+                self.name = Some(directory.to_string());
+            }
+        }
+
+        let filename = relative_path(&file.name(), &webkit_relative_path);
+        self.files.push((filename, file.clone().into()));
+    }
+
+    pub fn add_blob(&mut self, filename: &str, blob: &Blob) {
+        self.files.push((filename.to_string(), blob.clone()));
+    }
+
+    /// The repository id the files are stored under.
+    #[wasm_bindgen(getter)]
+    pub fn repository(&self) -> String {
+        local_repository_id(self.name.as_deref().unwrap_or("model"))
+    }
+
+    /// Validates and stores every file, then resolves to the repository id. Nothing is
+    /// stored when a file is invalid or a required one is missing. The manifest is only
+    /// written once all files are stored.
+    pub async fn start(&self) -> Result<String, JsValue> {
+        let repository = self.repository();
+        let filenames: Vec<String> = self.files.iter().map(|(name, _)| name.clone()).collect();
+        let plan = plan_import(&filenames).map_err(|e| JsValue::from_str(&e))?;
+
+        for (index, (filename, _)) in self.files.iter().enumerate() {
+            if !plan.iter().any(|file| file.index == index) {
+                if let Some(cb) = self.skip_callback.as_ref() {
+                    cb.call1(&JsValue::NULL, &JsValue::from_str(filename))?;
+                }
+            }
+        }
+
+        // Everything is validated before anything is stored
+        for file in &plan {
+            let (filename, blob) = &self.files[file.index];
+            Self::validate(file.kind, blob)
+                .await
+                .map_err(|e| JsValue::from_str(&format!("{}: {}", filename, e)))?;
+        }
+
+        let downloader = Downloader::new(&repository);
+        let db = Downloader::open_db().await?;
+
+        for file in &plan {
+            let filename = &file.filename;
+            let blob = &self.files[file.index].1;
+            let key = repository_key(&repository, filename);
+            downloader.ensure_capacity(&key, blob.size() as u64).await?;
+
+            let content = read_blob(blob).await?;
+            let entry = CacheEntry::new(
+                &key,
+                content.length() as u64,
+                &repository,
+                LOCAL_REVISION,
+                js_sys::Date::now(),
+            );
+
+            let transaction = Downloader::transaction(
+                &db,
+                &[STORE_NAME, METADATA_STORE_NAME],
+                IdbTransactionMode::Readwrite,
+            )?;
+            let request = transaction
+                .object_store(STORE_NAME)?
+                .put_with_key(&content, &JsValue::from_str(&key))?;
+            Downloader::put_entry(&transaction, &entry)?;
+            Downloader::idbrequest_to_result::<JsValue>(&request).await?;
+
+            if let Some(cb) = self.file_complete_callback.as_ref() {
+                cb.call1(&JsValue::NULL, &JsValue::from_str(filename))?;
+            }
+        }
+
+        let manifest = RepositoryManifest {
+            repository: repository.clone(),
+            revision: LOCAL_REVISION.to_string(),
+            files: plan.into_iter().map(|file| file.filename).collect(),
+            completed_at: js_sys::Date::now(),
+        };
+        RepositoryDownload::put_manifest(&manifest).await?;
+
+        if let Some(cb) = self.complete_callback.as_ref() {
+            cb.call1(&JsValue::NULL, &JsValue::from_str(&repository))?;
+        }
+
+        Ok(repository)
+    }
+}
+
+impl LocalImport {
+    async fn validate(kind: ImportKind, blob: &Blob) -> Result<(), String> {
+        let message = |e: JsValue| e.as_string().unwrap_or_else(|| format!("{:?}", e));
+
+        match kind {
+            // Only the header is read, the weights may be larger than the wasm heap
+            ImportKind::Weights => {
+                let size = blob.size() as u64;
+                let prefix = read_range(blob, 0, tensor_header::HEADER_PREFIX_LEN)
+                    .await
+                    .map_err(message)?;
+                let header_len = tensor_header::header_len(&prefix)?;
+
+                let end = tensor_header::HEADER_PREFIX_LEN + header_len;
+                if end > size {
+                    return Err("Safetensors header extends past the end of the file".into());
+                }
+
+                let header = read_range(blob, tensor_header::HEADER_PREFIX_LEN, end)
+                    .await
+                    .map_err(message)?;
+                tensor_header::parse_header(&header, size)?;
+            }
+            ImportKind::Tokenizer => {
+                let bytes = read_blob(blob).await.map_err(message)?.to_vec();
+                Tokenizer::from_bytes(bytes).map_err(|e| e.to_string())?;
+            }
+            ImportKind::Config => {
+                let bytes = read_blob(blob).await.map_err(message)?.to_vec();
+                serde_json::from_slice::<LlamaConfig>(&bytes)
+                    .map_err(|e| format!("Not a Llama config: {}", e))?;
+            }
+            ImportKind::Json => {
+                let bytes = read_blob(blob).await.map_err(message)?.to_vec();
+                serde_json::from_slice::<serde_json::Value>(&bytes).map_err(|e| e.to_string())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+async fn read_blob(blob: &Blob) -> Result<Uint8Array, JsValue> {
+    let buffer = JsFuture::from(blob.array_buffer()).await?;
+    Ok(Uint8Array::new(&buffer))
+}
+
+async fn read_range(blob: &Blob, start: u64, end: u64) -> Result<Vec<u8>, JsValue> {
+    let slice = blob.slice_with_f64_and_f64(start as f64, end as f64)?;
+    Ok(read_blob(&slice).await?.to_vec())
+}
@@ -0,0 +1,316 @@
+use std::collections::BTreeMap;
+
+use serde_json::{Map, Value};
+
+use crate::grammar::MAX_REPETITIONS;
+
+/// Rules shared by every converted schema.
+const PRIMITIVES: &[(&str, &str)] = &[
+    ("space", r#"" "?"#),
+    (
+        "char",
+        r#"[^"\\\x7F\x00-\x1F] | "\\" (["\\/bfnrt] | "u" [0-9a-fA-F]{4})"#,
+    ),
+    ("string", r#""\"" char* "\"" space"#),
+    ("integer", r#""-"? ("0" | [1-9] [0-9]{0,15}) space"#),
+    (
+        "number",
+        r#""-"? ("0" | [1-9] [0-9]{0,15}) ("." [0-9]+)? ([eE] [-+]? [0-9]+)? space"#,
+    ),
+    ("boolean", r#"("true" | "false") space"#),
+    ("null", r#""null" space"#),
+    (
+        "value",
+        r#"object | array | string | number | boolean | null"#,
+    ),
+    (
+        "object",
+        r#""{" space (string ":" space value ("," space string ":" space value)*)? "}" space"#,
+    ),
+    (
+        "array",
+        r#""[" space (value ("," space value)*)? "]" space"#,
+    ),
+];
+
+/// Converts a JSON Schema to a GBNF grammar matching the JSON documents it describes.
+///
+/// Supported are `type` (also as a list), `properties` with `required`, `items` with
+/// `minItems` and `maxItems`, `minLength` and `maxLength`, `enum`, `const`, `anyOf`, `oneOf`
+/// and local `$ref`s to `$defs` or `definitions`. Objects take no additional properties and
+/// list theirs in alphabetical order; `format` is ignored and `pattern` is rejected.
+pub fn json_schema_to_gbnf(schema: &Value) -> Result<String, String> {
+    let mut converter = Converter {
+        root: schema,
+        rules: BTreeMap::new(),
+    };
+    converter.visit(schema, "root")?;
+
+    let mut grammar = String::new();
+    for (name, body) in &converter.rules {
+        grammar.push_str(&format!("{} ::= {}\n", name, body));
+    }
+    for (name, body) in PRIMITIVES {
+        if !converter.rules.contains_key(*name) {
+            grammar.push_str(&format!("{} ::= {}\n", name, body));
+        }
+    }
+
+    Ok(grammar)
+}
+
+/// A GBNF literal matching `text` exactly.
+fn literal(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// A JSON value as a GBNF literal, followed by optional space.
+fn json_literal(value: &Value) -> String {
+    format!("{} space", literal(&value.to_string()))
+}
+
+struct Converter<'a> {
+    root: &'a Value,
+    rules: BTreeMap<String, String>,
+}
+
+impl<'a> Converter<'a> {
+    /// Adds a rule named after `name` and returns the name it got, which differs when a
+    /// different rule already has it.
+    fn add_rule(&mut self, name: &str, body: String) -> String {
+        let name: String = name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+            .collect();
+
+        let mut unique = name.clone();
+        let mut index = 1;
+        while let Some(existing) = self.rules.get(&unique) {
+            if *existing == body {
+                return unique;
+            }
+            unique = format!("{}{}", name, index);
+            index += 1;
+        }
+
+        self.rules.insert(unique.clone(), body);
+        unique
+    }
+
+    /// Converts `schema` into a rule named after `name` and returns a reference to it.
+    fn visit(&mut self, schema: &'a Value, name: &str) -> Result<String, String> {
+        let body = self.body(schema, name)?;
+        Ok(self.add_rule(name, body))
+    }
+
+    fn body(&mut self, schema: &'a Value, name: &str) -> Result<String, String> {
+        let schema = match schema {
+            Value::Bool(true) => return Ok("value".to_string()),
+            Value::Bool(false) => return Err(format!("Schema {} matches nothing", name)),
+            Value::Object(schema) => schema,
+            _ => return Err(format!("Schema {} is not an object", name)),
+        };
+
+        if let Some(reference) = schema.get("$ref") {
+            return self.reference(reference);
+        }
+        if let Some(value) = schema.get("const") {
+            return Ok(json_literal(value));
+        }
+        if let Some(values) = schema.get("enum") {
+            let values = values
+                .as_array()
+                .ok_or_else(|| format!("enum of {} is not an array", name))?;
+            return Ok(values
+                .iter()
+                .map(json_literal)
+                .collect::<Vec<_>>()
+                .join(" | "));
+        }
+        if let Some(schemas) = schema.get("anyOf").or_else(|| schema.get("oneOf")) {
+            let schemas = schemas
+                .as_array()
+                .ok_or_else(|| format!("anyOf of {} is not an array", name))?;
+            return self.alternatives(schemas.iter(), name);
+        }
+        if schema.contains_key("pattern") {
+            return Err(format!("pattern of {} is not supported", name));
+        }
+
+        match schema.get("type") {
+            None => Ok("value".to_string()),
+            Some(Value::String(kind)) => self.typed(schema, kind, name),
+            Some(Value::Array(kinds)) => {
+                let mut bodies = Vec::with_capacity(kinds.len());
+                for kind in kinds {
+                    let kind = kind
+                        .as_str()
+                        .ok_or_else(|| format!("type of {} is not a string", name))?;
+                    let body = self.typed(schema, kind, &format!("{}-{}", name, kind))?;
+                    bodies.push(format!("({})", body));
+                }
+                Ok(bodies.join(" | "))
+            }
+            Some(_) => Err(format!("type of {} is not a string", name)),
+        }
+    }
+
+    fn alternatives(
+        &mut self,
+        schemas: impl Iterator<Item = &'a Value>,
+        name: &str,
+    ) -> Result<String, String> {
+        let mut rules = Vec::new();
+        for (index, schema) in schemas.enumerate() {
+            rules.push(self.visit(schema, &format!("{}-{}", name, index))?);
+        }
+
+        Ok(rules.join(" | "))
+    }
+
+    fn reference(&mut self, reference: &Value) -> Result<String, String> {
+        let reference = reference
+            .as_str()
+            .ok_or_else(|| "$ref is not a string".to_string())?;
+        let definition = reference
+            .strip_prefix("#/$defs/")
+            .or_else(|| reference.strip_prefix("#/definitions/"))
+            .ok_or_else(|| format!("Only local $refs are supported, not {}", reference))?;
+
+        let name = format!("ref-{}", definition);
+        let sanitized: String = name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+            .collect();
+        if self.rules.contains_key(&sanitized) {
+            return Ok(sanitized);
+        }
+
+        let schema = ["$defs", "definitions"]
+            .iter()
+            .find_map(|key| self.root.get(key)?.get(definition))
+            .ok_or_else(|| format!("$ref {} is not defined", reference))?;
+
+        // Reserved first, so recursive schemas refer to the rule being built
+        self.rules.insert(sanitized.clone(), String::new());
+        let body = self.body(schema, &sanitized)?;
+        self.rules.insert(sanitized.clone(), body);
+
+        Ok(sanitized)
+    }
+
+    fn typed(
+        &mut self,
+        schema: &'a Map<String, Value>,
+        kind: &str,
+        name: &str,
+    ) -> Result<String, String> {
+        match kind {
+            "object" => self.object(schema, name),
+            "array" => self.array(schema, name),
+            "string" => {
+                let min = repetition_field(schema, "minLength")?.unwrap_or(0);
+                match (min, repetition_field(schema, "maxLength")?) {
+                    (0, None) => Ok("string".to_string()),
+                    (min, None) => Ok(format!(r#""\"" char{{{},}} "\"" space"#, min)),
+                    (min, Some(max)) => Ok(format!(r#""\"" char{{{},{}}} "\"" space"#, min, max)),
+                }
+            }
+            "integer" | "number" | "boolean" | "null" => Ok(kind.to_string()),
+            _ => Err(format!("Unsupported type {} of {}", kind, name)),
+        }
+    }
+
+    fn object(&mut self, schema: &'a Map<String, Value>, name: &str) -> Result<String, String> {
+        let Some(properties) = schema.get("properties") else {
+            return Ok("object".to_string());
+        };
+        let properties = properties
+            .as_object()
+            .ok_or_else(|| format!("properties of {} is not an object", name))?;
+        let required: Vec<&str> = schema
+            .get("required")
+            .and_then(Value::as_array)
+            .map(|required| required.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+
+        let mut required_pairs = Vec::new();
+        let mut optional_pairs = Vec::new();
+        for (key, property) in properties {
+            let value = self.visit(property, &format!("{}-{}", name, key))?;
+            let pair = format!(
+                "{} \":\" space {}",
+                literal(&Value::String(key.clone()).to_string()),
+                value
+            );
+            if required.contains(&key.as_str()) {
+                required_pairs.push(pair);
+            } else {
+                optional_pairs.push(pair);
+            }
+        }
+
+        let mut body = r#""{" space"#.to_string();
+        if required_pairs.is_empty() {
+            // Any property may come first, the ones after it keep their order
+            if !optional_pairs.is_empty() {
+                let firsts: Vec<String> = (0..optional_pairs.len())
+                    .map(|first| {
+                        let mut sequence = optional_pairs[first].clone();
+                        for pair in &optional_pairs[first + 1..] {
+                            sequence.push_str(&format!(r#" ("," space {})?"#, pair));
+                        }
+                        sequence
+                    })
+                    .collect();
+                body.push_str(&format!(" ({})?", firsts.join(" | ")));
+            }
+        } else {
+            body.push_str(&format!(" {}", required_pairs.join(r#" "," space "#)));
+            for pair in &optional_pairs {
+                body.push_str(&format!(r#" ("," space {})?"#, pair));
+            }
+        }
+        body.push_str(r#" "}" space"#);
+
+        Ok(body)
+    }
+
+    fn array(&mut self, schema: &'a Map<String, Value>, name: &str) -> Result<String, String> {
+        let item = match schema.get("items") {
+            Some(items) => self.visit(items, &format!("{}-item", name))?,
+            None => "value".to_string(),
+        };
+        let min = repetition_field(schema, "minItems")?.unwrap_or(0);
+        let max = repetition_field(schema, "maxItems")?;
+
+        let rest = match (min.saturating_sub(1), max) {
+            (min, None) => format!("{{{},}}", min),
+            (_, Some(0)) => return Ok(r#""[" space "]" space"#.to_string()),
+            (min, Some(max)) => format!("{{{},{}}}", min, max - 1),
+        };
+        let items = format!(r#"{} ("," space {}){}"#, item, item, rest);
+        let items = if min == 0 {
+            format!("({})?", items)
+        } else {
+            items
+        };
+
+        Ok(format!(r#""[" space {} "]" space"#, items))
+    }
+}
+
+/// A length or item count, which the grammar repeats a rule for, so it is capped.
+fn repetition_field(schema: &Map<String, Value>, key: &str) -> Result<Option<usize>, String> {
+    let Some(value) = schema.get(key) else {
+        return Ok(None);
+    };
+    let value = value
+        .as_u64()
+        .ok_or_else(|| format!("{} is not a non-negative integer", key))?;
+    if value > MAX_REPETITIONS as u64 {
+        return Err(format!("{} {} is above {}", key, value, MAX_REPETITIONS));
+    }
+
+    Ok(Some(value as usize))
+}
@@ -1,3 +1,27 @@
+pub mod archive;
+pub mod beam;
+pub mod buffer;
+pub mod cache;
+pub mod constraint;
+pub mod coordination;
 pub mod downloader;
+pub mod generation_config;
 pub mod generator;
+pub mod grammar;
+pub mod hub;
+pub mod import;
+pub mod json_schema;
+pub mod loader;
+pub mod logits;
+pub mod memory;
+pub mod migrations;
+pub mod pattern;
+pub mod penalties;
+pub mod progress;
+pub mod quantize;
+pub mod repository;
+pub mod retry;
+pub mod sampling;
+pub mod tensor_header;
 pub mod token_output_stream;
+pub mod transcode;
@@ -0,0 +1,448 @@
+use std::collections::HashMap;
+
+use candle_core::quantized::GgmlDType;
+use candle_core::{DType, Device, Tensor};
+use candle_transformers::models::llama::LlamaConfig;
+use js_sys::Uint8Array;
+use serde::Deserialize;
+use wasm_bindgen::prelude::*;
+
+use crate::downloader::Downloader;
+use crate::memory;
+use crate::quantize::{self, Quantizer};
+use crate::repository::repository_key;
+use crate::tensor_header::{self, TensorInfo, HEADER_PREFIX_LEN};
+
+/// Bytes copied from JS into the wasm heap at a time when loading cached weights.
+pub const LOAD_CHUNK_LEN: usize = 16 * 1024 * 1024;
+
+/// Lists the shards of a checkpoint that is split over several safetensors files.
+pub const SHARD_INDEX: &str = "model.safetensors.index.json";
+
+#[derive(Deserialize)]
+struct ShardIndex {
+    weight_map: HashMap<String, String>,
+}
+
+/// The files the `weight_map` of a [`SHARD_INDEX`] spreads the tensors over, in order.
+pub fn shard_files(index: &[u8]) -> Result<Vec<String>, String> {
+    let index: ShardIndex =
+        serde_json::from_slice(index).map_err(|e| format!("Invalid {}: {}", SHARD_INDEX, e))?;
+
+    let mut files: Vec<String> = index.weight_map.into_values().collect();
+    files.sort();
+    files.dedup();
+
+    if files.is_empty() {
+        return Err(format!("{} lists no shards", SHARD_INDEX));
+    }
+
+    Ok(files)
+}
+
+/// The candle dtype of a safetensors dtype, or `None` for dtypes candle cannot hold, such
+/// as `I16` and `I32`.
+pub fn candle_dtype(dtype: &str) -> Option<DType> {
+    match dtype {
+        "U8" => Some(DType::U8),
+        "U32" => Some(DType::U32),
+        "I64" => Some(DType::I64),
+        "BF16" => Some(DType::BF16),
+        "F16" => Some(DType::F16),
+        "F32" => Some(DType::F32),
+        "F64" => Some(DType::F64),
+        _ => None,
+    }
+}
+
+/// The safetensors dtype of a candle dtype.
+pub fn safetensors_dtype(dtype: DType) -> Option<&'static str> {
+    match dtype {
+        DType::U8 => Some("U8"),
+        DType::U32 => Some("U32"),
+        DType::I64 => Some("I64"),
+        DType::BF16 => Some("BF16"),
+        DType::F16 => Some("F16"),
+        DType::F32 => Some("F32"),
+        DType::F64 => Some("F64"),
+    }
+}
+
+/// Builds tensors from a safetensors file that arrives in chunks. Each tensor is converted
+/// to the target dtype as soon as its bytes are complete and its raw bytes are released, so
+/// the file is never held in the wasm heap as a whole and peak memory stays close to the
+/// size of the loaded model.
+#[wasm_bindgen]
+pub struct WeightsLoader {
+    dtype: DType,
+    device: Device,
+    file_size: u64,
+    received: u64,
+    /// Start of the data section, known once the header is parsed.
+    data_start: Option<u64>,
+    /// Bytes of the header, then of the tensor being assembled.
+    pending: Vec<u8>,
+    /// Tensors still to come, the next one last.
+    remaining: Vec<TensorInfo>,
+    tensors: HashMap<String, Tensor>,
+    /// Takes the tensors instead of `tensors` when weights are quantized while loading.
+    quantizer: Option<Quantizer>,
+}
+
+#[wasm_bindgen]
+impl WeightsLoader {
+    /// A loader for a file of `file_size` bytes whose floating point tensors are converted
+    /// to `dtype`, `f16` unless given.
+    #[wasm_bindgen(constructor)]
+    pub fn new(file_size: f64, dtype: Option<String>) -> Result<WeightsLoader, JsValue> {
+        let dtype = memory::parse_dtype(dtype.as_deref()).map_err(|e| JsValue::from_str(&e))?;
+        let device = Device::cuda_if_available(0).unwrap_or(Device::Cpu);
+
+        Ok(Self::for_device(file_size as u64, dtype, &device))
+    }
+
+    /// Quantizes the linear layers to `q8_0` or `q4_k` while loading, for the Llama model
+    /// described by `config_bytes`. Must be called before any bytes are pushed.
+    pub fn set_quantization(
+        &mut self,
+        quantization: &str,
+        config_bytes: &[u8],
+    ) -> Result<(), JsValue> {
+        let error = |e: String| JsValue::from_str(&e);
+
+        if self.received > 0 {
+            return Err(error("Quantization must be set before loading".to_string()));
+        }
+
+        let dtype = quantize::parse_quantization(Some(quantization))
+            .map_err(error)?
+            .unwrap_or(GgmlDType::Q8_0);
+        let config: LlamaConfig = serde_json::from_slice(config_bytes)
+            .map_err(|e| error(format!("Invalid config.json: {}", e)))?;
+        self.quantizer = Some(Quantizer::new(&config, dtype).map_err(error)?);
+
+        Ok(())
+    }
+
+    /// Takes the next bytes of the file, converting every tensor they complete.
+    pub fn push(&mut self, chunk: &[u8]) -> Result<(), JsValue> {
+        self.push_bytes(chunk).map_err(|e| JsValue::from_str(&e))
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn received(&self) -> f64 {
+        self.received as f64
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn tensor_count(&self) -> usize {
+        match self.quantizer.as_ref() {
+            Some(quantizer) => quantizer.tensor_count(),
+            None => self.tensors.len(),
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn is_complete(&self) -> bool {
+        self.data_start.is_some() && self.remaining.is_empty() && self.received == self.file_size
+    }
+
+    /// Loads a cached weights file, `model.safetensors` unless given, or else every shard
+    /// listed in `model.safetensors.index.json`. One file at a time stays in JS memory and is
+    /// copied into the wasm heap one chunk at a time. With `quantization`, the cached
+    /// `config.json` describes the model to quantize.
+    pub async fn from_cache(
+        repository: &str,
+        filename: Option<String>,
+        dtype: Option<String>,
+        quantization: Option<String>,
+    ) -> Result<WeightsLoader, JsValue> {
+        let read = |filename: &str| {
+            let key = repository_key(repository, filename);
+            let filename = filename.to_string();
+            async move {
+                Downloader::get(&key)
+                    .await
+                    .ok_or_else(|| JsValue::from_str(&format!("{} is not cached", filename)))
+            }
+        };
+
+        let (data, shards) = match filename {
+            Some(filename) => (read(&filename).await?, Vec::new()),
+            None => match Downloader::get(&repository_key(repository, "model.safetensors")).await {
+                Some(data) => (data, Vec::new()),
+                None => {
+                    let index = read(SHARD_INDEX).await?.to_vec();
+                    let shards = shard_files(&index).map_err(|e| JsValue::from_str(&e))?;
+                    (read(&shards[0]).await?, shards[1..].to_vec())
+                }
+            },
+        };
+        let mut loader = Self::new(data.length() as f64, dtype)?;
+
+        if let Some(quantization) = quantization {
+            let config = read("config.json").await?.to_vec();
+            loader.set_quantization(&quantization, &config)?;
+        }
+
+        loader.push_array(&data)?;
+        drop(data);
+
+        for shard in shards {
+            let data = read(&shard).await?;
+            loader
+                .start_file(data.length() as u64)
+                .map_err(|e| JsValue::from_str(&e))?;
+            loader.push_array(&data)?;
+        }
+
+        Ok(loader)
+    }
+}
+
+impl WeightsLoader {
+    pub fn for_device(file_size: u64, dtype: DType, device: &Device) -> Self {
+        Self {
+            dtype,
+            device: device.clone(),
+            file_size,
+            received: 0,
+            data_start: None,
+            pending: Vec::new(),
+            remaining: Vec::new(),
+            tensors: HashMap::new(),
+            quantizer: None,
+        }
+    }
+
+    /// Quantizes the tensors with `quantizer` as they complete instead of converting them.
+    pub fn with_quantizer(mut self, quantizer: Quantizer) -> Self {
+        self.quantizer = Some(quantizer);
+        self
+    }
+
+    pub fn is_quantized(&self) -> bool {
+        self.quantizer.is_some()
+    }
+
+    /// The type linear layers are quantized to, if they are.
+    pub fn quantization(&self) -> Option<GgmlDType> {
+        self.quantizer.as_ref().map(Quantizer::dtype)
+    }
+
+    pub fn dtype(&self) -> DType {
+        self.dtype
+    }
+
+    /// Pushes a JS array in chunks of [`LOAD_CHUNK_LEN`] through one reused buffer.
+    pub fn push_array(&mut self, data: &Uint8Array) -> Result<(), JsValue> {
+        let length = data.length();
+        let mut chunk = vec![0; (length as usize).min(LOAD_CHUNK_LEN)];
+        let mut offset = 0;
+
+        while offset < length {
+            let end = length.min(offset + LOAD_CHUNK_LEN as u32);
+            let chunk = &mut chunk[..(end - offset) as usize];
+            data.subarray(offset, end).copy_to(chunk);
+            self.push(chunk)?;
+            offset = end;
+        }
+
+        Ok(())
+    }
+
+    pub fn push_bytes(&mut self, mut chunk: &[u8]) -> Result<(), String> {
+        if self.received + chunk.len() as u64 > self.file_size {
+            return Err(format!(
+                "Received more than the {} bytes of the weights file",
+                self.file_size
+            ));
+        }
+
+        while !chunk.is_empty() {
+            let taken = match self.data_start {
+                None => self.take_header(chunk)?,
+                Some(data_start) => self.take_data(chunk, self.received - data_start)?,
+            };
+
+            chunk = &chunk[taken..];
+            self.received += taken as u64;
+        }
+
+        Ok(())
+    }
+
+    /// Continues with the next shard of a checkpoint, a file of `file_size` bytes, keeping
+    /// the tensors loaded so far. Fails unless the current file was received completely.
+    pub fn start_file(&mut self, file_size: u64) -> Result<(), String> {
+        self.check_complete()?;
+
+        self.file_size = file_size;
+        self.received = 0;
+        self.data_start = None;
+        self.pending = Vec::new();
+
+        Ok(())
+    }
+
+    /// The loaded tensors, or an error when the file has not been received completely.
+    pub fn finish(self) -> Result<HashMap<String, Tensor>, String> {
+        self.check_complete()?;
+
+        if self.quantizer.is_some() {
+            return Err("Weights were quantized, use finish_quantized".to_string());
+        }
+
+        Ok(self.tensors)
+    }
+
+    /// The quantizer holding every quantized tensor, ready to build the model.
+    pub fn finish_quantized(self) -> Result<Quantizer, String> {
+        self.check_complete()?;
+        self.quantizer
+            .ok_or_else(|| "Weights were not quantized".to_string())
+    }
+
+    fn check_complete(&self) -> Result<(), String> {
+        if !self.is_complete() {
+            return Err(format!(
+                "Weights file is incomplete: received {} of {} bytes",
+                self.received, self.file_size
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn take_header(&mut self, chunk: &[u8]) -> Result<usize, String> {
+        let prefix_len = HEADER_PREFIX_LEN as usize;
+        let needed = if self.pending.len() < prefix_len {
+            prefix_len
+        } else {
+            prefix_len + tensor_header::header_len(&self.pending)? as usize
+        };
+
+        let taken = (needed - self.pending.len()).min(chunk.len());
+        self.pending.extend_from_slice(&chunk[..taken]);
+
+        if self.pending.len() < needed {
+            return Ok(taken);
+        }
+
+        if needed == prefix_len {
+            // The prefix is complete, the header follows
+            if tensor_header::header_len(&self.pending)? == 0 {
+                return Err("Safetensors header is empty".to_string());
+            }
+        } else {
+            let mut tensors =
+                tensor_header::parse_header(&self.pending[prefix_len..], self.file_size)?;
+            tensors.reverse();
+
+            self.remaining = tensors;
+            self.data_start = Some(needed as u64);
+            self.pending = Vec::new();
+            self.take_empty(0)?;
+        }
+
+        Ok(taken)
+    }
+
+    /// Takes bytes at `offset` of the data section for the next tensor.
+    fn take_data(&mut self, chunk: &[u8], offset: u64) -> Result<usize, String> {
+        let Some(tensor) = self.remaining.last() else {
+            return Err("Weights file continues after its last tensor".to_string());
+        };
+        let (begin, end) = tensor.data_offsets;
+
+        // Bytes between tensors belong to none of them
+        if offset < begin {
+            let skipped = (begin - offset).min(chunk.len() as u64);
+            self.take_empty(offset + skipped)?;
+            return Ok(skipped as usize);
+        }
+
+        if offset > begin && self.pending.is_empty() {
+            return Err(format!("Tensor {} overlaps the previous one", tensor.name));
+        }
+
+        let taken = (end - offset).min(chunk.len() as u64) as usize;
+        let size = (end - begin) as usize;
+
+        if taken == size {
+            // The whole tensor is in this chunk, it is read without a copy
+            let (name, tensor) = self.read_next(&chunk[..taken])?;
+            self.insert(name, tensor)?;
+        } else {
+            if self.pending.is_empty() {
+                self.pending
+                    .try_reserve_exact(size)
+                    .map_err(|_| format!("Not enough memory for tensor {}", tensor.name))?;
+            }
+
+            self.pending.extend_from_slice(&chunk[..taken]);
+
+            if self.pending.len() == size {
+                let bytes = std::mem::take(&mut self.pending);
+                let (name, tensor) = self.read_next(&bytes)?;
+                // Released before the conversion allocates the converted copy
+                drop(bytes);
+                self.insert(name, tensor)?;
+            }
+        }
+
+        self.take_empty(offset + taken as u64)?;
+        Ok(taken)
+    }
+
+    /// Tensors without elements take no bytes, so they are complete once reached.
+    fn take_empty(&mut self, offset: u64) -> Result<(), String> {
+        while let Some(tensor) = self.remaining.last() {
+            let (begin, end) = tensor.data_offsets;
+            if begin != end || begin > offset {
+                break;
+            }
+            let (name, tensor) = self.read_next(&[])?;
+            self.insert(name, tensor)?;
+        }
+
+        Ok(())
+    }
+
+    /// Builds the next tensor from its bytes, in the dtype it is stored in.
+    fn read_next(&mut self, bytes: &[u8]) -> Result<(String, Tensor), String> {
+        let info = self
+            .remaining
+            .pop()
+            .ok_or_else(|| "Weights file continues after its last tensor".to_string())?;
+
+        let dtype = candle_dtype(&info.dtype)
+            .ok_or_else(|| format!("Unsupported dtype {} of tensor {}", info.dtype, info.name))?;
+        let tensor = Tensor::from_raw_buffer(bytes, dtype, &info.shape, &self.device)
+            .map_err(|e| format!("Invalid tensor {}: {}", info.name, e))?;
+
+        Ok((info.name, tensor))
+    }
+
+    /// Stores a tensor, converting floating point tensors to the target dtype, or hands it
+    /// to the quantizer.
+    fn insert(&mut self, name: String, tensor: Tensor) -> Result<(), String> {
+        if let Some(quantizer) = self.quantizer.as_mut() {
+            return quantizer.add(&name, tensor);
+        }
+
+        let dtype = tensor.dtype();
+        let tensor = if dtype.is_float() && dtype != self.dtype {
+            tensor
+                .to_dtype(self.dtype)
+                .map_err(|e| format!("Cannot convert tensor {}: {}", name, e))?
+        } else {
+            tensor
+        };
+
+        if self.tensors.insert(name.clone(), tensor).is_some() {
+            return Err(format!("Tensor {} appears in more than one file", name));
+        }
+        Ok(())
+    }
+}
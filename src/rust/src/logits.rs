@@ -0,0 +1,196 @@
+use std::collections::HashSet;
+use std::ops::ControlFlow;
+
+use candle_core::{Device, Tensor};
+use candle_transformers::generation::LogitsProcessor as CandleLogitsProcessor;
+use js_sys::{Float32Array, Uint32Array};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+use crate::constraint::{self, TokenConstraint};
+use crate::penalties::Penalties;
+use crate::sampling::SamplerChain;
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(extends = js_sys::Function)]
+    #[wasm_bindgen(
+        typescript_type = "(logits: Float32Array, tokens: Uint32Array) => Float32Array | undefined"
+    )]
+    pub type LogitsProcessorCallback;
+}
+
+/// One step of a `LogitsPipeline`, rewriting the logits of the next token.
+pub trait LogitsProcessor {
+    /// Rewrites `logits`, one per token of the vocabulary, given the prompt and generated
+    /// `tokens` so far. Breaks when generation has to stop before sampling.
+    fn process(&mut self, tokens: &[u32], logits: &mut [f32]) -> Result<ControlFlow<()>, String>;
+
+    /// Called with every generated token that does not end generation.
+    fn accept(&mut self, _token: u32) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Picks the next token from the processed logits.
+pub trait TokenSampler {
+    fn sample(&mut self, logits: &[f32]) -> Result<u32, String>;
+}
+
+/// Runs logits processors in the order they were pushed, then samples the next token.
+pub struct LogitsPipeline<'a> {
+    processors: Vec<Box<dyn LogitsProcessor + 'a>>,
+    sampler: Box<dyn TokenSampler + 'a>,
+}
+
+impl<'a> LogitsPipeline<'a> {
+    pub fn new(sampler: impl TokenSampler + 'a) -> Self {
+        Self {
+            processors: Vec::new(),
+            sampler: Box::new(sampler),
+        }
+    }
+
+    pub fn push(&mut self, processor: impl LogitsProcessor + 'a) {
+        self.processors.push(Box::new(processor));
+    }
+
+    /// Appends processors built elsewhere, such as those handed over from JS.
+    pub fn extend(&mut self, processors: impl IntoIterator<Item = Box<dyn LogitsProcessor + 'a>>) {
+        self.processors.extend(processors);
+    }
+
+    /// The next token, or `None` when a processor stopped generation.
+    pub fn next_token(
+        &mut self,
+        tokens: &[u32],
+        mut logits: Vec<f32>,
+    ) -> Result<Option<u32>, String> {
+        for processor in &mut self.processors {
+            if processor.process(tokens, &mut logits)?.is_break() {
+                return Ok(None);
+            }
+        }
+
+        self.sampler.sample(&logits).map(Some)
+    }
+
+    /// Passes a generated token to every processor.
+    pub fn accept(&mut self, token: u32) -> Result<(), String> {
+        self.processors
+            .iter_mut()
+            .try_for_each(|processor| processor.accept(token))
+    }
+}
+
+/// Divides positive logits of the last `last_n` tokens by `penalty` and multiplies negative
+/// ones, as `candle_transformers::utils::apply_repeat_penalty` does.
+pub struct RepeatPenalty {
+    pub penalty: f32,
+    pub last_n: usize,
+}
+
+impl RepeatPenalty {
+    pub fn apply(&self, tokens: &[u32], logits: &mut [f32]) {
+        let context = &tokens[tokens.len().saturating_sub(self.last_n)..];
+        let unique: HashSet<u32> = context.iter().copied().collect();
+        for token in unique {
+            if let Some(logit) = logits.get_mut(token as usize) {
+                if *logit >= 0. {
+                    *logit /= self.penalty;
+                } else {
+                    *logit *= self.penalty;
+                }
+            }
+        }
+    }
+}
+
+impl LogitsProcessor for RepeatPenalty {
+    fn process(&mut self, tokens: &[u32], logits: &mut [f32]) -> Result<ControlFlow<()>, String> {
+        self.apply(tokens, logits);
+        Ok(ControlFlow::Continue(()))
+    }
+}
+
+impl LogitsProcessor for Penalties {
+    fn process(&mut self, _tokens: &[u32], logits: &mut [f32]) -> Result<ControlFlow<()>, String> {
+        self.apply(logits);
+        Ok(ControlFlow::Continue(()))
+    }
+
+    fn accept(&mut self, token: u32) -> Result<(), String> {
+        Penalties::accept(self, token);
+        Ok(())
+    }
+}
+
+/// Masks the tokens a constraint rejects, and stops once it is complete with nothing to
+/// follow and no EOS token to end with.
+pub struct Constrain<'a> {
+    pub constraint: Box<dyn TokenConstraint + 'a>,
+    pub eos_token_ids: Vec<u32>,
+}
+
+impl LogitsProcessor for Constrain<'_> {
+    fn process(&mut self, _tokens: &[u32], logits: &mut [f32]) -> Result<ControlFlow<()>, String> {
+        if constraint::mask_values(logits, self.constraint.as_mut(), &self.eos_token_ids)? {
+            Ok(ControlFlow::Continue(()))
+        } else {
+            Ok(ControlFlow::Break(()))
+        }
+    }
+
+    fn accept(&mut self, token: u32) -> Result<(), String> {
+        self.constraint.accept(token)
+    }
+}
+
+/// Hands the logits to a JS function, which either changes the `Float32Array` it is given
+/// in place and returns nothing, or returns a new one of the same length.
+pub struct JsLogitsProcessor(pub LogitsProcessorCallback);
+
+impl LogitsProcessor for JsLogitsProcessor {
+    fn process(&mut self, tokens: &[u32], logits: &mut [f32]) -> Result<ControlFlow<()>, String> {
+        let error = |e: JsValue| format!("Logits processor failed: {:?}", e);
+
+        let array = Float32Array::from(&*logits);
+        let returned = self
+            .0
+            .call2(&JsValue::NULL, &array, &Uint32Array::from(tokens))
+            .map_err(error)?;
+        let array = if returned.is_undefined() {
+            array
+        } else {
+            returned
+                .dyn_into::<Float32Array>()
+                .map_err(|_| "Logits processor did not return a Float32Array".to_string())?
+        };
+
+        if array.length() as usize != logits.len() {
+            return Err(format!(
+                "Logits processor returned {} logits instead of {}",
+                array.length(),
+                logits.len()
+            ));
+        }
+        array.copy_to(logits);
+
+        Ok(ControlFlow::Continue(()))
+    }
+}
+
+impl TokenSampler for SamplerChain {
+    fn sample(&mut self, logits: &[f32]) -> Result<u32, String> {
+        SamplerChain::sample(self, logits)
+    }
+}
+
+impl TokenSampler for CandleLogitsProcessor {
+    fn sample(&mut self, logits: &[f32]) -> Result<u32, String> {
+        let error = |e: candle_core::Error| e.to_string();
+        let logits = Tensor::new(logits, &Device::Cpu).map_err(error)?;
+
+        CandleLogitsProcessor::sample(self, &logits).map_err(error)
+    }
+}
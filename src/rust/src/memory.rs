@@ -0,0 +1,262 @@
+use candle_core::quantized::GgmlDType;
+use candle_core::DType;
+use candle_transformers::models::llama::LlamaConfig;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::hub;
+use crate::quantize;
+
+/// Everything a wasm32 module can address: linear memory is indexed with 32 bits.
+pub const WASM32_ADDRESS_SPACE: u64 = 4 * 1024 * 1024 * 1024;
+
+/// Context the loader plans for. The KV cache grows up to `max_position_embeddings`, which
+/// recent models set far beyond what a browser tab can hold, so the check uses this instead.
+pub const PREFLIGHT_CONTEXT_LEN: usize = 2048;
+
+/// Bytes a Llama model needs at run time, as estimated from its config.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MemoryEstimate {
+    pub parameter_count: u64,
+    pub weight_bytes: u64,
+    /// Keys and values of every layer for `context_len` tokens.
+    pub kv_cache_bytes: u64,
+    /// Upper bound of the intermediate tensors of one forward pass over the whole context.
+    pub activation_bytes: u64,
+    pub total_bytes: u64,
+    pub limit_bytes: u64,
+    pub fits: bool,
+}
+
+impl MemoryEstimate {
+    /// Adds bytes that are resident next to the model, such as the safetensors buffer the
+    /// weights are loaded from.
+    pub fn with_resident_bytes(mut self, bytes: u64) -> Self {
+        self.total_bytes = self.total_bytes.saturating_add(bytes);
+        self.fits = self.total_bytes <= self.limit_bytes;
+        self
+    }
+
+    /// Replaces the size of the weights, for weights that are not stored in the model dtype.
+    pub fn with_weight_bytes(mut self, bytes: u64) -> Self {
+        self.total_bytes = (self.total_bytes - self.weight_bytes).saturating_add(bytes);
+        self.weight_bytes = bytes;
+        self.fits = self.total_bytes <= self.limit_bytes;
+        self
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "about {} needed ({} weights, {} KV cache, {} activations) but at most {} is addressable",
+            format_bytes(self.total_bytes),
+            format_bytes(self.weight_bytes),
+            format_bytes(self.kv_cache_bytes),
+            format_bytes(self.activation_bytes),
+            format_bytes(self.limit_bytes),
+        )
+    }
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const GIB: f64 = 1024.0 * 1024.0 * 1024.0;
+    const MIB: f64 = 1024.0 * 1024.0;
+
+    if bytes as f64 >= GIB {
+        format!("{:.2} GiB", bytes as f64 / GIB)
+    } else {
+        format!("{:.1} MiB", bytes as f64 / MIB)
+    }
+}
+
+/// The dtype weights are loaded in, `f16` unless given.
+pub fn parse_dtype(dtype: Option<&str>) -> Result<DType, String> {
+    match dtype {
+        Some("f16") | None => Ok(DType::F16),
+        Some("bf16") => Ok(DType::BF16),
+        Some("f32") => Ok(DType::F32),
+        Some(dtype) => Err(format!("Unsupported dtype {}", dtype)),
+    }
+}
+
+/// Checks the head counts the estimates divide by, so a malformed `config.json` is reported
+/// instead of panicking.
+pub fn check_config(config: &LlamaConfig) -> Result<(), String> {
+    let heads = config.num_attention_heads;
+    let kv_heads = config.num_key_value_heads();
+
+    if heads == 0 || kv_heads == 0 {
+        return Err(format!(
+            "Invalid config.json: {} attention and {} key/value heads",
+            heads, kv_heads
+        ));
+    }
+    if !config.hidden_size.is_multiple_of(heads) {
+        return Err(format!(
+            "Invalid config.json: hidden_size {} is not a multiple of num_attention_heads {}",
+            config.hidden_size, heads
+        ));
+    }
+    if !heads.is_multiple_of(kv_heads) {
+        return Err(format!(
+            "Invalid config.json: num_attention_heads {} is not a multiple of num_key_value_heads {}",
+            heads, kv_heads
+        ));
+    }
+
+    Ok(())
+}
+
+/// Product of `factors`, or an error when it does not fit in a `u64`. The sizes come from
+/// `config.json` and the caller, so they are not trusted to stay small.
+fn product(factors: &[u64]) -> Result<u64, String> {
+    factors
+        .iter()
+        .try_fold(1u64, |product, &factor| product.checked_mul(factor))
+        .ok_or_else(overflow)
+}
+
+/// Sum of `terms`, or an error when it does not fit in a `u64`.
+fn sum(terms: &[u64]) -> Result<u64, String> {
+    terms
+        .iter()
+        .try_fold(0u64, |sum, &term| sum.checked_add(term))
+        .ok_or_else(overflow)
+}
+
+fn overflow() -> String {
+    "The model, context or batch is too large to estimate its memory".to_string()
+}
+
+pub fn parameter_count(config: &LlamaConfig) -> Result<u64, String> {
+    check_config(config)?;
+
+    let hidden = config.hidden_size as u64;
+    let intermediate = config.intermediate_size as u64;
+    let vocab = config.vocab_size as u64;
+    let head_dim = hidden / config.num_attention_heads as u64;
+    let kv_dim = product(&[config.num_key_value_heads() as u64, head_dim])?;
+
+    // q and o projections, k and v projections, gate/up/down and the two norms
+    let layer = sum(&[
+        product(&[2, hidden, hidden])?,
+        product(&[2, hidden, kv_dim])?,
+        product(&[3, hidden, intermediate])?,
+        product(&[2, hidden])?,
+    ])?;
+    let embedding = product(&[vocab, hidden])?;
+    let lm_head = if config.tie_word_embeddings.unwrap_or(false) {
+        0
+    } else {
+        embedding
+    };
+
+    sum(&[
+        embedding,
+        product(&[config.num_hidden_layers as u64, layer])?,
+        hidden,
+        lm_head,
+    ])
+}
+
+pub fn estimate(
+    config: &LlamaConfig,
+    dtype: DType,
+    context_len: usize,
+    batch: usize,
+) -> Result<MemoryEstimate, String> {
+    check_config(config)?;
+
+    let size = dtype.size_in_bytes() as u64;
+    let f32_size = DType::F32.size_in_bytes() as u64;
+    let hidden = config.hidden_size as u64;
+    let heads = config.num_attention_heads as u64;
+    let head_dim = hidden / heads;
+    let kv_dim = product(&[config.num_key_value_heads() as u64, head_dim])?;
+    let batch = batch as u64;
+    let context_len = context_len as u64;
+    let tokens = product(&[batch, context_len])?;
+
+    let parameter_count = parameter_count(config)?;
+    let weight_bytes = product(&[parameter_count, size])?;
+
+    // Rotary tables cover every position and are kept next to the cache
+    let rotary = product(&[2, config.max_position_embeddings as u64, head_dim / 2, size])?;
+    let kv_cache_bytes = sum(&[
+        product(&[2, config.num_hidden_layers as u64, tokens, kv_dim, size])?,
+        rotary,
+    ])?;
+
+    // Residual stream, q/k/v and the MLP in the model dtype; attention scores and the last
+    // position's logits are computed in f32
+    let stream = sum(&[product(&[5, hidden])?, product(&[2, kv_dim])?])?;
+    let activation_bytes = sum(&[
+        product(&[tokens, stream, size])?,
+        product(&[tokens, 3, config.intermediate_size as u64, size])?,
+        product(&[2, batch, heads, context_len, context_len, f32_size])?,
+        product(&[batch, config.vocab_size as u64, f32_size])?,
+    ])?;
+
+    let total_bytes = sum(&[weight_bytes, kv_cache_bytes, activation_bytes])?;
+
+    Ok(MemoryEstimate {
+        parameter_count,
+        weight_bytes,
+        kv_cache_bytes,
+        activation_bytes,
+        total_bytes,
+        limit_bytes: WASM32_ADDRESS_SPACE,
+        fits: total_bytes <= WASM32_ADDRESS_SPACE,
+    })
+}
+
+/// Like [`estimate`] for a model whose weights are quantized to `quantization` while
+/// loading. The quantized model computes in f32 and keeps its token embeddings dequantized.
+pub fn estimate_quantized(
+    config: &LlamaConfig,
+    quantization: GgmlDType,
+    context_len: usize,
+    batch: usize,
+) -> Result<MemoryEstimate, String> {
+    let estimate = estimate(config, DType::F32, context_len, batch)?;
+    let embedding = product(&[config.vocab_size as u64, config.hidden_size as u64])?;
+
+    // Tied models also keep the quantized embeddings as their output layer
+    let quantized = if config.tie_word_embeddings.unwrap_or(false) {
+        estimate.parameter_count
+    } else {
+        estimate.parameter_count - embedding
+    };
+    let quantized_bytes =
+        product(&[quantized, quantization.type_size() as u64])? / quantization.block_size() as u64;
+    let embedding_bytes = product(&[embedding, DType::F32.size_in_bytes() as u64])?;
+
+    Ok(estimate.with_weight_bytes(sum(&[quantized_bytes, embedding_bytes])?))
+}
+
+/// Estimates the memory a model needs for `context_len` tokens in `batch` sequences, so
+/// callers can warn before downloading or loading it. With `quantization`, `dtype` is
+/// ignored.
+#[wasm_bindgen]
+pub fn estimate_memory(
+    config_bytes: &[u8],
+    dtype: Option<String>,
+    context_len: usize,
+    batch: usize,
+    quantization: Option<String>,
+) -> Result<JsValue, JsValue> {
+    let error = |e: String| JsValue::from_str(&e);
+    let config: LlamaConfig = serde_json::from_slice(config_bytes)
+        .map_err(|e| error(format!("Invalid config.json: {}", e)))?;
+    let batch = batch.max(1);
+
+    let estimate = match quantize::parse_quantization(quantization.as_deref()).map_err(error)? {
+        Some(quantization) => estimate_quantized(&config, quantization, context_len, batch),
+        None => {
+            let dtype = parse_dtype(dtype.as_deref()).map_err(error)?;
+            estimate(&config, dtype, context_len, batch)
+        }
+    }
+    .map_err(error)?;
+
+    hub::to_js(&estimate)
+}
@@ -0,0 +1,81 @@
+use std::ops::Range;
+
+use wasm_bindgen::JsValue;
+use web_sys::{IdbDatabase, IdbTransaction};
+
+use crate::downloader::{MANIFEST_STORE_NAME, METADATA_STORE_NAME, STORE_NAME};
+
+/// A single schema upgrade. It runs inside the `versionchange` transaction, so it may both
+/// change object stores through the database and rewrite records through the transaction.
+pub type Migration = fn(&IdbDatabase, &IdbTransaction) -> Result<(), JsValue>;
+
+/// Schema upgrades in order: entry `i` upgrades the database from version `i` to `i + 1`.
+/// Append new steps at the end and never edit a step that has already shipped.
+pub const MIGRATIONS: &[Migration] = &[
+    create_models_store,
+    create_metadata_store,
+    create_manifests_store,
+    remove_legacy_entries,
+];
+
+/// Keys the first layout stored files under, before they were scoped by repository.
+pub const LEGACY_KEYS: &[&str] = &["model", "tokenizer", "config"];
+
+/// The IndexedDB version matching the newest schema.
+pub const DB_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// Indices into [`MIGRATIONS`] that bring a database at `old_version` up to `new_version`.
+pub fn pending(old_version: u32, new_version: u32) -> Range<usize> {
+    let end = (new_version as usize).min(MIGRATIONS.len());
+    let start = (old_version as usize).min(end);
+
+    start..end
+}
+
+/// Applies every pending step, stopping at the first failure.
+pub fn apply(
+    db: &IdbDatabase,
+    transaction: &IdbTransaction,
+    old_version: u32,
+    new_version: u32,
+) -> Result<(), JsValue> {
+    for index in pending(old_version, new_version) {
+        MIGRATIONS[index](db, transaction).map_err(|e| {
+            JsValue::from_str(&format!(
+                "Migration to version {} failed: {:?}",
+                index + 1,
+                e
+            ))
+        })?;
+    }
+
+    Ok(())
+}
+
+fn create_models_store(db: &IdbDatabase, _: &IdbTransaction) -> Result<(), JsValue> {
+    db.create_object_store(STORE_NAME)?;
+    Ok(())
+}
+
+fn create_metadata_store(db: &IdbDatabase, _: &IdbTransaction) -> Result<(), JsValue> {
+    db.create_object_store(METADATA_STORE_NAME)?;
+    Ok(())
+}
+
+fn create_manifests_store(db: &IdbDatabase, _: &IdbTransaction) -> Result<(), JsValue> {
+    db.create_object_store(MANIFEST_STORE_NAME)?;
+    Ok(())
+}
+
+/// Entries of the first layout do not record their repository, so they cannot be moved under
+/// a repository key; they are removed instead of being left unreachable.
+fn remove_legacy_entries(_: &IdbDatabase, transaction: &IdbTransaction) -> Result<(), JsValue> {
+    let store = transaction.object_store(STORE_NAME)?;
+    let metadata = transaction.object_store(METADATA_STORE_NAME)?;
+    for key in LEGACY_KEYS {
+        store.delete(&JsValue::from_str(key))?;
+        metadata.delete(&JsValue::from_str(key))?;
+    }
+
+    Ok(())
+}
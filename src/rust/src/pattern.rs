@@ -0,0 +1,124 @@
+use std::collections::{HashMap, HashSet};
+
+use regex_automata::dfa::{dense, Automaton, StartKind};
+use regex_automata::util::primitives::StateID;
+use regex_automata::util::start;
+use regex_automata::{Anchored, MatchKind};
+
+use crate::constraint::Constraint;
+
+/// Bytes the DFA of a pattern may take, so patterns with large Unicode classes fail instead
+/// of exhausting wasm memory.
+const DFA_SIZE_LIMIT: usize = 16 << 20;
+
+/// A regular expression the whole output has to match, compiled to a DFA over bytes. The
+/// syntax is that of the `regex` crate.
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    dfa: dense::DFA<Vec<u32>>,
+    start: StateID,
+    /// States from which the text can still end in a match.
+    live: HashSet<StateID>,
+}
+
+impl Pattern {
+    /// Builds the DFA and its live states, each up to `DFA_SIZE_LIMIT` bytes, so generation
+    /// compiles a pattern once, in `GenerationArguments::get_internal`, and keeps it.
+    pub fn new(pattern: &str) -> Result<Pattern, String> {
+        let error = |e: String| format!("Invalid regex {}: {}", pattern, e);
+
+        // Every match is kept, so the DFA does not stop at the first one
+        let config = dense::DFA::config()
+            .match_kind(MatchKind::All)
+            .start_kind(StartKind::Anchored)
+            .dfa_size_limit(Some(DFA_SIZE_LIMIT))
+            .determinize_size_limit(Some(DFA_SIZE_LIMIT));
+        let dfa = dense::Builder::new()
+            .configure(config)
+            .build(pattern)
+            .map_err(|e| error(e.to_string()))?;
+        let start = dfa
+            .start_state(&start::Config::new().anchored(Anchored::Yes))
+            .map_err(|e| error(e.to_string()))?;
+
+        let live = live_states(&dfa, start);
+
+        Ok(Pattern { dfa, start, live })
+    }
+
+    /// Whether `text` as a whole matches.
+    pub fn matches(&self, text: &str) -> bool {
+        let mut state = self.start;
+        for byte in text.bytes() {
+            match self.advance(&state, byte) {
+                Some(next) => state = next,
+                None => return false,
+            }
+        }
+
+        self.is_accepting(&state)
+    }
+}
+
+impl Constraint for Pattern {
+    type State = StateID;
+
+    fn start(&self) -> StateID {
+        self.start
+    }
+
+    fn advance(&self, state: &StateID, byte: u8) -> Option<StateID> {
+        let next = self.dfa.next_state(*state, byte);
+        self.live.contains(&next).then_some(next)
+    }
+
+    fn is_accepting(&self, state: &StateID) -> bool {
+        // Matches are reported one transition late, here on the end of the input
+        self.dfa.is_match_state(self.dfa.next_eoi_state(*state))
+    }
+}
+
+/// The states reachable from `start` that can reach a match at the end of the input. The
+/// DFA reports matches one byte late, so a byte after a complete match leads to a match
+/// state that is not dead yet but cannot match anything longer; such states are excluded.
+fn live_states(dfa: &dense::DFA<Vec<u32>>, start: StateID) -> HashSet<StateID> {
+    let mut index = HashMap::from([(start, 0)]);
+    let mut states = vec![start];
+    let mut predecessors: Vec<Vec<usize>> = vec![Vec::new()];
+
+    let mut next_index = 0;
+    while next_index < states.len() {
+        let state = states[next_index];
+        for byte in 0..=255 {
+            let next = dfa.next_state(state, byte);
+            if dfa.is_dead_state(next) || dfa.is_quit_state(next) {
+                continue;
+            }
+            let target = *index.entry(next).or_insert_with(|| {
+                states.push(next);
+                predecessors.push(Vec::new());
+                states.len() - 1
+            });
+            predecessors[target].push(next_index);
+        }
+        next_index += 1;
+    }
+
+    let mut live = vec![false; states.len()];
+    let mut pending: Vec<usize> = (0..states.len())
+        .filter(|&i| dfa.is_match_state(dfa.next_eoi_state(states[i])))
+        .collect();
+    while let Some(state) = pending.pop() {
+        if live[state] {
+            continue;
+        }
+        live[state] = true;
+        pending.extend(predecessors[state].iter().filter(|&&p| !live[p]));
+    }
+
+    states
+        .into_iter()
+        .zip(live)
+        .filter_map(|(state, live)| live.then_some(state))
+        .collect()
+}
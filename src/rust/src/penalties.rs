@@ -0,0 +1,129 @@
+use std::collections::{BTreeMap, HashMap};
+
+use tokenizers::Tokenizer;
+
+/// The range OpenAI accepts for `frequency_penalty` and `presence_penalty`.
+pub const PENALTY_RANGE: (f32, f32) = (-2., 2.);
+
+/// The range OpenAI accepts for `logit_bias` values; -100 in effect bans a token.
+pub const LOGIT_BIAS_RANGE: (f32, f32) = (-100., 100.);
+
+/// Adjusts the logits of the next token from the tokens generated so far. Unlike the repeat
+/// penalty, the prompt is not taken into account.
+#[derive(Clone, Debug, Default)]
+pub struct Penalties {
+    frequency: f32,
+    presence: f32,
+    bias: Vec<(u32, f32)>,
+    banned: Vec<Vec<u32>>,
+    counts: HashMap<u32, usize>,
+    generated: Vec<u32>,
+}
+
+impl Penalties {
+    /// `frequency` is subtracted once per time a token was generated and `presence` once if
+    /// it was generated at all. `bias` is added to the logits of its tokens, and the last
+    /// token of a `banned` sequence is never generated after the others.
+    pub fn new(
+        frequency: f32,
+        presence: f32,
+        bias: Vec<(u32, f32)>,
+        banned: Vec<Vec<u32>>,
+    ) -> Self {
+        Self {
+            frequency,
+            presence,
+            bias,
+            banned: banned
+                .into_iter()
+                .filter(|sequence| !sequence.is_empty())
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Whether applying the penalties never changes the logits.
+    pub fn is_empty(&self) -> bool {
+        self.frequency == 0.
+            && self.presence == 0.
+            && self.bias.is_empty()
+            && self.banned.is_empty()
+    }
+
+    pub fn apply(&self, logits: &mut [f32]) {
+        for (&token, &count) in &self.counts {
+            if let Some(logit) = logits.get_mut(token as usize) {
+                *logit -= count as f32 * self.frequency + self.presence;
+            }
+        }
+        for &(token, bias) in &self.bias {
+            if let Some(logit) = logits.get_mut(token as usize) {
+                *logit += bias;
+            }
+        }
+
+        for sequence in &self.banned {
+            let (last, prefix) = sequence.split_last().unwrap_or((&0, &[]));
+            if self.generated.ends_with(prefix) {
+                if let Some(logit) = logits.get_mut(*last as usize) {
+                    *logit = f32::NEG_INFINITY;
+                }
+            }
+        }
+    }
+
+    /// Records a generated token.
+    pub fn accept(&mut self, token: u32) {
+        *self.counts.entry(token).or_default() += 1;
+        self.generated.push(token);
+    }
+}
+
+/// Resolves the keys of `logit_bias` to token ids. A key is a token id, or else a token of
+/// the vocabulary or text encoding to a single token.
+pub fn resolve_logit_bias(
+    logit_bias: &BTreeMap<String, f32>,
+    tokenizer: &Tokenizer,
+) -> Result<Vec<(u32, f32)>, String> {
+    logit_bias
+        .iter()
+        .map(|(key, &bias)| Ok((resolve_token(key, tokenizer)?, bias)))
+        .collect()
+}
+
+fn resolve_token(key: &str, tokenizer: &Tokenizer) -> Result<u32, String> {
+    if let Ok(id) = key.parse::<u32>() {
+        return if (id as usize) < tokenizer.get_vocab_size(true) {
+            Ok(id)
+        } else {
+            Err(format!("logit_bias token {} is not in the vocabulary", id))
+        };
+    }
+    if let Some(id) = tokenizer.token_to_id(key) {
+        return Ok(id);
+    }
+
+    match encode(tokenizer, key)?.as_slice() {
+        [id] => Ok(*id),
+        _ => Err(format!("logit_bias key {:?} is not a single token", key)),
+    }
+}
+
+/// Encodes every banned sequence without special tokens. Text tokenizes differently after
+/// a space, so a word banned anywhere may need to be listed with and without one.
+pub fn encode_banned_sequences(
+    sequences: &[String],
+    tokenizer: &Tokenizer,
+) -> Result<Vec<Vec<u32>>, String> {
+    sequences
+        .iter()
+        .map(|sequence| encode(tokenizer, sequence))
+        .collect()
+}
+
+fn encode(tokenizer: &Tokenizer, text: &str) -> Result<Vec<u32>, String> {
+    tokenizer
+        .encode(text, false)
+        .map(|encoding| encoding.get_ids().to_vec())
+        .map_err(|e| format!("Could not encode {:?}: {}", text, e))
+}
@@ -0,0 +1,246 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::downloader::DownloadTask;
+
+#[wasm_bindgen(typescript_custom_section)]
+const TS_APPEND_CONTENT: &'static str = r#"
+export interface DownloadGroup {
+  on(event: 'progress', callback: (bytesReceived: number, totalBytes: number | undefined, percentage: number | undefined, bytesPerSecond: number, etaSeconds?: number) => void): DownloadGroup;
+  on(event: 'complete', callback: () => void): DownloadGroup;
+}
+"#;
+
+/// Default minimum time between two progress events.
+pub const DEFAULT_PROGRESS_INTERVAL_MS: f64 = 100.0;
+
+/// Weight of the newest throughput sample in the moving average.
+const THROUGHPUT_SMOOTHING: f64 = 0.3;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct ProgressSnapshot {
+    pub received: f64,
+    pub total: Option<f64>,
+    pub percentage: Option<i32>,
+    pub bytes_per_second: f64,
+    pub eta_seconds: Option<f64>,
+}
+
+impl ProgressSnapshot {
+    /// Arguments of a progress callback, after the leading ones the caller adds.
+    pub fn push_args(&self, args: &js_sys::Array) {
+        let optional = |value: Option<f64>| value.map_or(JsValue::UNDEFINED, JsValue::from_f64);
+
+        args.push(&JsValue::from_f64(self.received));
+        args.push(&optional(self.total));
+        args.push(&optional(self.percentage.map(f64::from)));
+        args.push(&JsValue::from_f64(self.bytes_per_second));
+        args.push(&optional(self.eta_seconds));
+    }
+}
+
+/// Turns a stream of byte counts into throttled progress with throughput and ETA.
+#[derive(Clone, Debug)]
+pub struct ProgressTracker {
+    interval_ms: f64,
+    started_at: Option<f64>,
+    last_emit: Option<(f64, f64)>,
+    bytes_per_second: f64,
+}
+
+impl ProgressTracker {
+    pub fn new(interval_ms: f64) -> Self {
+        Self {
+            interval_ms,
+            started_at: None,
+            last_emit: None,
+            bytes_per_second: 0.0,
+        }
+    }
+
+    /// Records `received` bytes at `now` milliseconds and returns a snapshot when one is due:
+    /// on the first update, once `interval_ms` passed since the last one, when the total is
+    /// reached, or when `force` is set. Nothing is returned if no bytes arrived since the
+    /// last snapshot.
+    pub fn update(
+        &mut self,
+        now: f64,
+        received: f64,
+        total: Option<f64>,
+        force: bool,
+    ) -> Option<ProgressSnapshot> {
+        let started_at = *self.started_at.get_or_insert(now);
+        let finished = total.is_some_and(|total| received >= total);
+
+        match self.last_emit {
+            // A restarted download goes back to zero, which would read as negative throughput
+            Some((_, last_received)) if received < last_received => {
+                self.last_emit = Some((now, received));
+                self.bytes_per_second = 0.0;
+            }
+            Some((last_time, last_received)) => {
+                let elapsed = now - last_time;
+
+                if received == last_received || (elapsed < self.interval_ms && !finished && !force)
+                {
+                    return None;
+                }
+
+                if elapsed > 0.0 {
+                    let rate = (received - last_received) / elapsed * 1000.0;
+                    self.bytes_per_second = if self.bytes_per_second == 0.0 {
+                        rate
+                    } else {
+                        THROUGHPUT_SMOOTHING * rate
+                            + (1.0 - THROUGHPUT_SMOOTHING) * self.bytes_per_second
+                    };
+                }
+                self.last_emit = Some((now, received));
+            }
+            None => {
+                let elapsed = now - started_at;
+                if elapsed > 0.0 {
+                    self.bytes_per_second = received / elapsed * 1000.0;
+                }
+                self.last_emit = Some((now, received));
+            }
+        }
+
+        let eta_seconds = match total {
+            Some(total) if self.bytes_per_second > 0.0 => {
+                Some((total - received).max(0.0) / self.bytes_per_second)
+            }
+            Some(_) if finished => Some(0.0),
+            _ => None,
+        };
+
+        Some(ProgressSnapshot {
+            received,
+            total,
+            percentage: total
+                .filter(|total| *total > 0.0)
+                .map(|total| (received / total * 100.0) as i32),
+            bytes_per_second: self.bytes_per_second,
+            eta_seconds,
+        })
+    }
+}
+
+struct GroupState {
+    files: Vec<(f64, Option<f64>)>,
+    completed: Vec<bool>,
+    tracker: ProgressTracker,
+    progress_callback: Option<js_sys::Function>,
+    complete_callback: Option<js_sys::Function>,
+}
+
+/// Membership of a task in a [`DownloadGroup`].
+#[derive(Clone)]
+pub(crate) struct GroupMember {
+    state: Rc<RefCell<GroupState>>,
+    index: usize,
+}
+
+impl GroupMember {
+    pub(crate) fn report(&self, received: f64, total: Option<f64>) {
+        let mut state = self.state.borrow_mut();
+        state.files[self.index] = (received, total);
+        state.emit(false);
+    }
+
+    pub(crate) fn complete(&self) {
+        let mut state = self.state.borrow_mut();
+        state.completed[self.index] = true;
+
+        if state.completed.iter().all(|completed| *completed) {
+            state.emit(true);
+
+            if let Some(cb) = state.complete_callback.as_ref() {
+                let _ = cb.call0(&JsValue::NULL);
+            }
+        }
+    }
+}
+
+impl GroupState {
+    fn emit(&mut self, force: bool) {
+        let received: f64 = self.files.iter().map(|(received, _)| received).sum();
+        // Totals are only meaningful once every file has reported its size
+        let total: Option<f64> = self.files.iter().map(|(_, total)| *total).sum();
+
+        let Some(snapshot) = self
+            .tracker
+            .update(js_sys::Date::now(), received, total, force)
+        else {
+            return;
+        };
+
+        if let Some(cb) = self.progress_callback.as_ref() {
+            let args = js_sys::Array::new();
+            snapshot.push_args(&args);
+            let _ = cb.apply(&JsValue::NULL, &args);
+        }
+    }
+}
+
+/// Combines the progress of several download tasks into one overall figure.
+#[wasm_bindgen]
+pub struct DownloadGroup {
+    state: Rc<RefCell<GroupState>>,
+}
+
+#[wasm_bindgen]
+impl DownloadGroup {
+    #[wasm_bindgen(constructor)]
+    pub fn new(progress_interval_ms: Option<f64>) -> Self {
+        Self {
+            state: Rc::new(RefCell::new(GroupState {
+                files: Vec::new(),
+                completed: Vec::new(),
+                tracker: ProgressTracker::new(
+                    progress_interval_ms.unwrap_or(DEFAULT_PROGRESS_INTERVAL_MS),
+                ),
+                progress_callback: None,
+                complete_callback: None,
+            })),
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn on(&mut self, event: &str, callback: Option<js_sys::Function>) {
+        if let Some(cb) = callback {
+            let mut state = self.state.borrow_mut();
+            match event {
+                "progress" => state.progress_callback = Some(cb),
+                "complete" => state.complete_callback = Some(cb),
+                _ => {}
+            }
+        }
+    }
+
+    /// Includes `task` in the overall progress. Add every task before starting any of them.
+    pub fn add(&self, task: &mut DownloadTask) {
+        task.join_group(self.member());
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn size(&self) -> usize {
+        self.state.borrow().files.len()
+    }
+}
+
+impl DownloadGroup {
+    pub(crate) fn member(&self) -> GroupMember {
+        let mut state = self.state.borrow_mut();
+        state.files.push((0.0, None));
+        state.completed.push(false);
+
+        GroupMember {
+            state: self.state.clone(),
+            index: state.files.len() - 1,
+        }
+    }
+}
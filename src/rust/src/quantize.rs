@@ -0,0 +1,246 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Seek, SeekFrom};
+
+use candle_core::quantized::{gguf_file, GgmlDType, QTensor};
+use candle_core::{Device, Tensor};
+use candle_transformers::models::llama::LlamaConfig;
+use candle_transformers::models::quantized_llama::ModelWeights;
+
+/// The quantized type linear layers are stored in, or `None` to keep float weights.
+pub fn parse_quantization(quantization: Option<&str>) -> Result<Option<GgmlDType>, String> {
+    match quantization {
+        None => Ok(None),
+        Some("q8_0") => Ok(Some(GgmlDType::Q8_0)),
+        Some("q4_k") => Ok(Some(GgmlDType::Q4K)),
+        Some(quantization) => Err(format!("Unsupported quantization {}", quantization)),
+    }
+}
+
+/// Name of a Hugging Face Llama tensor in GGUF files, which the quantized model reads.
+/// Tensors the model does not use, such as rotary frequencies, have none.
+pub fn gguf_name(name: &str) -> Option<String> {
+    match name {
+        "model.embed_tokens.weight" => return Some("token_embd.weight".to_string()),
+        "model.norm.weight" => return Some("output_norm.weight".to_string()),
+        "lm_head.weight" => return Some("output.weight".to_string()),
+        _ => {}
+    }
+
+    let (layer, rest) = name.strip_prefix("model.layers.")?.split_once('.')?;
+    let layer: usize = layer.parse().ok()?;
+    let tensor = match rest {
+        "self_attn.q_proj.weight" => "attn_q",
+        "self_attn.k_proj.weight" => "attn_k",
+        "self_attn.v_proj.weight" => "attn_v",
+        "self_attn.o_proj.weight" => "attn_output",
+        "mlp.gate_proj.weight" => "ffn_gate",
+        "mlp.up_proj.weight" => "ffn_up",
+        "mlp.down_proj.weight" => "ffn_down",
+        "input_layernorm.weight" => "attn_norm",
+        "post_attention_layernorm.weight" => "ffn_norm",
+        _ => return None,
+    };
+
+    Some(format!("blk.{}.{}.weight", layer, tensor))
+}
+
+/// Reorders the rows of a q or k projection from the half-split rotary layout of Hugging
+/// Face checkpoints to the interleaved layout of GGUF, like llama.cpp's converter does.
+pub fn permute_for_rope(tensor: &Tensor, heads: usize) -> candle_core::Result<Tensor> {
+    let (rows, columns) = tensor.dims2()?;
+
+    tensor
+        .reshape((heads, 2, rows / heads / 2, columns))?
+        .transpose(1, 2)?
+        .reshape((rows, columns))
+}
+
+/// The type a weight with `columns` inputs is quantized to: `dtype` when its block size
+/// divides `columns`, else the closest type whose block size does.
+fn quantized_type(dtype: GgmlDType, columns: usize) -> GgmlDType {
+    [dtype, GgmlDType::Q8_0, GgmlDType::F16]
+        .into_iter()
+        .find(|candidate| columns.is_multiple_of(candidate.block_size()))
+        .unwrap_or(GgmlDType::F32)
+}
+
+/// Quantizes the tensors of a Llama checkpoint one at a time as they are loaded. Only the
+/// quantized bytes are kept, and they are handed to candle's quantized Llama through an
+/// in-memory GGUF file that is never assembled in one piece.
+pub struct Quantizer {
+    dtype: GgmlDType,
+    heads: usize,
+    kv_heads: usize,
+    metadata: HashMap<String, gguf_file::Value>,
+    tensor_infos: HashMap<String, gguf_file::TensorInfo>,
+    segments: BTreeMap<u64, Vec<u8>>,
+    offset: u64,
+}
+
+impl Quantizer {
+    /// Fails for models with `rope_scaling`, which the quantized model does not apply.
+    pub fn new(config: &LlamaConfig, dtype: GgmlDType) -> Result<Self, String> {
+        if config.rope_scaling.is_some() {
+            return Err("Quantized models do not support rope_scaling".to_string());
+        }
+
+        let heads = config.num_attention_heads;
+        let kv_heads = config.num_key_value_heads();
+        let head_dim = config.hidden_size / heads;
+
+        let metadata = [
+            (
+                "llama.attention.head_count",
+                gguf_file::Value::U32(heads as u32),
+            ),
+            (
+                "llama.attention.head_count_kv",
+                gguf_file::Value::U32(kv_heads as u32),
+            ),
+            (
+                "llama.block_count",
+                gguf_file::Value::U32(config.num_hidden_layers as u32),
+            ),
+            (
+                "llama.embedding_length",
+                gguf_file::Value::U32(config.hidden_size as u32),
+            ),
+            (
+                "llama.rope.dimension_count",
+                gguf_file::Value::U32(head_dim as u32),
+            ),
+            (
+                "llama.attention.layer_norm_rms_epsilon",
+                gguf_file::Value::F32(config.rms_norm_eps as f32),
+            ),
+            (
+                "llama.rope.freq_base",
+                gguf_file::Value::F32(config.rope_theta),
+            ),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect();
+
+        Ok(Self {
+            dtype,
+            heads,
+            kv_heads,
+            metadata,
+            tensor_infos: HashMap::new(),
+            segments: BTreeMap::new(),
+            offset: 0,
+        })
+    }
+
+    /// Bytes of the quantized tensors so far.
+    pub fn size_in_bytes(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn dtype(&self) -> GgmlDType {
+        self.dtype
+    }
+
+    pub fn tensor_count(&self) -> usize {
+        self.tensor_infos.len()
+    }
+
+    /// Quantizes a tensor named as in Hugging Face checkpoints. Weight matrices are
+    /// quantized, norms are kept in f32 and tensors the model does not use are dropped.
+    pub fn add(&mut self, name: &str, tensor: Tensor) -> Result<(), String> {
+        let Some(gguf) = gguf_name(name) else {
+            return Ok(());
+        };
+        let error = |e: candle_core::Error| format!("Cannot quantize tensor {}: {}", name, e);
+
+        let tensor = if gguf.ends_with("attn_q.weight") {
+            permute_for_rope(&tensor, self.heads).map_err(error)?
+        } else if gguf.ends_with("attn_k.weight") {
+            permute_for_rope(&tensor, self.kv_heads).map_err(error)?
+        } else {
+            tensor
+        };
+
+        let dtype = match tensor.dims() {
+            [_, columns] => quantized_type(self.dtype, *columns),
+            _ => GgmlDType::F32,
+        };
+        let quantized = QTensor::quantize(&tensor, dtype).map_err(error)?;
+        let bytes = quantized.data().map_err(error)?.into_owned();
+
+        self.tensor_infos.insert(
+            gguf,
+            gguf_file::TensorInfo {
+                ggml_dtype: dtype,
+                shape: tensor.shape().clone(),
+                offset: self.offset,
+            },
+        );
+        let len = bytes.len() as u64;
+        self.segments.insert(self.offset, bytes);
+        self.offset += len;
+
+        Ok(())
+    }
+
+    /// Builds the quantized model, releasing each tensor's bytes once the model holds them.
+    pub fn load(self, device: &Device) -> Result<ModelWeights, String> {
+        let content = gguf_file::Content {
+            magic: gguf_file::VersionedMagic::GgufV3,
+            metadata: self.metadata,
+            tensor_infos: self.tensor_infos,
+            tensor_data_offset: 0,
+        };
+        let mut reader = SegmentReader {
+            segments: self.segments,
+            position: 0,
+        };
+
+        ModelWeights::from_gguf(content, &mut reader, device)
+            .map_err(|e| format!("Failed to load quantized model: {}", e))
+    }
+}
+
+/// Serves the tensor data of the in-memory GGUF file. Every tensor is read exactly once,
+/// so a segment is dropped as soon as it has been read to its end.
+struct SegmentReader {
+    segments: BTreeMap<u64, Vec<u8>>,
+    position: u64,
+}
+
+impl Read for SegmentReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let Some((&start, segment)) = self.segments.range(..=self.position).next_back() else {
+            return Ok(0);
+        };
+
+        let offset = (self.position - start) as usize;
+        let len = buf.len().min(segment.len().saturating_sub(offset));
+        buf[..len].copy_from_slice(&segment[offset..offset + len]);
+        self.position += len as u64;
+
+        if offset + len == segment.len() {
+            self.segments.remove(&start);
+        }
+
+        Ok(len)
+    }
+}
+
+impl Seek for SegmentReader {
+    fn seek(&mut self, position: SeekFrom) -> std::io::Result<u64> {
+        self.position = match position {
+            SeekFrom::Start(position) => position,
+            SeekFrom::Current(delta) => self.position.saturating_add_signed(delta),
+            SeekFrom::End(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    "the end of in-memory GGUF data is not known",
+                ))
+            }
+        };
+
+        Ok(self.position)
+    }
+}
@@ -0,0 +1,582 @@
+use std::cell::RefCell;
+
+use candle_core::DType;
+use js_sys::{Promise, Uint8Array};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::{future_to_promise, JsFuture};
+use web_sys::{AbortController, IdbTransactionMode};
+
+use crate::buffer::ByteBuffer;
+use crate::cache::CacheEntry;
+use crate::downloader::{
+    Downloader, DEFAULT_REVISION, MANIFEST_STORE_NAME, METADATA_STORE_NAME, STORE_NAME,
+};
+use crate::hub::{self, FileRevision, RepositoryRevision};
+use crate::memory;
+use crate::progress::DownloadGroup;
+use crate::retry::RetryPolicy;
+use crate::transcode;
+
+#[wasm_bindgen(typescript_custom_section)]
+const TS_APPEND_CONTENT: &'static str = r#"
+export interface RepositoryDownload {
+  on(event: 'begin', callback: (filename: string) => void): RepositoryDownload;
+  on(event: 'progress', callback: (repository: string, bytesReceived: number, totalBytes: number | undefined, percentage: number | undefined, bytesPerSecond: number, etaSeconds?: number) => void): RepositoryDownload;
+  on(event: 'file_complete', callback: (filename: string) => void): RepositoryDownload;
+  on(event: 'complete', callback: (repository: string) => void): RepositoryDownload;
+  on(event: 'abort', callback: (repository: string) => void): RepositoryDownload;
+  on(event: 'retry', callback: (filename: string, attempt: number, delayMs: number, reason: string) => void): RepositoryDownload;
+  on(event: 'error', callback: (filename: string, reason: string) => void): RepositoryDownload;
+}
+"#;
+
+/// Recorded once every file of a repository has been stored.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RepositoryManifest {
+    pub repository: String,
+    pub revision: String,
+    pub files: Vec<String>,
+    pub completed_at: f64,
+}
+
+/// How a cached file compares to the same file upstream.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateStatus {
+    Current,
+    Changed,
+    /// The cached entry predates revision tracking, so it cannot be compared.
+    Unknown,
+    /// Not cached yet.
+    Missing,
+    /// No longer exists upstream. The cached copy is kept.
+    Removed,
+}
+
+impl UpdateStatus {
+    pub fn needs_download(self) -> bool {
+        matches!(
+            self,
+            UpdateStatus::Changed | UpdateStatus::Unknown | UpdateStatus::Missing
+        )
+    }
+}
+
+/// Result of comparing one file of a repository against the hub.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FileUpdate {
+    pub filename: String,
+    pub status: UpdateStatus,
+    pub cached: Option<FileRevision>,
+    pub remote: Option<FileRevision>,
+}
+
+impl FileUpdate {
+    pub fn new(filename: &str, cached: Option<&CacheEntry>, remote: Option<FileRevision>) -> Self {
+        let status = match (cached, remote.as_ref()) {
+            (_, None) => UpdateStatus::Removed,
+            (None, Some(_)) => UpdateStatus::Missing,
+            (Some(entry), Some(remote)) => match (&entry.etag, &remote.etag) {
+                (Some(cached), Some(remote)) if cached == remote => UpdateStatus::Current,
+                (Some(_), Some(_)) => UpdateStatus::Changed,
+                _ => UpdateStatus::Unknown,
+            },
+        };
+
+        Self {
+            filename: filename.to_string(),
+            status,
+            cached: cached.map(CacheEntry::file_revision),
+            remote,
+        }
+    }
+}
+
+/// Cache key of a repository file. Repository ids always contain exactly one `/`, so the
+/// first two path segments identify the repository.
+pub fn repository_key(repository: &str, filename: &str) -> String {
+    format!("{}/{}", repository, filename)
+}
+
+/// Whether `filename` holds model weights rather than a tokenizer, config or index.
+pub fn is_weights_file(filename: &str) -> bool {
+    filename.ends_with(".safetensors")
+}
+
+/// Files needed to run a model of the given architecture, either the `model_type` of
+/// `config.json` or one of its `architectures`.
+pub fn files_for_architecture(architecture: &str) -> Option<Vec<String>> {
+    match architecture.to_lowercase().as_str() {
+        "llama" | "llamaforcausallm" => Some(
+            ["model.safetensors", "tokenizer.json", "config.json"]
+                .iter()
+                .map(|f| f.to_string())
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+/// Files fetched alongside the manifest files when the repository has them. They are not
+/// recorded in the manifest, so a repository without them is still complete.
+pub const OPTIONAL_FILES: &[&str] = &["generation_config.json"];
+
+#[wasm_bindgen]
+pub struct RepositoryDownload {
+    repository: String,
+    files: Vec<String>,
+    abort_controller: RefCell<AbortController>,
+    retry_policy: RetryPolicy,
+    weights_dtype: Option<DType>,
+
+    begin_callback: Option<js_sys::Function>,
+    progress_callback: Option<js_sys::Function>,
+    file_complete_callback: Option<js_sys::Function>,
+    complete_callback: Option<js_sys::Function>,
+    abort_callback: Option<js_sys::Function>,
+    retry_callback: Option<js_sys::Function>,
+    error_callback: Option<js_sys::Function>,
+}
+
+#[wasm_bindgen]
+impl RepositoryDownload {
+    /// Downloads `files` from `repository`, or the files of a Llama model when omitted.
+    #[wasm_bindgen(constructor)]
+    pub fn new(repository: &str, files: Option<Vec<String>>) -> Self {
+        let files = files.unwrap_or_else(|| files_for_architecture("llama").unwrap());
+
+        Self {
+            repository: repository.to_string(),
+            files,
+            abort_controller: RefCell::new(AbortController::new().unwrap()),
+            retry_policy: RetryPolicy::new(),
+            weights_dtype: None,
+            begin_callback: None,
+            progress_callback: None,
+            file_complete_callback: None,
+            complete_callback: None,
+            abort_callback: None,
+            retry_callback: None,
+            error_callback: None,
+        }
+    }
+
+    pub fn for_architecture(
+        repository: &str,
+        architecture: &str,
+    ) -> Result<RepositoryDownload, JsValue> {
+        let files = files_for_architecture(architecture).ok_or_else(|| {
+            JsValue::from_str(&format!("Unsupported architecture: {}", architecture))
+        })?;
+
+        Ok(Self::new(repository, Some(files)))
+    }
+
+    /// Builds the manifest from the hub listing, picking the weights variant automatically.
+    pub async fn discover(repository: &str) -> Result<RepositoryDownload, JsValue> {
+        let listing = hub::list_files(repository, DEFAULT_REVISION).await?;
+        let weights = hub::select_weights(&listing)
+            .map_err(|e| JsValue::from_str(&format!("{}: {}", repository, e)))?;
+
+        let mut files = weights.files();
+        for extra in ["tokenizer.json", "config.json"] {
+            if listing.iter().any(|file| file.path == extra) {
+                files.push(extra.to_string());
+            }
+        }
+
+        Ok(Self::new(repository, Some(files)))
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn files(&self) -> Vec<String> {
+        self.files.clone()
+    }
+
+    #[wasm_bindgen]
+    pub fn on(&mut self, event: &str, callback: Option<js_sys::Function>) {
+        if let Some(cb) = callback {
+            match event {
+                "begin" => self.begin_callback = Some(cb),
+                "progress" => self.progress_callback = Some(cb),
+                "file_complete" => self.file_complete_callback = Some(cb),
+                "complete" => self.complete_callback = Some(cb),
+                "abort" => self.abort_callback = Some(cb),
+                "retry" => self.retry_callback = Some(cb),
+                "error" => self.error_callback = Some(cb),
+                _ => {}
+            }
+        }
+    }
+
+    /// Retry policy applied to every file of the repository.
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    /// Transcodes downloaded `.safetensors` files to `dtype` before the download completes,
+    /// so later loads read smaller files that need no conversion. `start()` still resolves
+    /// to the files as downloaded.
+    pub fn set_weights_dtype(&mut self, dtype: Option<String>) -> Result<(), JsValue> {
+        self.weights_dtype = match dtype {
+            Some(dtype) => {
+                Some(memory::parse_dtype(Some(&dtype)).map_err(|e| JsValue::from_str(&e))?)
+            }
+            None => None,
+        };
+
+        Ok(())
+    }
+
+    /// Cancels every file of a running `start()`. No manifest is written.
+    pub fn cancel(&self) {
+        self.abort_controller.borrow().abort();
+    }
+
+    /// Downloads every file concurrently and resolves to their contents in manifest order.
+    /// The manifest is only written when all of them were stored; if one file fails the
+    /// others are cancelled. `OPTIONAL_FILES` are cached afterwards but not resolved to.
+    pub async fn start(&self) -> Result<js_sys::Array, JsValue> {
+        self.run(&self.files, false).await
+    }
+
+    /// Like `start()`, but resolves to a `ByteBuffer` per file.
+    pub async fn start_buffers(&self) -> Result<js_sys::Array, JsValue> {
+        self.run(&self.files, true).await
+    }
+
+    /// Downloads only the files that changed upstream since they were cached, or that are not
+    /// cached, and resolves to their names. The manifest is rewritten once all are stored.
+    pub async fn update(&self) -> Result<js_sys::Array, JsValue> {
+        let stale: Vec<String> = Self::compare(&self.repository, &self.files)
+            .await?
+            .into_iter()
+            .filter(|update| update.status.needs_download())
+            .map(|update| update.filename)
+            .collect();
+
+        self.run(&stale, false).await?;
+
+        Ok(stale
+            .iter()
+            .map(|filename| JsValue::from_str(filename))
+            .collect())
+    }
+
+    /// Compares every cached file of `repository` with the hub, without downloading anything.
+    /// The hashes of all files come from a single request. Resolves to one `FileUpdate` per
+    /// file.
+    pub async fn check_updates(repository: &str) -> Result<JsValue, JsValue> {
+        let files = Self::cached_files(repository).await?;
+        hub::to_js(&Self::compare(repository, &files).await?)
+    }
+
+    /// Rewrites every cached `.safetensors` file of `repository` with its floating point
+    /// tensors in `dtype`, `f16` unless given, and resolves to the names of the rewritten
+    /// files. Files already in `dtype` are left alone.
+    pub async fn transcode_weights(
+        repository: &str,
+        dtype: Option<String>,
+    ) -> Result<js_sys::Array, JsValue> {
+        let dtype = memory::parse_dtype(dtype.as_deref()).map_err(|e| JsValue::from_str(&e))?;
+        let files = Self::cached_files(repository).await?;
+
+        Self::transcode(repository, &files, dtype).await
+    }
+
+    /// Whether a manifest was recorded for `repository` and all of its files are still cached.
+    pub async fn is_complete(repository: &str) -> bool {
+        Self::check_complete(repository).await.unwrap_or(false)
+    }
+
+    pub async fn get_file(repository: &str, filename: &str) -> Option<Uint8Array> {
+        Downloader::get(&repository_key(repository, filename)).await
+    }
+
+    pub async fn get_file_buffer(repository: &str, filename: &str) -> Option<ByteBuffer> {
+        Downloader::get_buffer(&repository_key(repository, filename)).await
+    }
+
+    /// Removes the manifest and every cached file of `repository` in a single transaction,
+    /// including files of downloads that never completed.
+    pub async fn remove_repository(repository: &str) -> bool {
+        Self::remove(repository).await.is_ok()
+    }
+}
+
+impl RepositoryDownload {
+    async fn run(&self, files: &[String], buffers: bool) -> Result<js_sys::Array, JsValue> {
+        let controller = AbortController::new()?;
+        self.abort_controller.replace(controller.clone());
+
+        let downloader = Downloader::new(&self.repository);
+        let mut group = DownloadGroup::new(None);
+        if let Some(cb) = self.progress_callback.as_ref() {
+            let repository = JsValue::from_str(&self.repository);
+            let bound = cb.bind1(&JsValue::NULL, &repository);
+            group.on("progress", Some(bound.unchecked_into()));
+        }
+
+        // Resolved once for every file. Looked up before the downloads start, so a file that
+        // changes meanwhile is recorded as outdated rather than current.
+        let upstream = hub::repository_revision(&self.repository, DEFAULT_REVISION)
+            .await
+            .ok();
+
+        let promises = js_sys::Array::new();
+
+        for filename in files {
+            let mut task = downloader
+                .save_file(filename, &repository_key(&self.repository, filename))
+                .with_abort_controller(controller.clone())
+                .with_upstream_revision(upstream.as_ref().and_then(|u| u.file(filename)));
+            task.on("begin", self.begin_callback.clone());
+            task.on("complete", self.file_complete_callback.clone());
+            task.on("retry", self.retry_callback.clone());
+            task.on("error", self.error_callback.clone());
+            task.set_retry_policy(self.retry_policy.clone());
+
+            group.add(&mut task);
+
+            promises.push(&future_to_promise(async move {
+                if buffers {
+                    task.start_buffer().await.map(JsValue::from)
+                } else {
+                    task.start().await.map(JsValue::from)
+                }
+            }));
+        }
+
+        let result = JsFuture::from(Promise::all(&promises)).await;
+
+        let contents: js_sys::Array = match result {
+            Ok(contents) => contents.into(),
+            Err(e) => {
+                let cancelled = controller.signal().aborted();
+                controller.abort();
+
+                if cancelled {
+                    if let Some(cb) = self.abort_callback.as_ref() {
+                        cb.call1(&JsValue::NULL, &JsValue::from_str(&self.repository))?;
+                    }
+                }
+
+                return Err(e);
+            }
+        };
+
+        // A missing or unreachable optional file does not fail the download, cancelling does
+        if let Err(e) = self
+            .fetch_optional(&downloader, &controller, upstream.as_ref())
+            .await
+        {
+            if controller.signal().aborted() {
+                if let Some(cb) = self.abort_callback.as_ref() {
+                    cb.call1(&JsValue::NULL, &JsValue::from_str(&self.repository))?;
+                }
+
+                return Err(e);
+            }
+        }
+
+        let manifest = RepositoryManifest {
+            repository: self.repository.clone(),
+            revision: DEFAULT_REVISION.to_string(),
+            files: self.files.clone(),
+            completed_at: js_sys::Date::now(),
+        };
+        Self::put_manifest(&manifest).await?;
+
+        if let Some(dtype) = self.weights_dtype {
+            Self::transcode(&self.repository, files, dtype).await?;
+        }
+
+        if let Some(cb) = self.complete_callback.as_ref() {
+            cb.call1(&JsValue::NULL, &JsValue::from_str(&self.repository))?;
+        }
+
+        Ok(contents)
+    }
+
+    /// Downloads the `OPTIONAL_FILES` that exist upstream and are not cached or changed
+    /// since they were.
+    async fn fetch_optional(
+        &self,
+        downloader: &Downloader,
+        controller: &AbortController,
+        upstream: Option<&RepositoryRevision>,
+    ) -> Result<(), JsValue> {
+        let files: Vec<String> = OPTIONAL_FILES
+            .iter()
+            .filter(|optional| !self.files.iter().any(|file| file == *optional))
+            .map(|optional| optional.to_string())
+            .collect();
+
+        for update in Self::compare(&self.repository, &files).await? {
+            if !update.status.needs_download() {
+                continue;
+            }
+
+            let mut task = downloader
+                .save_file(
+                    &update.filename,
+                    &repository_key(&self.repository, &update.filename),
+                )
+                .with_abort_controller(controller.clone())
+                .with_upstream_revision(upstream.and_then(|u| u.file(&update.filename)));
+            task.set_retry_policy(self.retry_policy.clone());
+            task.start().await?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) async fn put_manifest(manifest: &RepositoryManifest) -> Result<(), JsValue> {
+        let json =
+            serde_json::to_string(manifest).map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+        let db = Downloader::open_db().await?;
+        let transaction =
+            Downloader::transaction(&db, &[MANIFEST_STORE_NAME], IdbTransactionMode::Readwrite)?;
+        let request = transaction
+            .object_store(MANIFEST_STORE_NAME)?
+            .put_with_key(
+                &JsValue::from_str(&json),
+                &JsValue::from_str(&manifest.repository),
+            )?;
+
+        Downloader::idbrequest_to_result::<JsValue>(&request).await?;
+        Ok(())
+    }
+
+    async fn transcode(
+        repository: &str,
+        files: &[String],
+        dtype: DType,
+    ) -> Result<js_sys::Array, JsValue> {
+        let transcoded = js_sys::Array::new();
+
+        for filename in files.iter().filter(|file| file.ends_with(".safetensors")) {
+            if transcode::transcode_cached(repository, filename, dtype).await? {
+                transcoded.push(&JsValue::from_str(filename));
+            }
+        }
+
+        Ok(transcoded)
+    }
+
+    /// Files of the manifest, or of the cached entries when no download completed.
+    async fn cached_files(repository: &str) -> Result<Vec<String>, JsValue> {
+        if let Some(manifest) = Self::read_manifest(repository).await? {
+            return Ok(manifest.files);
+        }
+
+        let db = Downloader::open_db().await?;
+        let prefix = repository_key(repository, "");
+
+        Ok(Downloader::read_entries(&db)
+            .await?
+            .into_iter()
+            .filter(|entry| entry.repository == repository)
+            .filter_map(|entry| entry.key.strip_prefix(&prefix).map(str::to_string))
+            .collect())
+    }
+
+    async fn compare(repository: &str, files: &[String]) -> Result<Vec<FileUpdate>, JsValue> {
+        let db = Downloader::open_db().await?;
+        let entries = Downloader::read_entries(&db).await?;
+
+        let upstream = hub::repository_revision(repository, DEFAULT_REVISION).await?;
+
+        Ok(files
+            .iter()
+            .map(|filename| {
+                let key = repository_key(repository, filename);
+                let cached = entries.iter().find(|entry| entry.key == key);
+
+                FileUpdate::new(filename, cached, upstream.file(filename))
+            })
+            .collect())
+    }
+
+    pub(crate) async fn read_manifest(
+        repository: &str,
+    ) -> Result<Option<RepositoryManifest>, JsValue> {
+        let db = Downloader::open_db().await?;
+        let transaction =
+            Downloader::transaction(&db, &[MANIFEST_STORE_NAME], IdbTransactionMode::Readonly)?;
+        let request = transaction
+            .object_store(MANIFEST_STORE_NAME)?
+            .get(&JsValue::from_str(repository))?;
+        let value = Downloader::idbrequest_to_result::<JsValue>(&request).await?;
+
+        Ok(value
+            .as_string()
+            .and_then(|json| serde_json::from_str(&json).ok()))
+    }
+
+    async fn check_complete(repository: &str) -> Result<bool, JsValue> {
+        let db = Downloader::open_db().await?;
+        let transaction = Downloader::transaction(
+            &db,
+            &[MANIFEST_STORE_NAME, STORE_NAME],
+            IdbTransactionMode::Readonly,
+        )?;
+
+        let request = transaction
+            .object_store(MANIFEST_STORE_NAME)?
+            .get(&JsValue::from_str(repository))?;
+        let json = match Downloader::idbrequest_to_result::<js_sys::JsString>(&request).await {
+            Ok(json) => String::from(json),
+            Err(_) => return Ok(false),
+        };
+        let manifest: RepositoryManifest =
+            serde_json::from_str(&json).map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+        let store = transaction.object_store(STORE_NAME)?;
+        for filename in &manifest.files {
+            let key = JsValue::from_str(&repository_key(repository, filename));
+            let request = store.count_with_key(&key)?;
+            let count = Downloader::idbrequest_to_result::<JsValue>(&request).await?;
+
+            if count.as_f64() != Some(1.0) {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    async fn remove(repository: &str) -> Result<(), JsValue> {
+        let db = Downloader::open_db().await?;
+        let transaction = Downloader::transaction(
+            &db,
+            &[STORE_NAME, METADATA_STORE_NAME, MANIFEST_STORE_NAME],
+            IdbTransactionMode::Readwrite,
+        )?;
+
+        let metadata = transaction.object_store(METADATA_STORE_NAME)?;
+        let store = transaction.object_store(STORE_NAME)?;
+        let manifests = transaction.object_store(MANIFEST_STORE_NAME)?;
+
+        let request = metadata.get_all()?;
+        let values = Downloader::idbrequest_to_result::<js_sys::Array>(&request).await?;
+        let entries = values
+            .iter()
+            .filter_map(|value| value.as_string())
+            .filter_map(|json| serde_json::from_str::<CacheEntry>(&json).ok())
+            .filter(|entry| entry.repository == repository);
+
+        for entry in entries {
+            store.delete(&JsValue::from_str(&entry.key))?;
+            metadata.delete(&JsValue::from_str(&entry.key))?;
+        }
+
+        let request = manifests.delete(&JsValue::from_str(repository))?;
+        Downloader::idbrequest_to_result::<JsValue>(&request).await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    pub initial_delay_ms: f64,
+    pub max_delay_ms: f64,
+    /// Factor the delay grows by after every failed attempt.
+    pub multiplier: f64,
+    /// HTTP statuses worth retrying. Network errors are always retried.
+    pub retryable_statuses: Vec<u16>,
+}
+
+#[wasm_bindgen]
+impl RetryPolicy {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 4,
+            initial_delay_ms: 500.0,
+            max_delay_ms: 30_000.0,
+            multiplier: 2.0,
+            retryable_statuses: vec![408, 425, 429, 500, 502, 503, 504],
+        }
+    }
+
+    /// A policy that gives up after the first failure.
+    pub fn none() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 1,
+            ..Self::new()
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RetryPolicy {
+    pub fn is_retryable(&self, status: u16) -> bool {
+        self.retryable_statuses.contains(&status)
+    }
+
+    /// Whether another attempt may follow the failed attempt number `attempt`, counting from 1.
+    pub fn allows_retry(&self, attempt: u32) -> bool {
+        attempt < self.max_attempts
+    }
+
+    /// Milliseconds to wait after the failed attempt number `attempt`. A server-provided
+    /// `Retry-After` wins when it asks for a longer wait, but never beyond `max_delay_ms`.
+    pub fn delay_ms(&self, attempt: u32, retry_after_ms: Option<f64>) -> f64 {
+        let exponent = attempt.saturating_sub(1) as i32;
+        let backoff = self.initial_delay_ms * self.multiplier.powi(exponent);
+
+        backoff
+            .max(retry_after_ms.unwrap_or(0.0))
+            .min(self.max_delay_ms)
+    }
+}
+
+/// Parses a `Retry-After` header given in seconds. HTTP dates are ignored.
+pub fn parse_retry_after(value: &str) -> Option<f64> {
+    value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|seconds| *seconds >= 0.0)
+        .map(|seconds| seconds * 1000.0)
+}
+
+/// Total size from a `Content-Range` header such as `bytes 100-999/1000`.
+pub fn parse_content_range_total(value: &str) -> Option<f64> {
+    value.rsplit('/').next()?.trim().parse().ok()
+}
@@ -0,0 +1,282 @@
+use rand::distr::weighted::WeightedIndex;
+use rand::distr::Distribution;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+
+/// One step of a sampler chain. Steps narrow down the candidate tokens in the order they
+/// are listed; the token is then drawn from the candidates left, or picked by Mirostat.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Sampler {
+    /// Keeps the `k` most likely tokens.
+    TopK { k: usize },
+    /// Keeps the most likely tokens whose probabilities add up to `p`.
+    TopP { p: f64 },
+    /// Keeps the tokens at least `p` times as likely as the most likely one.
+    MinP { p: f64 },
+    /// Locally typical sampling: keeps the tokens whose surprise is closest to the entropy
+    /// of the distribution, up to a total probability of `p`.
+    Typical { p: f64 },
+    /// Tail-free sampling: cuts the tail where the second derivative of the sorted
+    /// probabilities has added up to `z`.
+    TailFree { z: f64 },
+    /// Keeps the tokens with a probability of at least `a` times the square of the highest.
+    TopA { a: f64 },
+    /// Divides the logits by `temperature`; 0 keeps only the most likely token.
+    Temperature { temperature: f64 },
+    /// Mirostat 2.0: keeps the surprise of the text near `tau` by cutting tokens more
+    /// surprising than a bound that learns at rate `eta`. Picks the token, so it comes last.
+    MirostatV2 { tau: f64, eta: f64 },
+}
+
+impl Sampler {
+    fn check(&self) -> Result<(), String> {
+        let in_unit = |name: &str, value: f64| {
+            if value > 0. && value <= 1. {
+                Ok(())
+            } else {
+                Err(format!("{} must be in (0, 1], got {}", name, value))
+            }
+        };
+
+        match *self {
+            Sampler::TopK { k: 0 } => Err("top_k must keep at least 1 token".to_string()),
+            Sampler::TopK { .. } => Ok(()),
+            Sampler::TopP { p } => in_unit("top_p", p),
+            Sampler::MinP { p } if (0. ..=1.).contains(&p) => Ok(()),
+            Sampler::MinP { p } => Err(format!("min_p must be in [0, 1], got {}", p)),
+            Sampler::Typical { p } => in_unit("typical", p),
+            Sampler::TailFree { z } => in_unit("tail_free", z),
+            Sampler::TopA { a } if a >= 0. && a.is_finite() => Ok(()),
+            Sampler::TopA { a } => Err(format!("top_a must be 0 or more, got {}", a)),
+            Sampler::Temperature { temperature }
+                if temperature >= 0. && temperature.is_finite() =>
+            {
+                Ok(())
+            }
+            Sampler::Temperature { temperature } => Err(format!(
+                "temperature must be 0 or more, got {}",
+                temperature
+            )),
+            Sampler::MirostatV2 { tau, eta } if tau > 0. && eta > 0. => Ok(()),
+            Sampler::MirostatV2 { .. } => {
+                Err("mirostat_v2 needs a positive tau and eta".to_string())
+            }
+        }
+    }
+}
+
+/// Checks the values of every sampler and that Mirostat, which picks the token, is last.
+pub fn check_samplers(samplers: &[Sampler]) -> Result<(), String> {
+    for sampler in samplers {
+        sampler.check()?;
+    }
+
+    let mirostat = samplers
+        .iter()
+        .position(|sampler| matches!(sampler, Sampler::MirostatV2 { .. }));
+    match mirostat {
+        Some(index) if index + 1 != samplers.len() => {
+            Err("mirostat_v2 has to be the last sampler".to_string())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// A candidate token with its logit, and its probability once computed.
+#[derive(Clone, Copy, Debug)]
+struct Candidate {
+    token: u32,
+    logit: f64,
+    probability: f64,
+}
+
+/// Applies a list of samplers in order. Mirostat's surprise bound is carried from one
+/// token to the next, so a chain is used for a single generation.
+pub struct SamplerChain {
+    samplers: Vec<Sampler>,
+    rng: StdRng,
+    /// Mirostat's bound on the surprise of a token, starting at twice `tau`.
+    mu: Option<f64>,
+}
+
+impl SamplerChain {
+    pub fn new(samplers: Vec<Sampler>, seed: u64) -> Self {
+        let mu = samplers.iter().find_map(|sampler| match sampler {
+            Sampler::MirostatV2 { tau, .. } => Some(2. * tau),
+            _ => None,
+        });
+
+        Self {
+            samplers,
+            rng: StdRng::seed_from_u64(seed),
+            mu,
+        }
+    }
+
+    /// Mirostat's current surprise bound, if the chain has Mirostat.
+    pub fn mirostat_mu(&self) -> Option<f64> {
+        self.mu
+    }
+
+    /// Picks the next token from the logits of every token. Tokens with infinite negative
+    /// logits, such as those masked by a constraint, are never picked.
+    pub fn sample(&mut self, logits: &[f32]) -> Result<u32, String> {
+        let mut candidates: Vec<Candidate> = logits
+            .iter()
+            .enumerate()
+            .filter(|(_, logit)| logit.is_finite())
+            .map(|(token, &logit)| Candidate {
+                token: token as u32,
+                logit: logit as f64,
+                probability: 0.,
+            })
+            .collect();
+        if candidates.is_empty() {
+            return Err("No token can be sampled".to_string());
+        }
+        candidates.sort_by(|a, b| b.logit.total_cmp(&a.logit));
+
+        for index in 0..self.samplers.len() {
+            match self.samplers[index] {
+                Sampler::TopK { k } => candidates.truncate(k.max(1)),
+                Sampler::TopP { p } => {
+                    softmax(&mut candidates);
+                    let mut total = 0.;
+                    let keep = candidates
+                        .iter()
+                        .position(|candidate| {
+                            total += candidate.probability;
+                            total >= p
+                        })
+                        .map_or(candidates.len(), |index| index + 1);
+                    candidates.truncate(keep);
+                }
+                Sampler::MinP { p } => {
+                    softmax(&mut candidates);
+                    let threshold = p * candidates[0].probability;
+                    candidates.retain(|candidate| candidate.probability >= threshold);
+                }
+                Sampler::Typical { p } => typical(&mut candidates, p),
+                Sampler::TailFree { z } => tail_free(&mut candidates, z),
+                Sampler::TopA { a } => {
+                    softmax(&mut candidates);
+                    let threshold = a * candidates[0].probability.powi(2);
+                    candidates.retain(|candidate| candidate.probability >= threshold);
+                }
+                Sampler::Temperature { temperature } if temperature <= 0. => candidates.truncate(1),
+                Sampler::Temperature { temperature } => {
+                    for candidate in &mut candidates {
+                        candidate.logit /= temperature;
+                    }
+                }
+                Sampler::MirostatV2 { tau, eta } => return self.mirostat(candidates, tau, eta),
+            }
+        }
+
+        self.draw(&mut candidates).map(|candidate| candidate.token)
+    }
+
+    fn draw(&mut self, candidates: &mut [Candidate]) -> Result<Candidate, String> {
+        softmax(candidates);
+        let distribution = WeightedIndex::new(candidates.iter().map(|c| c.probability))
+            .map_err(|e| e.to_string())?;
+
+        Ok(candidates[distribution.sample(&mut self.rng)])
+    }
+
+    fn mirostat(
+        &mut self,
+        mut candidates: Vec<Candidate>,
+        tau: f64,
+        eta: f64,
+    ) -> Result<u32, String> {
+        let mu = self.mu.unwrap_or(2. * tau);
+
+        softmax(&mut candidates);
+        let keep = candidates
+            .iter()
+            .position(|candidate| -candidate.probability.log2() > mu)
+            .unwrap_or(candidates.len())
+            .max(1);
+        candidates.truncate(keep);
+
+        let picked = self.draw(&mut candidates)?;
+        let surprise = -picked.probability.log2();
+        self.mu = Some(mu - eta * (surprise - tau));
+
+        Ok(picked.token)
+    }
+}
+
+/// Sets the probabilities of candidates sorted by logit, most likely first.
+fn softmax(candidates: &mut [Candidate]) {
+    let max = candidates[0].logit;
+    let mut total = 0.;
+    for candidate in candidates.iter_mut() {
+        candidate.probability = (candidate.logit - max).exp();
+        total += candidate.probability;
+    }
+    for candidate in candidates.iter_mut() {
+        candidate.probability /= total;
+    }
+}
+
+fn typical(candidates: &mut Vec<Candidate>, p: f64) {
+    softmax(candidates);
+    let entropy: f64 = candidates
+        .iter()
+        .filter(|candidate| candidate.probability > 0.)
+        .map(|candidate| -candidate.probability * candidate.probability.ln())
+        .sum();
+
+    let distance = |candidate: &Candidate| (-candidate.probability.ln() - entropy).abs();
+    let mut typical = candidates.clone();
+    typical.sort_by(|a, b| distance(a).total_cmp(&distance(b)));
+
+    let mut total = 0.;
+    let keep = typical
+        .iter()
+        .position(|candidate| {
+            total += candidate.probability;
+            total >= p
+        })
+        .map_or(typical.len(), |index| index + 1);
+    typical.truncate(keep);
+
+    typical.sort_by(|a, b| b.logit.total_cmp(&a.logit));
+    *candidates = typical;
+}
+
+fn tail_free(candidates: &mut Vec<Candidate>, z: f64) {
+    if candidates.len() < 3 {
+        return;
+    }
+    softmax(candidates);
+
+    let first: Vec<f64> = candidates
+        .windows(2)
+        .map(|pair| pair[0].probability - pair[1].probability)
+        .collect();
+    let second: Vec<f64> = first
+        .windows(2)
+        .map(|pair| (pair[0] - pair[1]).abs())
+        .collect();
+    let total: f64 = second.iter().sum();
+    if total <= 0. {
+        return;
+    }
+
+    // The token after the last second derivative still below `z` is the last one kept
+    let mut cumulative = 0.;
+    let keep = second
+        .iter()
+        .position(|value| {
+            cumulative += value / total;
+            cumulative > z
+        })
+        .map_or(candidates.len(), |index| index + 1)
+        .max(1);
+    candidates.truncate(keep);
+}
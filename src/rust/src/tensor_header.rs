@@ -0,0 +1,168 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Bytes before the JSON header, holding its length as a little-endian `u64`.
+pub const HEADER_PREFIX_LEN: u64 = 8;
+
+/// Headers larger than this are rejected, like the reference implementation does.
+pub const MAX_HEADER_LEN: u64 = 100_000_000;
+
+/// One tensor of a safetensors file. Offsets are relative to the start of the data section.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TensorInfo {
+    pub name: String,
+    pub dtype: String,
+    pub shape: Vec<usize>,
+    pub data_offsets: (u64, u64),
+}
+
+impl TensorInfo {
+    /// Number of elements, or `None` when the shape is too large to count in a `u64`.
+    pub fn element_count(&self) -> Option<u64> {
+        self.shape
+            .iter()
+            .try_fold(1u64, |count, dim| count.checked_mul(*dim as u64))
+    }
+
+    /// Size of the data in `dtype_size` bytes per element, or `None` on overflow.
+    pub fn byte_len(&self, dtype_size: u64) -> Option<u64> {
+        self.element_count()?.checked_mul(dtype_size)
+    }
+}
+
+#[derive(Deserialize)]
+struct RawTensorInfo {
+    dtype: String,
+    shape: Vec<usize>,
+    data_offsets: (u64, u64),
+}
+
+/// Size in bytes of one element of a safetensors dtype.
+pub fn dtype_size(dtype: &str) -> Option<u64> {
+    match dtype {
+        "BOOL" | "U8" | "I8" | "F8_E5M2" | "F8_E4M3" => Some(1),
+        "U16" | "I16" | "F16" | "BF16" => Some(2),
+        "U32" | "I32" | "F32" => Some(4),
+        "U64" | "I64" | "F64" => Some(8),
+        _ => None,
+    }
+}
+
+/// Length of the JSON header, read from the first bytes of the file.
+pub fn header_len(prefix: &[u8]) -> Result<u64, String> {
+    let bytes: [u8; 8] = prefix
+        .get(..HEADER_PREFIX_LEN as usize)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| "File is too short to be safetensors".to_string())?;
+    let len = u64::from_le_bytes(bytes);
+
+    if len > MAX_HEADER_LEN {
+        return Err(format!("Safetensors header of {} bytes is too large", len));
+    }
+
+    Ok(len)
+}
+
+/// Parses the JSON header of a file of `file_size` bytes and checks that every tensor lies
+/// within the file, matches its dtype and shape, and that the data section is complete.
+/// Tensors are returned in file order.
+pub fn parse_header(header: &[u8], file_size: u64) -> Result<Vec<TensorInfo>, String> {
+    let data_len = file_size
+        .checked_sub(HEADER_PREFIX_LEN + header.len() as u64)
+        .ok_or_else(|| "Safetensors header extends past the end of the file".to_string())?;
+
+    let entries: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_slice(header).map_err(|e| format!("Invalid safetensors header: {}", e))?;
+
+    let mut tensors = Vec::with_capacity(entries.len());
+    for (name, value) in entries {
+        if name == "__metadata__" {
+            continue;
+        }
+
+        let raw: RawTensorInfo = serde_json::from_value(value)
+            .map_err(|e| format!("Invalid entry for tensor {}: {}", name, e))?;
+        let tensor = TensorInfo {
+            name,
+            dtype: raw.dtype,
+            shape: raw.shape,
+            data_offsets: raw.data_offsets,
+        };
+
+        let (begin, end) = tensor.data_offsets;
+        let size = dtype_size(&tensor.dtype).ok_or_else(|| {
+            format!(
+                "Unsupported dtype {} of tensor {}",
+                tensor.dtype, tensor.name
+            )
+        })?;
+
+        if begin > end || end > data_len {
+            return Err(format!("Tensor {} lies outside of the file", tensor.name));
+        }
+
+        if tensor.byte_len(size) != Some(end - begin) {
+            return Err(format!(
+                "Tensor {} does not match its shape and dtype",
+                tensor.name
+            ));
+        }
+
+        tensors.push(tensor);
+    }
+
+    tensors.sort_by_key(|tensor| tensor.data_offsets);
+
+    let covered = tensors.last().map_or(0, |tensor| tensor.data_offsets.1);
+    if covered != data_len {
+        return Err(format!(
+            "Safetensors data is {} bytes but the tensors cover {}",
+            data_len, covered
+        ));
+    }
+
+    Ok(tensors)
+}
+
+/// What the header tells about a file before its weights are downloaded.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HeaderSummary {
+    pub tensors: Vec<TensorInfo>,
+    pub parameter_count: u64,
+    /// Size of the whole file, header included.
+    pub file_size: u64,
+    /// Number of parameters stored in each dtype.
+    pub dtypes: BTreeMap<String, u64>,
+    /// Free-form `__metadata__` of the header, such as `{"format": "pt"}`.
+    pub metadata: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct RawMetadata {
+    #[serde(rename = "__metadata__", default)]
+    metadata: BTreeMap<String, String>,
+}
+
+/// Parses and checks the header like [`parse_header`] and totals it up.
+pub fn summarize_header(header: &[u8], file_size: u64) -> Result<HeaderSummary, String> {
+    let tensors = parse_header(header, file_size)?;
+    let metadata = serde_json::from_slice::<RawMetadata>(header)
+        .map(|raw| raw.metadata)
+        .unwrap_or_default();
+
+    let mut dtypes = BTreeMap::new();
+    for tensor in &tensors {
+        // Checked by `parse_header` to fit within the file
+        *dtypes.entry(tensor.dtype.clone()).or_insert(0) +=
+            tensor.element_count().unwrap_or_default();
+    }
+
+    Ok(HeaderSummary {
+        parameter_count: dtypes.values().sum(),
+        tensors,
+        file_size,
+        dtypes,
+        metadata,
+    })
+}
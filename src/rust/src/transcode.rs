@@ -0,0 +1,273 @@
+use candle_core::{DType, Device, Tensor};
+use js_sys::Uint8Array;
+use serde_json::{json, Map, Value};
+use wasm_bindgen::prelude::*;
+use web_sys::{IdbDatabase, IdbTransactionMode};
+
+use crate::cache::CacheEntry;
+use crate::coordination::{coordination_name, KeyLock};
+use crate::downloader::{Downloader, METADATA_STORE_NAME, STORE_NAME};
+use crate::loader::{candle_dtype, safetensors_dtype};
+use crate::repository::repository_key;
+use crate::tensor_header::{self, TensorInfo, HEADER_PREFIX_LEN};
+
+/// Metadata entry naming the dtype a file was transcoded to.
+pub const TRANSCODED_METADATA_KEY: &str = "transcoded_to";
+
+/// Layout of a safetensors file after transcoding.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TranscodePlan {
+    /// Length prefix and JSON header of the transcoded file.
+    pub header: Vec<u8>,
+    /// Every tensor as stored in the source file and in the transcoded one, in file order.
+    pub tensors: Vec<(TensorInfo, TensorInfo)>,
+    pub file_size: u64,
+}
+
+impl TranscodePlan {
+    /// Whether any tensor is stored in another dtype, so the file needs rewriting at all.
+    pub fn changes_dtypes(&self) -> bool {
+        self.tensors
+            .iter()
+            .any(|(source, target)| source.dtype != target.dtype)
+    }
+
+    /// Fails when the transcoded file is too large for a JS array, whose lengths and
+    /// offsets are 32 bits wide.
+    pub fn check_size(&self) -> Result<(), String> {
+        if self.file_size > u32::MAX as u64 {
+            return Err(format!(
+                "the transcoded file takes {} bytes, more than an array can hold",
+                self.file_size
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Plans the file that stores the floating point tensors of a file of `file_size` bytes in
+/// `dtype`. Other tensors and the metadata are kept, and the tensors keep their order.
+pub fn plan_transcode(
+    header: &[u8],
+    file_size: u64,
+    dtype: DType,
+) -> Result<TranscodePlan, String> {
+    let summary = tensor_header::summarize_header(header, file_size)?;
+    let target_dtype =
+        safetensors_dtype(dtype).ok_or_else(|| format!("Cannot transcode to {:?}", dtype))?;
+
+    let mut metadata = summary.metadata;
+    metadata.insert(
+        TRANSCODED_METADATA_KEY.to_string(),
+        target_dtype.to_string(),
+    );
+
+    let mut entries = Map::new();
+    entries.insert("__metadata__".to_string(), json!(metadata));
+
+    let mut offset = 0u64;
+    let mut tensors = Vec::with_capacity(summary.tensors.len());
+    for source in summary.tensors {
+        let is_float = candle_dtype(&source.dtype).is_some_and(|dtype| dtype.is_float());
+        let dtype = if is_float {
+            target_dtype.to_string()
+        } else {
+            source.dtype.clone()
+        };
+
+        // The header was validated, so every dtype left has a known size
+        let end = source
+            .byte_len(tensor_header::dtype_size(&dtype).unwrap_or(0))
+            .and_then(|size| offset.checked_add(size))
+            .ok_or_else(|| format!("Tensor {} is too large to transcode", source.name))?;
+        let target = TensorInfo {
+            name: source.name.clone(),
+            dtype,
+            shape: source.shape.clone(),
+            data_offsets: (offset, end),
+        };
+        offset = end;
+
+        entries.insert(
+            target.name.clone(),
+            json!({
+                "dtype": target.dtype,
+                "shape": target.shape,
+                "data_offsets": [target.data_offsets.0, target.data_offsets.1],
+            }),
+        );
+        tensors.push((source, target));
+    }
+
+    let mut json = serde_json::to_vec(&Value::Object(entries)).map_err(|e| e.to_string())?;
+    // Padded with spaces so the data section starts 8-byte aligned, like other writers do
+    json.resize(json.len().next_multiple_of(8), b' ');
+
+    let mut header = (json.len() as u64).to_le_bytes().to_vec();
+    header.extend_from_slice(&json);
+
+    Ok(TranscodePlan {
+        file_size: header.len() as u64 + offset,
+        header,
+        tensors,
+    })
+}
+
+/// Converts the bytes of one tensor from its source to its target dtype.
+pub fn transcode_tensor(
+    source: &TensorInfo,
+    target: &TensorInfo,
+    bytes: &[u8],
+) -> Result<Vec<u8>, String> {
+    if source.dtype == target.dtype {
+        return Ok(bytes.to_vec());
+    }
+
+    let error = |e: candle_core::Error| format!("Cannot transcode tensor {}: {}", source.name, e);
+    let dtype = |name: &str| {
+        candle_dtype(name).ok_or_else(|| format!("Unsupported dtype {} of {}", name, source.name))
+    };
+
+    let tensor = Tensor::from_raw_buffer(bytes, dtype(&source.dtype)?, &source.shape, &Device::Cpu)
+        .map_err(error)?
+        .to_dtype(dtype(&target.dtype)?)
+        .map_err(error)?;
+
+    let mut converted =
+        Vec::with_capacity((target.data_offsets.1 - target.data_offsets.0) as usize);
+    tensor.write_bytes(&mut converted).map_err(error)?;
+
+    Ok(converted)
+}
+
+/// Rewrites a cached safetensors file with its floating point tensors in `dtype` and
+/// replaces the cached copy. Both files stay in JS memory; only tensors that change dtype
+/// pass through the wasm heap, one at a time. Resolves to whether the file was rewritten,
+/// which it is not when it already is in `dtype` or another context is downloading it.
+pub(crate) async fn transcode_cached(
+    repository: &str,
+    filename: &str,
+    dtype: DType,
+) -> Result<bool, JsValue> {
+    let error = |e: String| JsValue::from_str(&format!("{}: {}", filename, e));
+    let key = repository_key(repository, filename);
+    let target_dtype = safetensors_dtype(dtype)
+        .ok_or_else(|| error(format!("Cannot transcode to {:?}", dtype)))?;
+
+    let Some(_lock) = KeyLock::try_acquire(&coordination_name(&key)).await? else {
+        return Ok(false);
+    };
+
+    let db = Downloader::open_db().await?;
+    let entry = Downloader::read_entry(&db, &key)
+        .await?
+        .ok_or_else(|| error("not cached".to_string()))?;
+    if entry.dtype.as_deref() == Some(target_dtype) {
+        return Ok(false);
+    }
+
+    let source = Downloader::get(&key)
+        .await
+        .ok_or_else(|| error("not cached".to_string()))?;
+    let prefix_len = HEADER_PREFIX_LEN as u32;
+    let header_len =
+        tensor_header::header_len(&source.subarray(0, prefix_len).to_vec()).map_err(error)? as u32;
+    let data_start = prefix_len + header_len;
+    if data_start > source.length() {
+        return Err(error("header extends past the end of the file".to_string()));
+    }
+
+    let header = source.subarray(prefix_len, data_start).to_vec();
+    let plan = plan_transcode(&header, source.length() as u64, dtype).map_err(error)?;
+    plan.check_size().map_err(error)?;
+
+    let transaction = |db: &IdbDatabase| {
+        Downloader::transaction(
+            db,
+            &[STORE_NAME, METADATA_STORE_NAME],
+            IdbTransactionMode::Readwrite,
+        )
+    };
+
+    if !plan.changes_dtypes() {
+        // Only recorded so the header is not checked again, the write is not waited for
+        let entry = CacheEntry {
+            dtype: Some(target_dtype.to_string()),
+            ..entry
+        };
+        Downloader::put_entry(&transaction(&db)?, &entry)?;
+        return Ok(false);
+    }
+
+    let content = write_plan(&plan, &source, data_start)?;
+    drop(source);
+
+    if plan.file_size > entry.size {
+        Downloader::new(repository)
+            .ensure_capacity(&key, plan.file_size - entry.size)
+            .await?;
+    }
+
+    let entry = CacheEntry {
+        size: plan.file_size,
+        dtype: Some(target_dtype.to_string()),
+        ..entry
+    };
+
+    let transaction = transaction(&db)?;
+    let request = transaction
+        .object_store(STORE_NAME)?
+        .put_with_key(&content, &JsValue::from_str(&key))?;
+    Downloader::put_entry(&transaction, &entry)?;
+    Downloader::idbrequest_to_result::<JsValue>(&request).await?;
+
+    Ok(true)
+}
+
+/// Writes the transcoded file into a new JS array. Tensors that keep their dtype are copied
+/// from `source` without entering the wasm heap.
+fn write_plan(
+    plan: &TranscodePlan,
+    source: &Uint8Array,
+    data_start: u32,
+) -> Result<Uint8Array, JsValue> {
+    let error = |e: String| JsValue::from_str(&e);
+    // Offsets past u32::MAX would wrap and write over other tensors
+    let offset = |base: u32, offset: u64| {
+        u32::try_from(offset)
+            .ok()
+            .and_then(|offset| base.checked_add(offset))
+            .ok_or_else(|| {
+                error(format!(
+                    "Offset {} is beyond what an array can hold",
+                    offset
+                ))
+            })
+    };
+
+    plan.check_size().map_err(error)?;
+    let output = Uint8Array::new_with_length(plan.file_size as u32);
+    let header_len = plan.header.len() as u32;
+    output.subarray(0, header_len).copy_from(&plan.header);
+
+    for (source_info, target_info) in &plan.tensors {
+        let begin = offset(data_start, source_info.data_offsets.0)?;
+        let end = offset(data_start, source_info.data_offsets.1)?;
+        let target_begin = offset(header_len, target_info.data_offsets.0)?;
+        let target_end = offset(header_len, target_info.data_offsets.1)?;
+
+        let bytes = source.subarray(begin, end);
+        if source_info.dtype == target_info.dtype {
+            output.set(&bytes, target_begin);
+        } else {
+            let converted =
+                transcode_tensor(source_info, target_info, &bytes.to_vec()).map_err(error)?;
+            output
+                .subarray(target_begin, target_end)
+                .copy_from(&converted);
+        }
+    }
+
+    Ok(output)
+}
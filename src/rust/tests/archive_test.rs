@@ -0,0 +1,80 @@
+use gh_pages_rust::archive::{
+    check_index, padding, parse_tar_header, read_tar, sha256_hex, tar_header, write_tar,
+    ArchiveIndex, ArchivedFile, ARCHIVE_VERSION, BLOCK_SIZE,
+};
+use gh_pages_rust::cache::CacheEntry;
+
+#[test]
+fn test_tar_round_trip() -> Result<(), String> {
+    let config = br#"{"model_type":"llama"}"#;
+    let weights = vec![7u8; 1300];
+    let archive = write_tar(
+        &[
+            ("files/config.json", config),
+            ("files/model.safetensors", &weights),
+        ],
+        1_700_000_000,
+    )?;
+
+    assert_eq!(archive.len() % BLOCK_SIZE, 0);
+
+    let members = read_tar(&archive)?;
+    assert_eq!(members.len(), 2);
+    assert_eq!(members[0].0, "files/config.json");
+    assert_eq!(&archive[members[0].1.clone()], config);
+    assert_eq!(&archive[members[1].1.clone()], &weights[..]);
+
+    Ok(())
+}
+
+#[test]
+fn test_long_paths_use_the_prefix_field() -> Result<(), String> {
+    let path = format!("files/{}/model.safetensors", "nested".repeat(20));
+    let header = tar_header(&path, 3, 0)?;
+
+    assert_eq!(parse_tar_header(&header)?.unwrap().path, path);
+    assert!(tar_header(&"x".repeat(300), 3, 0).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_corrupt_header_is_rejected() -> Result<(), String> {
+    let mut header = tar_header("files/config.json", 10, 0)?;
+    header[0] = b'X';
+
+    assert!(parse_tar_header(&header).is_err());
+    assert_eq!(parse_tar_header(&[0; BLOCK_SIZE])?, None);
+    assert_eq!(padding(512), 0);
+    assert_eq!(padding(513), 511);
+
+    Ok(())
+}
+
+#[test]
+fn test_index_must_match_members() {
+    let index = ArchiveIndex {
+        version: ARCHIVE_VERSION,
+        repository: "timinar/baby-llama-58m".to_string(),
+        manifest: None,
+        files: vec![ArchivedFile {
+            filename: "config.json".to_string(),
+            sha256: sha256_hex(b"{}"),
+            entry: CacheEntry::new(
+                "timinar/baby-llama-58m/config.json",
+                2,
+                "timinar/baby-llama-58m",
+                "main",
+                0.0,
+            ),
+        }],
+    };
+
+    assert!(check_index(&index, &[("files/config.json".to_string(), 2)]).is_ok());
+    assert!(check_index(&index, &[("files/config.json".to_string(), 3)]).is_err());
+    assert!(check_index(&index, &[]).is_err());
+    assert_eq!(
+        sha256_hex(b""),
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+}
@@ -0,0 +1,111 @@
+use gh_pages_rust::beam::{self, BeamSearch, Hypothesis};
+use gh_pages_rust::generator::GenerationArguments;
+
+const EOS: u32 = 0;
+const A: u32 = 1;
+const B: u32 = 2;
+
+/// "A" is the most likely first token, but "B" is nearly always followed by EOS, so "B"
+/// alone is the most likely sequence.
+fn next_token_logprobs(tokens: &[u32]) -> Vec<f64> {
+    let probabilities: [f64; 3] = match tokens {
+        [] => [0., 0.6, 0.4],
+        [.., B] => [0.9, 0.05, 0.05],
+        _ => [0.34, 0.33, 0.33],
+    };
+    probabilities.iter().map(|p| p.ln()).collect()
+}
+
+fn run(mut search: BeamSearch, max_len: usize, count: usize) -> Vec<Hypothesis> {
+    for _ in 0..max_len {
+        let logprobs: Vec<Vec<f64>> = search
+            .beams()
+            .iter()
+            .map(|beam| next_token_logprobs(&beam.tokens))
+            .collect();
+        let parents = search.step(&logprobs);
+        assert_eq!(parents.len(), search.beams().len());
+        if search.is_done() {
+            break;
+        }
+    }
+
+    search.finish(count)
+}
+
+#[test]
+fn test_beam_search_beats_greedy() {
+    // One beam is greedy decoding
+    let greedy = run(BeamSearch::new(1, 0., false, vec![EOS]), 4, 1);
+    assert_eq!(greedy[0].tokens, vec![A]);
+
+    let beams = run(BeamSearch::new(2, 0., false, vec![EOS]), 4, 2);
+    assert_eq!(beams.len(), 2);
+    assert_eq!(beams[0].tokens, vec![B]);
+    assert!((beams[0].score - (0.4f64 * 0.9).ln()).abs() < 1e-9);
+    assert!(beams[0].score > beams[1].score);
+}
+
+#[test]
+fn test_length_penalty_and_early_stopping() {
+    // Scores are divided by the length, EOS included
+    let normalized = run(BeamSearch::new(2, 1., false, vec![EOS]), 4, 1);
+    assert_eq!(normalized[0].tokens, vec![B]);
+    assert!((normalized[0].score - (0.4f64 * 0.9).ln() / 2.).abs() < 1e-9);
+
+    // Without an EOS token, every beam runs to the end
+    let unfinished = run(BeamSearch::new(2, 0., false, Vec::new()), 3, 2);
+    assert!(unfinished
+        .iter()
+        .all(|hypothesis| hypothesis.tokens.len() == 3));
+
+    // With early stopping, the search ends once it has num_beams finished sequences
+    let mut search = BeamSearch::new(1, 1., true, vec![EOS]);
+    search.step(&[vec![0., f64::NEG_INFINITY, -1.]]);
+    assert!(search.is_done());
+    let finished = search.finish(1);
+    assert_eq!(finished[0].tokens, Vec::<u32>::new());
+
+    let logprobs = beam::log_softmax(&[0., 0., f32::NEG_INFINITY]);
+    assert!((logprobs[0] - 0.5f64.ln()).abs() < 1e-9);
+    assert_eq!(logprobs[2], f64::NEG_INFINITY);
+}
+
+#[test]
+fn test_non_finite_logprobs_end_with_nothing() {
+    // NaN or all -inf logits leave no token to pick, so no beam survives the first step
+    let mut search = BeamSearch::new(2, 1., false, vec![EOS]);
+    let parents = search.step(&[vec![f64::NAN, f64::NEG_INFINITY, f64::NAN]]);
+
+    assert!(parents.is_empty());
+    assert!(search.is_done());
+    assert!(search.finish(1).is_empty());
+}
+
+#[test]
+fn test_beam_arguments_are_checked() {
+    let too_many = GenerationArguments {
+        num_beams: Some(2),
+        num_return_sequences: Some(3),
+        ..GenerationArguments::new()
+    };
+    let error = too_many.check().unwrap_err();
+    assert!(error.contains("num_return_sequences must be at most num_beams"));
+
+    let constrained = GenerationArguments {
+        num_beams: Some(4),
+        regex: Some("yes|no".to_string()),
+        ..GenerationArguments::new()
+    };
+    assert!(constrained.check().unwrap_err().contains("Beam search"));
+
+    let arguments = GenerationArguments::from_json(
+        r#"{"num_beams": 4, "length_penalty": 0.6, "early_stopping": true}"#,
+    )
+    .unwrap();
+    let internal = arguments.get_internal().unwrap();
+    assert_eq!(internal.num_beams, 4);
+    assert_eq!(internal.length_penalty, 0.6);
+    assert!(internal.early_stopping);
+    assert_eq!(internal.num_return_sequences, 1);
+}
@@ -22,7 +22,6 @@ async fn test_wasm_api_download() -> Result<(), JsValue> {
 
 #[wasm_bindgen_test]
 async fn test_generator() -> Result<(), JsValue> {
-    use gh_pages_rust::generator::GeneratorCallback;
     use gh_pages_rust::{downloader::Downloader, generator::Generator};
 
     let downloader = Downloader::new("timinar/baby-llama-58m");
@@ -42,10 +41,37 @@ async fn test_generator() -> Result<(), JsValue> {
         .start()
         .await?;
 
-    let generator = Generator::new(model.to_vec(), tokenizer.to_vec(), config.to_vec(), None);
-    let output = generator.generate("Once upon a time, ", None, None); // TODO: proper callback
+    let generator = Generator::new(model.to_vec(), tokenizer.to_vec(), config.to_vec(), None)?;
+    let output = generator.generate("Once upon a time, ", None, None)?; // TODO: proper callback
 
     println!("{output}");
 
     Ok(())
 }
+
+#[wasm_bindgen_test]
+async fn test_open_db_applies_migrations() -> Result<(), JsValue> {
+    use gh_pages_rust::downloader::Downloader;
+
+    // Any store access opens the database and runs the upgrade path on a fresh profile
+    assert!(Downloader::get("missing").await.is_none());
+    Downloader::storage_usage().await?;
+
+    Ok(())
+}
+
+#[wasm_bindgen_test]
+async fn test_repository_download() -> Result<(), JsValue> {
+    use gh_pages_rust::repository::RepositoryDownload;
+
+    let repository = "timinar/baby-llama-58m";
+
+    let contents = RepositoryDownload::new(repository, None).start().await?;
+    assert_eq!(contents.length(), 3);
+    assert!(RepositoryDownload::is_complete(repository).await);
+
+    assert!(RepositoryDownload::remove_repository(repository).await);
+    assert!(!RepositoryDownload::is_complete(repository).await);
+
+    Ok(())
+}
@@ -0,0 +1,22 @@
+use gh_pages_rust::buffer::ByteBuffer;
+
+#[test]
+fn test_byte_buffer_owns_bytes() {
+    let buffer = ByteBuffer::from(vec![1, 2, 3]);
+
+    assert_eq!(buffer.len(), 3);
+    assert!(!buffer.is_empty());
+    assert_eq!(buffer.as_slice(), &[1, 2, 3]);
+    assert_eq!(buffer.into_inner(), vec![1, 2, 3]);
+}
+
+#[test]
+fn test_byte_buffer_reserve_and_clear() {
+    let mut buffer = ByteBuffer::from(vec![1, 2, 3]);
+
+    assert!(buffer.reserve(1024).is_ok());
+    assert_eq!(buffer.len(), 3);
+
+    buffer.clear();
+    assert!(buffer.is_empty());
+}
@@ -0,0 +1,101 @@
+use gh_pages_rust::cache::{self, CacheEntry, EvictionPolicy};
+
+const MB: u64 = 1024 * 1024;
+
+fn entry(key: &str, size: u64, last_accessed: f64) -> CacheEntry {
+    let mut entry = CacheEntry::new(key, size, "timinar/baby-llama-58m", "main", 0.0);
+    entry.last_accessed = last_accessed;
+    entry
+}
+
+#[test]
+fn test_no_eviction_when_download_fits() {
+    let policy = EvictionPolicy::new();
+    let entries = vec![entry("model", 200 * MB, 1.0)];
+
+    let evicted = policy.select_evictions(&entries, 100 * MB, 200 * MB, 1000 * MB, &[]);
+    assert!(evicted.is_empty());
+}
+
+#[test]
+fn test_evicts_least_recently_used_first() {
+    let policy = EvictionPolicy {
+        enabled: true,
+        max_bytes: None,
+        headroom: 0.0,
+    };
+    let entries = vec![
+        entry("recent", 300 * MB, 30.0),
+        entry("oldest", 300 * MB, 10.0),
+        entry("older", 300 * MB, 20.0),
+    ];
+
+    let evicted = policy.select_evictions(&entries, 500 * MB, 900 * MB, 1000 * MB, &[]);
+    assert_eq!(evicted, vec!["oldest".to_string(), "older".to_string()]);
+}
+
+#[test]
+fn test_max_bytes_and_protected_keys() {
+    let policy = EvictionPolicy {
+        enabled: true,
+        max_bytes: Some((500 * MB) as f64),
+        headroom: 0.0,
+    };
+    let entries = vec![
+        entry("model", 300 * MB, 1.0),
+        entry("tokenizer", 100 * MB, 2.0),
+    ];
+
+    let evicted = policy.select_evictions(&entries, 300 * MB, 400 * MB, 10_000 * MB, &["model"]);
+    assert!(
+        evicted.is_empty(),
+        "cannot make room without the protected key"
+    );
+
+    let evicted = policy.select_evictions(&entries, 150 * MB, 400 * MB, 10_000 * MB, &["model"]);
+    assert_eq!(evicted, vec!["tokenizer".to_string()]);
+}
+
+#[test]
+fn test_sibling_keys_are_protected() {
+    let policy = EvictionPolicy {
+        enabled: true,
+        max_bytes: Some((1000 * MB) as f64),
+        headroom: 0.0,
+    };
+    let other = CacheEntry::new(
+        "other/model/model.safetensors",
+        300 * MB,
+        "other/model",
+        "main",
+        0.0,
+    );
+    let entries = vec![
+        entry("timinar/baby-llama-58m/config.json", MB, 1.0),
+        entry("timinar/baby-llama-58m/tokenizer.json", 100 * MB, 2.0),
+        other,
+    ];
+
+    let protected = cache::protected_keys(
+        &entries,
+        "timinar/baby-llama-58m/model.safetensors",
+        &["timinar/baby-llama-58m"],
+    );
+    assert_eq!(protected.len(), 3);
+
+    let evicted = policy.select_evictions(&entries, 800 * MB, 401 * MB, 10_000 * MB, &protected);
+    assert_eq!(evicted, vec!["other/model/model.safetensors".to_string()]);
+
+    let evicted = policy.select_evictions(&entries, 950 * MB, 401 * MB, 10_000 * MB, &protected);
+    assert!(evicted.is_empty(), "siblings are never evicted");
+}
+
+#[test]
+fn test_disabled_policy_never_evicts() {
+    let mut policy = EvictionPolicy::new();
+    policy.enabled = false;
+    let entries = vec![entry("model", 300 * MB, 1.0)];
+
+    let evicted = policy.select_evictions(&entries, 900 * MB, 900 * MB, 1000 * MB, &[]);
+    assert!(evicted.is_empty());
+}
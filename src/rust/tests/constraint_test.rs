@@ -0,0 +1,80 @@
+use gh_pages_rust::constraint::{Constrained, TokenConstraint, Vocabulary};
+use gh_pages_rust::grammar::Grammar;
+use tokenizers::Tokenizer;
+
+fn vocabulary() -> Vocabulary {
+    let tokens = [
+        "", "{", "}", "\"a\"", ":", " ", "1", "12", "{\"", "a\":", "\u{e9}",
+    ];
+    let mut tokens: Vec<Vec<u8>> = tokens.iter().map(|t| t.as_bytes().to_vec()).collect();
+    // The two bytes of "é" as separate tokens
+    tokens.push(vec![0xC3]);
+    tokens.push(vec![0xA9]);
+
+    Vocabulary::from_tokens(tokens)
+}
+
+#[test]
+fn test_allowed_tokens_follow_the_grammar() {
+    let vocabulary = vocabulary();
+    let grammar = Grammar::parse(r#"root ::= "{" "\"a\":" " "? [0-9]+ "}""#).unwrap();
+    let mut constraint = Constrained::new(grammar, &vocabulary);
+
+    let allowed = constraint.allowed();
+    assert!(allowed.contains(1) && allowed.contains(8));
+    assert_eq!(allowed.len(), 2);
+
+    constraint.accept(8).unwrap();
+    let allowed = constraint.allowed();
+    assert!(allowed.contains(9));
+    assert_eq!(allowed.len(), 1);
+    assert!(constraint.accept(3).is_err());
+
+    for token in [9, 5, 7] {
+        constraint.accept(token).unwrap();
+    }
+    assert!(!constraint.is_complete());
+    let allowed = constraint.allowed();
+    assert!(allowed.contains(2) && allowed.contains(6) && allowed.contains(7));
+    assert!(!allowed.contains(5));
+
+    constraint.accept(2).unwrap();
+    assert!(constraint.is_complete());
+    assert!(constraint.allowed().is_empty());
+}
+
+#[test]
+fn test_characters_split_across_tokens() {
+    let vocabulary = vocabulary();
+    let grammar = Grammar::parse(r#"root ::= [^a-z]"#).unwrap();
+    let mut constraint = Constrained::new(grammar, &vocabulary);
+
+    // The first byte alone could still become an allowed character
+    assert!(constraint.allowed().contains(11));
+    assert!(!constraint.allowed().contains(12));
+    constraint.accept(11).unwrap();
+    assert!(!constraint.is_complete());
+    constraint.accept(12).unwrap();
+    assert!(constraint.is_complete());
+}
+
+#[test]
+fn test_vocabulary_from_sentencepiece_tokenizer() {
+    let tokenizer = Tokenizer::from_bytes(
+        r#"{
+            "version": "1.0",
+            "added_tokens": [{"id": 0, "content": "</s>", "single_word": false, "lstrip": false,
+                "rstrip": false, "normalized": false, "special": true}],
+            "model": {"type": "WordLevel", "vocab": {"</s>": 0, "▁{": 1, "<0x0A>": 2, "a": 3},
+                "unk_token": "</s>"}
+        }"#,
+    )
+    .unwrap();
+    let vocabulary = Vocabulary::new(&tokenizer);
+
+    assert_eq!(vocabulary.len(), 4);
+    assert!(vocabulary.token_bytes(0).is_empty());
+    assert_eq!(vocabulary.token_bytes(1), b" {");
+    assert_eq!(vocabulary.token_bytes(2), b"\n");
+    assert_eq!(vocabulary.token_bytes(3), b"a");
+}
@@ -0,0 +1,107 @@
+use gh_pages_rust::generator::{GenerationArguments, PRESETS};
+
+#[test]
+fn test_json_round_trip() {
+    let arguments = GenerationArguments {
+        seed: u64::MAX,
+        eos_token_ids: Some(vec![2, 32000]),
+        ..GenerationArguments::preset("creative").unwrap()
+    };
+
+    let json = arguments.to_json().unwrap();
+    assert_eq!(GenerationArguments::from_json(&json).unwrap(), arguments);
+
+    // Missing fields take the defaults
+    let partial = GenerationArguments::from_json(r#"{"temperature": 0.2}"#).unwrap();
+    assert_eq!(
+        partial,
+        GenerationArguments {
+            temperature: Some(0.2),
+            ..GenerationArguments::new()
+        }
+    );
+    assert!(serde_json::from_str::<GenerationArguments>(r#"{"temprature": 0.2}"#).is_err());
+}
+
+#[test]
+fn test_out_of_range_values_are_rejected() {
+    let invalid = GenerationArguments {
+        top_k: Some(0),
+        top_p: Some(-0.5),
+        ..GenerationArguments::new()
+    };
+    let error = invalid.check().unwrap_err();
+    assert!(error.contains("top_k must be at least 1"), "{}", error);
+    assert!(
+        error.contains("top_p must be in (0, 1], got -0.5"),
+        "{}",
+        error
+    );
+
+    let negative = GenerationArguments {
+        temperature: Some(-1.),
+        ..GenerationArguments::new()
+    };
+    assert!(negative.check().is_err());
+
+    assert!(GenerationArguments::new().check().is_ok());
+}
+
+#[test]
+fn test_grammar_arguments() {
+    let schema = GenerationArguments {
+        json_schema: Some(r#"{"type": "boolean"}"#.to_string()),
+        ..GenerationArguments::new()
+    };
+    assert!(schema.check().is_ok());
+    // The checked arguments carry the compiled constraint
+    let constraint = schema.get_internal().unwrap().constraint.unwrap();
+    assert!(constraint.matches("true"));
+
+    let both = GenerationArguments {
+        grammar: Some(r#"root ::= "x""#.to_string()),
+        ..schema.clone()
+    };
+    assert!(both.check().unwrap_err().contains("Only one of"));
+
+    let invalid = GenerationArguments {
+        grammar: Some("root ::= missing".to_string()),
+        ..GenerationArguments::new()
+    };
+    assert!(invalid
+        .check()
+        .unwrap_err()
+        .contains("missing is not defined"));
+
+    let regex = GenerationArguments {
+        regex: Some(r"\d{4}-\d{2}-\d{2}".to_string()),
+        ..GenerationArguments::new()
+    };
+    let constraint = regex.get_internal().unwrap().constraint.unwrap();
+    assert!(constraint.matches("2024-02-29"));
+    assert!(!constraint.matches("2024-2-29"));
+
+    // Reported once, along with the other errors
+    let invalid = GenerationArguments {
+        regex: Some("(unclosed".to_string()),
+        top_k: Some(0),
+        ..GenerationArguments::new()
+    };
+    let error = invalid.get_internal().err().unwrap();
+    assert_eq!(error.matches("Invalid regex").count(), 1);
+    assert!(error.contains("top_k"));
+}
+
+#[test]
+fn test_presets() {
+    for name in PRESETS {
+        let preset = GenerationArguments::preset(name).unwrap();
+        assert!(preset.check().is_ok(), "{}", name);
+    }
+
+    let greedy = GenerationArguments::preset("greedy").unwrap();
+    assert_eq!(greedy.temperature, Some(0.));
+    assert_eq!(greedy.get_internal().unwrap().temperature, 0.);
+
+    assert!(GenerationArguments::preset("wild").is_err());
+}
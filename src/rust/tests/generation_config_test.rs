@@ -0,0 +1,61 @@
+use gh_pages_rust::generation_config::GenerationConfig;
+use gh_pages_rust::generator::GenerationArguments;
+
+#[test]
+fn test_generation_config_overrides_defaults() {
+    let arguments = GenerationArguments::from_generation_config(
+        br#"{
+            "bos_token_id": 1,
+            "eos_token_id": [2, 32000],
+            "do_sample": true,
+            "temperature": 0.6,
+            "top_p": 0.9,
+            "max_new_tokens": 256,
+            "transformers_version": "4.40.0"
+        }"#,
+    )
+    .unwrap();
+
+    assert_eq!(arguments.temperature, Some(0.6));
+    assert_eq!(arguments.top_p, Some(0.9));
+    assert_eq!(arguments.sample_len, Some(256));
+    assert_eq!(arguments.eos_token_ids, Some(vec![2, 32000]));
+
+    // Fields the file leaves out keep the defaults
+    let defaults = GenerationArguments::new();
+    assert_eq!(arguments.top_k, defaults.top_k);
+    assert_eq!(arguments.repeat_penalty, defaults.repeat_penalty);
+    assert_eq!(arguments.seed, defaults.seed);
+
+    assert_eq!(
+        arguments.get_internal().unwrap().eos_token_ids,
+        vec![2, 32000]
+    );
+}
+
+#[test]
+fn test_disabled_sampling_values() {
+    let config = GenerationConfig::from_slice(
+        br#"{"do_sample": false, "temperature": 0.7, "top_k": 0, "top_p": 1.0, "eos_token_id": 2}"#,
+    )
+    .unwrap();
+    let arguments = GenerationArguments::new().with_generation_config(&config);
+
+    assert_eq!(arguments.temperature, Some(0.));
+    assert_eq!(arguments.top_k, None);
+    assert_eq!(arguments.top_p, None);
+    assert_eq!(arguments.eos_token_ids, Some(vec![2]));
+}
+
+#[test]
+fn test_invalid_generation_config() {
+    assert!(GenerationConfig::from_slice(b"{\"temperature\": \"hot\"}").is_err());
+
+    let empty = GenerationConfig::from_slice(b"{}").unwrap();
+    let arguments = GenerationArguments::new().with_generation_config(&empty);
+    assert_eq!(
+        arguments.temperature,
+        GenerationArguments::new().temperature
+    );
+    assert_eq!(arguments.eos_token_ids, None);
+}
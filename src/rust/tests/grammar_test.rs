@@ -0,0 +1,70 @@
+use gh_pages_rust::grammar::{Grammar, MAX_REPETITIONS};
+use gh_pages_rust::json_schema::json_schema_to_gbnf;
+use serde_json::json;
+
+#[test]
+fn test_gbnf_grammar() {
+    let grammar = Grammar::parse(
+        r#"
+        # A list of answers
+        root ::= answer ("," " "? answer){0,2}
+        answer ::= "yes" | "no" | [0-9]+ | "\x41" .
+        "#,
+    )
+    .unwrap();
+
+    assert!(grammar.matches("yes"));
+    assert!(grammar.matches("no, 42,yes"));
+    assert!(grammar.matches("Aé"));
+    assert!(!grammar.matches("yes, no, 1, 2"));
+    assert!(!grammar.matches("maybe"));
+    assert!(!grammar.matches("yes,"));
+}
+
+#[test]
+fn test_invalid_grammars() {
+    let error = |source| Grammar::parse(source).unwrap_err();
+
+    assert!(error(r#"answer ::= "yes""#).contains("no root rule"));
+    assert!(error(r#"root ::= answer"#).contains("answer is not defined"));
+    assert!(error(r#"root ::= root "x" | "y""#).contains("left recursive"));
+    assert!(error(r#"root ::= "x"{3,1}"#).contains("line 1"));
+    assert!(error(r#"root ::= "x"{0,99999999}"#).contains("repetition count"));
+    assert!(error(r#"root ::= "x"{99999999,}"#).contains("repetition count"));
+    assert!(Grammar::parse(&format!(r#"root ::= "x"{{0,{}}}"#, MAX_REPETITIONS)).is_ok());
+}
+
+#[test]
+fn test_json_schema_grammar() {
+    let schema = json!({
+        "type": "object",
+        "properties": {
+            "name": {"type": "string", "maxLength": 8},
+            "age": {"type": "integer"},
+            "tags": {"type": "array", "items": {"enum": ["a", "b"]}, "maxItems": 2},
+            "pet": {"$ref": "#/$defs/pet"}
+        },
+        "required": ["name", "age"],
+        "$defs": {
+            "pet": {"anyOf": [{"type": "null"}, {"const": "cat"}]}
+        }
+    });
+    let grammar = Grammar::parse(&json_schema_to_gbnf(&schema).unwrap()).unwrap();
+
+    assert!(grammar.matches(r#"{"age": 7, "name": "Ada"}"#));
+    assert!(grammar.matches(r#"{ "age": -3, "name": "Bo", "pet": "cat", "tags": ["b", "a"] }"#));
+    assert!(grammar.matches(r#"{"age": 1, "name": "é", "pet": null}"#));
+    // Missing required property, too long, too many items, not an integer
+    assert!(!grammar.matches(r#"{"name": "Ada"}"#));
+    assert!(!grammar.matches(r#"{"age": 7, "name": "Adalovelace"}"#));
+    assert!(!grammar.matches(r#"{"age": 7, "name": "A", "tags": ["a", "a", "b"]}"#));
+    assert!(!grammar.matches(r#"{"age": 7.5, "name": "A"}"#));
+
+    assert!(json_schema_to_gbnf(&json!({"type": "string", "pattern": "a+"})).is_err());
+    assert!(
+        json_schema_to_gbnf(&json!({"type": "string", "maxLength": 1u64 << 40}))
+            .unwrap_err()
+            .contains("maxLength")
+    );
+    assert!(json_schema_to_gbnf(&json!({"type": "array", "minItems": 99999999})).is_err());
+}
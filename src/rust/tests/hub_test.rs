@@ -0,0 +1,126 @@
+use gh_pages_rust::hub::{
+    gguf_quantization, select_weights, FileRevision, HubFile, RepositoryRevision, WeightsVariant,
+};
+
+fn listing(paths: &[&str]) -> Vec<HubFile> {
+    paths
+        .iter()
+        .map(|path| HubFile {
+            kind: "file".to_string(),
+            path: path.to_string(),
+            oid: "0".repeat(40),
+            size: 100,
+            lfs: None,
+        })
+        .collect()
+}
+
+#[test]
+fn test_parse_tree_listing() -> Result<(), serde_json::Error> {
+    let json = r#"[
+        {"type":"file","oid":"a1","size":1519,"path":".gitattributes"},
+        {"type":"file","oid":"b2","size":135,"path":"model.safetensors",
+         "lfs":{"oid":"c3","size":233000000,"pointerSize":135}}
+    ]"#;
+
+    let files: Vec<HubFile> = serde_json::from_str(json)?;
+    assert_eq!(files.len(), 2);
+    assert_eq!(files[0].content_size(), 1519);
+    assert_eq!(files[1].content_size(), 233000000);
+    assert_eq!(files[1].lfs.as_ref().unwrap().oid, "c3");
+
+    Ok(())
+}
+
+#[test]
+fn test_gguf_quantization_from_filename() {
+    assert_eq!(gguf_quantization("llama-7b.Q4_K_M.gguf"), Some("Q4_K_M"));
+    assert_eq!(gguf_quantization("gguf/model-q8_0.gguf"), Some("Q8_0"));
+    assert_eq!(gguf_quantization("model-bf16.gguf"), Some("BF16"));
+    assert_eq!(gguf_quantization("model.safetensors"), None);
+}
+
+#[test]
+fn test_prefers_single_safetensors() {
+    let files = listing(&["config.json", "model.safetensors", "model.Q4_K_M.gguf"]);
+
+    assert_eq!(
+        select_weights(&files),
+        Ok(WeightsVariant::Single {
+            file: "model.safetensors".to_string()
+        })
+    );
+}
+
+#[test]
+fn test_selects_sorted_shards() {
+    let files = listing(&[
+        "model-00002-of-00002.safetensors",
+        "model.safetensors.index.json",
+        "model-00001-of-00002.safetensors",
+    ]);
+
+    let variant = select_weights(&files).unwrap();
+    assert_eq!(
+        variant.files(),
+        vec![
+            "model.safetensors.index.json",
+            "model-00001-of-00002.safetensors",
+            "model-00002-of-00002.safetensors",
+        ]
+    );
+}
+
+#[test]
+fn test_gguf_only_repository_is_rejected() {
+    let files = listing(&["model.Q8_0.gguf", "model.Q4_K_M.gguf", "README.md"]);
+
+    let error = select_weights(&files).unwrap_err();
+    assert!(error.contains("Only GGUF weights (Q4_K_M, Q8_0)"));
+    assert_eq!(
+        select_weights(&listing(&["config.json"])),
+        Err("No safetensors weights".to_string())
+    );
+}
+
+#[test]
+fn test_file_revision_prefers_linked_etag() {
+    let revision = FileRevision::from_header_values(
+        Some("\"b7c1a0f0e2\""),
+        Some("W/\"41-abc\""),
+        Some("5d3a1c9 "),
+    );
+    assert_eq!(revision.etag.as_deref(), Some("b7c1a0f0e2"));
+    assert_eq!(revision.commit.as_deref(), Some("5d3a1c9"));
+
+    let revision = FileRevision::from_header_values(None, Some("W/\"41-abc\""), None);
+    assert_eq!(revision.etag.as_deref(), Some("41-abc"));
+    assert_eq!(revision.commit, None);
+}
+
+#[test]
+fn test_repository_revision_files() -> Result<(), serde_json::Error> {
+    let json = r#"{
+        "id": "r/m",
+        "sha": "5d3a1c9",
+        "siblings": [
+            {"rfilename": "config.json", "blobId": "a1", "size": 665},
+            {"rfilename": "model.safetensors", "blobId": "b2", "size": 233000000,
+             "lfs": {"sha256": "c3", "size": 233000000, "pointerSize": 135}}
+        ]
+    }"#;
+
+    let revision: RepositoryRevision = serde_json::from_str(json)?;
+    let file = |etag: &str| {
+        Some(FileRevision {
+            etag: Some(etag.to_string()),
+            commit: Some("5d3a1c9".to_string()),
+        })
+    };
+    assert_eq!(revision.file("config.json"), file("a1"));
+    // LFS files are identified by their contents, like X-Linked-Etag does
+    assert_eq!(revision.file("model.safetensors"), file("c3"));
+    assert_eq!(revision.file("tokenizer.json"), None);
+
+    Ok(())
+}
@@ -0,0 +1,50 @@
+use gh_pages_rust::import::{local_repository_id, plan_import, relative_path, ImportKind};
+
+fn names(names: &[&str]) -> Vec<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+#[test]
+fn test_local_repository_id() {
+    assert_eq!(local_repository_id("baby-llama"), "local/baby-llama");
+    assert_eq!(local_repository_id("org/model"), "local/org-model");
+    assert_eq!(local_repository_id("  "), "local/model");
+}
+
+#[test]
+fn test_relative_path_drops_picked_directory() {
+    assert_eq!(
+        relative_path("config.json", "baby-llama/config.json"),
+        "config.json"
+    );
+    assert_eq!(
+        relative_path("a.json", "baby-llama/sub/a.json"),
+        "sub/a.json"
+    );
+    assert_eq!(relative_path("config.json", ""), "config.json");
+}
+
+#[test]
+fn test_single_weights_file_is_renamed() -> Result<(), String> {
+    let plan = plan_import(&names(&[
+        "README.md",
+        "pytorch_model.safetensors",
+        "tokenizer.json",
+        "config.json",
+    ]))?;
+
+    assert_eq!(plan.len(), 3);
+    assert_eq!(plan[0].index, 1);
+    assert_eq!(plan[0].filename, "model.safetensors");
+    assert_eq!(plan[0].kind, ImportKind::Weights);
+
+    Ok(())
+}
+
+#[test]
+fn test_missing_files_are_reported() {
+    let error = plan_import(&names(&["model.safetensors", "config.json"])).unwrap_err();
+    assert!(error.contains("tokenizer.json"));
+
+    assert!(plan_import(&names(&["tokenizer.json", "config.json"])).is_err());
+}
@@ -0,0 +1,140 @@
+use candle_core::{DType, Device};
+use gh_pages_rust::loader::{shard_files, WeightsLoader};
+
+/// A safetensors file with F32 `[2, 3]` and `[4]` tensors, an empty one and a U32 one.
+fn file() -> Vec<u8> {
+    let header = br#"{"__metadata__":{"format":"pt"},"w":{"dtype":"F32","shape":[2,3],"data_offsets":[0,24]},"e":{"dtype":"F32","shape":[0],"data_offsets":[24,24]},"b":{"dtype":"F32","shape":[4],"data_offsets":[24,40]},"i":{"dtype":"U32","shape":[2],"data_offsets":[40,48]}}"#;
+
+    let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+    bytes.extend_from_slice(header);
+    for value in [0.5f32, 1.0, 1.5, 2.0, 2.5, 3.0, -1.0, -2.0, -3.0, -4.0] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    for value in [7u32, 9] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    bytes
+}
+
+fn load(bytes: &[u8], chunk_len: usize) -> Result<WeightsLoader, String> {
+    let mut loader = WeightsLoader::for_device(bytes.len() as u64, DType::F16, &Device::Cpu);
+    for chunk in bytes.chunks(chunk_len) {
+        loader.push_bytes(chunk)?;
+    }
+
+    Ok(loader)
+}
+
+#[test]
+fn test_tensors_are_converted_for_any_chunking() -> Result<(), String> {
+    let bytes = file();
+
+    for chunk_len in [1, 5, 13, bytes.len()] {
+        let tensors = load(&bytes, chunk_len)?.finish()?;
+        assert_eq!(tensors.len(), 4);
+
+        let w = &tensors["w"];
+        assert_eq!(w.dtype(), DType::F16);
+        assert_eq!(w.dims(), &[2, 3]);
+        let w: Vec<Vec<f32>> = w.to_dtype(DType::F32).unwrap().to_vec2().unwrap();
+        assert_eq!(w, vec![vec![0.5, 1.0, 1.5], vec![2.0, 2.5, 3.0]]);
+
+        let b: Vec<f32> = tensors["b"]
+            .to_dtype(DType::F32)
+            .unwrap()
+            .to_vec1()
+            .unwrap();
+        assert_eq!(b, vec![-1.0, -2.0, -3.0, -4.0]);
+        assert_eq!(tensors["e"].dims(), &[0]);
+
+        // Integer tensors keep their dtype
+        assert_eq!(tensors["i"].dtype(), DType::U32);
+        assert_eq!(tensors["i"].to_vec1::<u32>().unwrap(), vec![7, 9]);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_tensors_are_built_while_bytes_arrive() -> Result<(), String> {
+    let bytes = file();
+    let data_start = bytes.len() - 48;
+
+    let mut loader = WeightsLoader::for_device(bytes.len() as u64, DType::F16, &Device::Cpu);
+    loader.push_bytes(&bytes[..data_start + 24])?;
+    assert_eq!(loader.tensor_count(), 2);
+    assert!(!loader.is_complete());
+
+    loader.push_bytes(&bytes[data_start + 24..])?;
+    assert!(loader.is_complete());
+    assert_eq!(loader.tensor_count(), 4);
+
+    Ok(())
+}
+
+#[test]
+fn test_incomplete_or_oversized_input_is_rejected() {
+    let bytes = file();
+
+    let mut loader = WeightsLoader::for_device(bytes.len() as u64, DType::F16, &Device::Cpu);
+    loader.push_bytes(&bytes[..bytes.len() - 1]).unwrap();
+    assert!(loader.finish().is_err());
+
+    let mut loader = WeightsLoader::for_device(bytes.len() as u64, DType::F16, &Device::Cpu);
+    loader.push_bytes(&bytes).unwrap();
+    assert!(loader.push_bytes(&[0]).is_err());
+
+    let mut loader = WeightsLoader::for_device(16, DType::F16, &Device::Cpu);
+    assert!(loader.push_bytes(&[0; 16]).is_err());
+}
+
+/// A safetensors file with a single F32 `[1]` tensor named `name`.
+fn shard(name: &str, value: f32) -> Vec<u8> {
+    let header = format!(
+        r#"{{"{}":{{"dtype":"F32","shape":[1],"data_offsets":[0,4]}}}}"#,
+        name
+    );
+
+    let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+    bytes.extend_from_slice(header.as_bytes());
+    bytes.extend_from_slice(&value.to_le_bytes());
+    bytes
+}
+
+#[test]
+fn test_shards_load_into_one_set_of_tensors() -> Result<(), String> {
+    let index = br#"{"metadata":{},"weight_map":{"b":"model-00002-of-00002.safetensors","a":"model-00001-of-00002.safetensors","c":"model-00002-of-00002.safetensors"}}"#;
+    assert_eq!(
+        shard_files(index)?,
+        vec![
+            "model-00001-of-00002.safetensors",
+            "model-00002-of-00002.safetensors"
+        ]
+    );
+    assert!(shard_files(br#"{"weight_map":{}}"#).is_err());
+
+    let (first, second) = (shard("a", 1.), shard("b", 2.));
+    let mut loader = WeightsLoader::for_device(first.len() as u64, DType::F32, &Device::Cpu);
+    loader.push_bytes(&first[..10])?;
+    // The first shard has to be complete before the next one starts
+    assert!(loader.start_file(second.len() as u64).is_err());
+    loader.push_bytes(&first[10..])?;
+    loader.start_file(second.len() as u64)?;
+    loader.push_bytes(&second)?;
+
+    let tensors = loader.finish()?;
+    assert_eq!(tensors.len(), 2);
+    assert_eq!(tensors["b"].to_vec1::<f32>().unwrap(), vec![2.]);
+
+    // A tensor stored twice is an error rather than silently replaced
+    let mut loader = WeightsLoader::for_device(first.len() as u64, DType::F32, &Device::Cpu);
+    loader.push_bytes(&first)?;
+    loader.start_file(first.len() as u64)?;
+    assert!(loader
+        .push_bytes(&first)
+        .unwrap_err()
+        .contains("more than one file"));
+
+    Ok(())
+}
@@ -0,0 +1,108 @@
+use std::ops::ControlFlow;
+
+use candle_core::{Device, Tensor};
+use gh_pages_rust::constraint::{Constrained, Vocabulary};
+use gh_pages_rust::logits::{
+    Constrain, LogitsPipeline, LogitsProcessor, RepeatPenalty, TokenSampler,
+};
+use gh_pages_rust::pattern::Pattern;
+use gh_pages_rust::penalties::Penalties;
+
+/// Always the most likely token.
+struct ArgMax;
+
+impl TokenSampler for ArgMax {
+    fn sample(&mut self, logits: &[f32]) -> Result<u32, String> {
+        (0..logits.len() as u32)
+            .max_by(|a, b| logits[*a as usize].total_cmp(&logits[*b as usize]))
+            .ok_or_else(|| "No logits".to_string())
+    }
+}
+
+/// Bans every token generated so far, and stops after `limit` tokens.
+struct NoRepeats {
+    seen: Vec<u32>,
+    limit: usize,
+}
+
+impl LogitsProcessor for NoRepeats {
+    fn process(&mut self, _tokens: &[u32], logits: &mut [f32]) -> Result<ControlFlow<()>, String> {
+        if self.seen.len() >= self.limit {
+            return Ok(ControlFlow::Break(()));
+        }
+        for &token in &self.seen {
+            logits[token as usize] = f32::NEG_INFINITY;
+        }
+        Ok(ControlFlow::Continue(()))
+    }
+
+    fn accept(&mut self, token: u32) -> Result<(), String> {
+        self.seen.push(token);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_processors_compose() {
+    let mut pipeline = LogitsPipeline::new(ArgMax);
+    pipeline.push(Penalties::new(0., 0., vec![(0, 10.)], Vec::new()));
+    pipeline.push(NoRepeats {
+        seen: Vec::new(),
+        limit: 3,
+    });
+
+    let logits = vec![0., 3., 2., 1.];
+    let mut generated = Vec::new();
+    while let Some(token) = pipeline.next_token(&generated, logits.clone()).unwrap() {
+        generated.push(token);
+        pipeline.accept(token).unwrap();
+    }
+
+    // The bias picks token 0 first, then the ban goes down the rest until the limit
+    assert_eq!(generated, vec![0, 1, 2]);
+}
+
+#[test]
+fn test_repeat_penalty_matches_candle() {
+    let tokens = [1, 3, 3, 0];
+    let values = vec![2., -1., 0.5, -3., 4.];
+    let mut logits = values.clone();
+    let flow = RepeatPenalty {
+        penalty: 1.5,
+        last_n: 3,
+    }
+    .process(&tokens, &mut logits)
+    .unwrap();
+    assert!(flow.is_continue());
+
+    let expected = candle_transformers::utils::apply_repeat_penalty(
+        &Tensor::new(values, &Device::Cpu).unwrap(),
+        1.5,
+        &tokens[1..],
+    )
+    .unwrap()
+    .to_vec1::<f32>()
+    .unwrap();
+    assert_eq!(logits, expected);
+}
+
+#[test]
+fn test_constraint_stops_the_pipeline() {
+    let vocabulary = Vocabulary::from_tokens(vec![Vec::new(), b"a".to_vec(), b"b".to_vec()]);
+    let pattern = Pattern::new("ab").unwrap();
+    let mut pipeline = LogitsPipeline::new(ArgMax);
+    pipeline.push(Constrain {
+        constraint: Box::new(Constrained::new(pattern, &vocabulary)),
+        eos_token_ids: Vec::new(),
+    });
+
+    // "b" is the most likely token, but only "a" can start the text
+    let logits = vec![0., 1., 2.];
+    assert_eq!(pipeline.next_token(&[], logits.clone()).unwrap(), Some(1));
+    pipeline.accept(1).unwrap();
+    assert_eq!(pipeline.next_token(&[1], logits.clone()).unwrap(), Some(2));
+    pipeline.accept(2).unwrap();
+
+    // Complete, and without an EOS token generation has to stop
+    assert_eq!(pipeline.next_token(&[1, 2], logits).unwrap(), None);
+}
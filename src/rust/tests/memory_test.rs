@@ -0,0 +1,114 @@
+use candle_core::quantized::GgmlDType;
+use candle_core::DType;
+use candle_transformers::models::llama::LlamaConfig;
+use gh_pages_rust::memory::{
+    check_config, estimate, estimate_quantized, parameter_count, parse_dtype, WASM32_ADDRESS_SPACE,
+};
+
+fn config(json: &str) -> LlamaConfig {
+    serde_json::from_str(json).unwrap()
+}
+
+fn tiny_llama() -> LlamaConfig {
+    config(
+        r#"{"hidden_size":2048,"intermediate_size":5632,"vocab_size":32000,
+            "num_hidden_layers":22,"num_attention_heads":32,"num_key_value_heads":4,
+            "rms_norm_eps":1e-5,"max_position_embeddings":2048,"tie_word_embeddings":false}"#,
+    )
+}
+
+#[test]
+fn test_parameter_count_matches_published_size() {
+    // TinyLlama-1.1B reports 1,100,048,384 parameters
+    assert_eq!(parameter_count(&tiny_llama()).unwrap(), 1_100_048_384);
+}
+
+#[test]
+fn test_tied_embeddings_are_counted_once() {
+    let untied = tiny_llama();
+    let mut tied = tiny_llama();
+    tied.tie_word_embeddings = Some(true);
+
+    assert_eq!(
+        parameter_count(&untied).unwrap() - parameter_count(&tied).unwrap(),
+        32000 * 2048
+    );
+}
+
+#[test]
+fn test_kv_cache_grows_with_context_and_batch() {
+    let config = tiny_llama();
+    let short = estimate(&config, DType::F16, 128, 1).unwrap();
+    let long = estimate(&config, DType::F16, 256, 2).unwrap();
+
+    assert_eq!(short.weight_bytes, 2 * 1_100_048_384);
+    assert!(long.kv_cache_bytes > 3 * (short.kv_cache_bytes / 2));
+    assert!(short.fits);
+}
+
+#[test]
+fn test_resident_weights_buffer_counts_against_the_limit() {
+    let f32 = estimate(&tiny_llama(), DType::F32, 128, 1).unwrap();
+    assert!(!f32.fits);
+    assert_eq!(f32.limit_bytes, WASM32_ADDRESS_SPACE);
+
+    // Loading from an f16 safetensors buffer keeps both copies alive
+    let f16 = estimate(&tiny_llama(), DType::F16, 128, 1).unwrap();
+    assert!(f16.fits);
+    assert!(!f16.with_resident_bytes(2 * 1_100_048_384).fits);
+}
+
+#[test]
+fn test_parse_dtype() {
+    assert_eq!(parse_dtype(None), Ok(DType::F16));
+    assert_eq!(parse_dtype(Some("bf16")), Ok(DType::BF16));
+    assert!(parse_dtype(Some("q4")).is_err());
+}
+
+#[test]
+fn test_quantized_weights_are_smaller() {
+    let config = tiny_llama();
+    let f16 = estimate(&config, DType::F16, 2048, 1).unwrap();
+    let q8 = estimate_quantized(&config, GgmlDType::Q8_0, 2048, 1).unwrap();
+    let q4 = estimate_quantized(&config, GgmlDType::Q4K, 2048, 1).unwrap();
+
+    assert!(q8.weight_bytes < f16.weight_bytes);
+    assert!(q4.weight_bytes < q8.weight_bytes);
+    assert_eq!(
+        q8.total_bytes - q8.weight_bytes,
+        q8.kv_cache_bytes + q8.activation_bytes
+    );
+}
+
+#[test]
+fn test_invalid_head_counts_are_rejected() {
+    let mut no_heads = tiny_llama();
+    no_heads.num_attention_heads = 0;
+    assert!(check_config(&no_heads).is_err());
+    assert!(parameter_count(&no_heads).is_err());
+    assert!(estimate(&no_heads, DType::F16, 128, 1).is_err());
+
+    let mut no_kv_heads = tiny_llama();
+    no_kv_heads.num_key_value_heads = Some(0);
+    assert!(estimate_quantized(&no_kv_heads, GgmlDType::Q8_0, 128, 1).is_err());
+
+    let mut uneven = tiny_llama();
+    uneven.num_attention_heads = 30;
+    assert!(check_config(&uneven)
+        .unwrap_err()
+        .contains("not a multiple of num_attention_heads"));
+}
+
+#[test]
+fn test_overflowing_sizes_are_rejected() {
+    let mut huge = tiny_llama();
+    huge.hidden_size = usize::MAX / 2;
+    huge.num_attention_heads = 1;
+    huge.num_key_value_heads = Some(1);
+    assert!(parameter_count(&huge).is_err());
+    assert!(estimate_quantized(&huge, GgmlDType::Q8_0, 128, 1).is_err());
+
+    let error = estimate(&tiny_llama(), DType::F16, usize::MAX, usize::MAX).unwrap_err();
+    assert!(error.contains("too large"));
+    assert!(estimate(&tiny_llama(), DType::F16, 1 << 40, 1).is_err());
+}
@@ -0,0 +1,29 @@
+use gh_pages_rust::migrations::{pending, DB_VERSION, LEGACY_KEYS, MIGRATIONS};
+
+#[test]
+fn test_fresh_database_runs_every_migration() {
+    assert_eq!(pending(0, DB_VERSION), 0..MIGRATIONS.len());
+}
+
+#[test]
+fn test_existing_database_runs_only_newer_migrations() {
+    assert_eq!(pending(1, DB_VERSION), 1..MIGRATIONS.len());
+    assert!(pending(DB_VERSION, DB_VERSION).is_empty());
+}
+
+#[test]
+fn test_pending_never_exceeds_known_migrations() {
+    assert_eq!(pending(0, DB_VERSION + 5), 0..MIGRATIONS.len());
+    assert!(pending(DB_VERSION + 1, DB_VERSION + 5).is_empty());
+}
+
+#[test]
+fn test_db_version_matches_migration_count() {
+    assert_eq!(DB_VERSION as usize, MIGRATIONS.len());
+}
+
+#[test]
+fn test_legacy_keys_never_clash_with_repository_keys() {
+    // Repository keys always contain the `owner/name` slash
+    assert!(LEGACY_KEYS.iter().all(|key| !key.contains('/')));
+}
@@ -0,0 +1,63 @@
+use std::ops::ControlFlow;
+
+use gh_pages_rust::constraint::{mask_values, Constrained, TokenConstraint, Vocabulary};
+use gh_pages_rust::logits::{Constrain, LogitsProcessor};
+use gh_pages_rust::pattern::Pattern;
+
+fn vocabulary() -> Vocabulary {
+    let tokens = ["", "yes", "no", "y", "es", "!", "1", "23"];
+    Vocabulary::from_tokens(tokens.iter().map(|t| t.as_bytes().to_vec()).collect())
+}
+
+#[test]
+fn test_pattern_matches_whole_text() {
+    let pattern = Pattern::new("(yes|no)|[0-9]{2,3}").unwrap();
+
+    assert!(pattern.matches("yes"));
+    assert!(pattern.matches("123"));
+    assert!(!pattern.matches("ye"));
+    assert!(!pattern.matches("yes!"));
+    assert!(!pattern.matches("1234"));
+
+    assert!(Pattern::new("(unclosed")
+        .unwrap_err()
+        .contains("Invalid regex"));
+}
+
+#[test]
+fn test_pattern_masks_tokens() {
+    let vocabulary = vocabulary();
+    let mut constraint = Constrained::new(Pattern::new("yes|no").unwrap(), &vocabulary);
+
+    let allowed = constraint.allowed();
+    assert!(allowed.contains(1) && allowed.contains(2) && allowed.contains(3));
+    assert_eq!(allowed.len(), 3);
+
+    constraint.accept(3).unwrap();
+    assert!(!constraint.is_complete());
+    assert!(constraint.accept(5).is_err());
+    constraint.accept(4).unwrap();
+    assert!(constraint.is_complete());
+}
+
+#[test]
+fn test_accepting_pattern_ends_generation() {
+    let vocabulary = vocabulary();
+    let mut constraint = Constrained::new(Pattern::new("[0-9]+").unwrap(), &vocabulary);
+
+    constraint.accept(6).unwrap();
+    // Complete, but more digits may follow: the EOS token 0 is allowed next to them
+    let mut logits = [0.0; 8];
+    assert!(mask_values(&mut logits, &mut constraint, &[0]).unwrap());
+    let allowed: Vec<usize> = (0..8).filter(|&i| logits[i].is_finite()).collect();
+    assert_eq!(allowed, vec![0, 6, 7]);
+
+    let mut yes = Constrain {
+        constraint: Box::new(Constrained::new(Pattern::new("yes").unwrap(), &vocabulary)),
+        eos_token_ids: Vec::new(),
+    };
+    yes.accept(1).unwrap();
+    // Nothing can follow and there is no EOS token, so generation stops
+    let flow = yes.process(&[1], &mut [0.0; 8]).unwrap();
+    assert_eq!(flow, ControlFlow::Break(()));
+}
@@ -0,0 +1,100 @@
+use std::collections::BTreeMap;
+
+use gh_pages_rust::generator::GenerationArguments;
+use gh_pages_rust::penalties::{self, Penalties};
+use tokenizers::Tokenizer;
+
+fn tokenizer() -> Tokenizer {
+    Tokenizer::from_bytes(
+        r#"{
+            "version": "1.0",
+            "pre_tokenizer": {"type": "Whitespace"},
+            "model": {"type": "WordLevel",
+                "vocab": {"<unk>": 0, "the": 1, "cat": 2, "sat": 3, "on": 4, "mat": 5},
+                "unk_token": "<unk>"}
+        }"#,
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_frequency_and_presence_penalties_count_generated_tokens() {
+    let mut penalties = Penalties::new(0.5, 1., Vec::new(), Vec::new());
+    assert!(!penalties.is_empty());
+
+    // Nothing generated yet, so nothing is penalized
+    let mut logits = vec![1.; 4];
+    penalties.apply(&mut logits);
+    assert_eq!(logits, vec![1.; 4]);
+
+    penalties.accept(2);
+    penalties.accept(2);
+    penalties.accept(3);
+    let mut logits = vec![1.; 4];
+    penalties.apply(&mut logits);
+    assert_eq!(logits, vec![1., 1., 1. - 2. * 0.5 - 1., 1. - 0.5 - 1.]);
+
+    assert!(Penalties::new(0., 0., Vec::new(), Vec::new()).is_empty());
+}
+
+#[test]
+fn test_logit_bias_and_banned_sequences() {
+    let tokenizer = tokenizer();
+    let logit_bias = BTreeMap::from([("3".to_string(), -100.), ("mat".to_string(), 5.)]);
+    let bias = penalties::resolve_logit_bias(&logit_bias, &tokenizer).unwrap();
+    assert_eq!(bias, vec![(3, -100.), (5, 5.)]);
+
+    let unknown = BTreeMap::from([("the cat".to_string(), 1.)]);
+    assert!(penalties::resolve_logit_bias(&unknown, &tokenizer).is_err());
+    let out_of_range = BTreeMap::from([("6".to_string(), 1.)]);
+    assert!(penalties::resolve_logit_bias(&out_of_range, &tokenizer).is_err());
+
+    let banned =
+        penalties::encode_banned_sequences(&["the cat".to_string(), "on".to_string()], &tokenizer)
+            .unwrap();
+    assert_eq!(banned, vec![vec![1, 2], vec![4]]);
+
+    let mut penalties = Penalties::new(0., 0., bias, banned);
+    let mut logits = vec![0.; 6];
+    penalties.apply(&mut logits);
+    assert_eq!(logits, vec![0., 0., 0., -100., f32::NEG_INFINITY, 5.]);
+
+    // "cat" is only banned right after "the"
+    penalties.accept(1);
+    let mut logits = vec![0.; 6];
+    penalties.apply(&mut logits);
+    assert_eq!(logits[2], f32::NEG_INFINITY);
+
+    penalties.accept(5);
+    let mut logits = vec![0.; 6];
+    penalties.apply(&mut logits);
+    assert_eq!(logits[2], 0.);
+}
+
+#[test]
+fn test_penalty_arguments_are_checked() {
+    let arguments = GenerationArguments {
+        frequency_penalty: Some(2.5),
+        presence_penalty: Some(-1.),
+        logit_bias: Some(BTreeMap::from([("13".to_string(), -101.)])),
+        banned_sequences: Some(vec![String::new()]),
+        ..GenerationArguments::new()
+    };
+    let error = arguments.check().unwrap_err();
+    assert!(
+        error.contains("frequency_penalty must be in [-2, 2], got 2.5"),
+        "{}",
+        error
+    );
+    assert!(!error.contains("presence_penalty"), "{}", error);
+    assert!(error.contains("logit_bias of \"13\""), "{}", error);
+    assert!(error.contains("banned_sequences"), "{}", error);
+
+    let json = r#"{"presence_penalty": 0.5, "logit_bias": {"13": -100}, "banned_sequences": ["as an AI"]}"#;
+    let arguments = GenerationArguments::from_json(json).unwrap();
+    assert_eq!(arguments.presence_penalty, Some(0.5));
+    assert_eq!(
+        arguments.get_internal().unwrap().logit_bias,
+        BTreeMap::from([("13".to_string(), -100.)])
+    );
+}
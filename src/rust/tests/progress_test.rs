@@ -0,0 +1,66 @@
+use gh_pages_rust::progress::{ProgressSnapshot, ProgressTracker};
+
+#[test]
+fn test_progress_is_throttled() {
+    let mut tracker = ProgressTracker::new(100.0);
+
+    assert!(tracker.update(0.0, 10.0, Some(1000.0), false).is_some());
+    assert!(tracker.update(50.0, 20.0, Some(1000.0), false).is_none());
+    assert!(tracker.update(99.0, 30.0, Some(1000.0), false).is_none());
+    assert!(tracker.update(100.0, 40.0, Some(1000.0), false).is_some());
+}
+
+#[test]
+fn test_completion_is_always_reported() {
+    let mut tracker = ProgressTracker::new(100.0);
+
+    tracker.update(0.0, 10.0, Some(100.0), false);
+    let snapshot = tracker.update(10.0, 100.0, Some(100.0), false).unwrap();
+
+    assert_eq!(snapshot.percentage, Some(100));
+    assert_eq!(snapshot.eta_seconds, Some(0.0));
+
+    // Nothing new arrived, so a forced flush has nothing to report
+    assert!(tracker.update(20.0, 100.0, Some(100.0), true).is_none());
+}
+
+#[test]
+fn test_throughput_and_eta() {
+    let mut tracker = ProgressTracker::new(100.0);
+
+    tracker.update(0.0, 0.0, Some(10_000.0), false);
+    let snapshot = tracker
+        .update(1000.0, 1000.0, Some(10_000.0), false)
+        .unwrap();
+
+    assert_eq!(snapshot.bytes_per_second, 1000.0);
+    assert_eq!(snapshot.eta_seconds, Some(9.0));
+    assert_eq!(snapshot.percentage, Some(10));
+}
+
+#[test]
+fn test_unknown_total_and_restart() {
+    let mut tracker = ProgressTracker::new(100.0);
+
+    tracker.update(0.0, 0.0, None, false);
+    let snapshot = tracker.update(500.0, 500.0, None, false).unwrap();
+    assert_eq!(snapshot.total, None);
+    assert_eq!(snapshot.percentage, None);
+    assert_eq!(snapshot.eta_seconds, None);
+
+    // A download that restarts from zero must not report negative throughput
+    let snapshot = tracker.update(600.0, 0.0, None, false).unwrap();
+    assert_eq!(snapshot.bytes_per_second, 0.0);
+}
+
+#[test]
+fn test_snapshot_survives_broadcast_serialization() {
+    let mut tracker = ProgressTracker::new(100.0);
+    let snapshot = tracker.update(1000.0, 500.0, None, false).unwrap();
+
+    let json = serde_json::to_string(&snapshot).unwrap();
+    let parsed: ProgressSnapshot = serde_json::from_str(&json).unwrap();
+
+    assert_eq!(parsed, snapshot);
+    assert_eq!(parsed.total, None);
+}
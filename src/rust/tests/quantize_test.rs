@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+
+use candle_core::quantized::GgmlDType;
+use candle_core::{DType, Device, Tensor};
+use candle_nn::VarBuilder;
+use candle_transformers::models::llama::{Cache, Llama, LlamaConfig};
+use gh_pages_rust::quantize::{gguf_name, parse_quantization, permute_for_rope, Quantizer};
+
+const CONFIG: &str = r#"{
+    "hidden_size": 64,
+    "intermediate_size": 128,
+    "vocab_size": 32,
+    "num_hidden_layers": 2,
+    "num_attention_heads": 4,
+    "num_key_value_heads": 2,
+    "rms_norm_eps": 1e-5,
+    "rope_theta": 10000.0,
+    "max_position_embeddings": 64
+}"#;
+
+/// Random weights of a small Llama, named as in Hugging Face checkpoints.
+fn weights(config: &LlamaConfig) -> candle_core::Result<HashMap<String, Tensor>> {
+    let hidden = config.hidden_size;
+    let kv_dim = config.num_key_value_heads() * hidden / config.num_attention_heads;
+    let intermediate = config.intermediate_size;
+
+    let mut shapes = vec![
+        (
+            "model.embed_tokens.weight".to_string(),
+            vec![config.vocab_size, hidden],
+        ),
+        (
+            "lm_head.weight".to_string(),
+            vec![config.vocab_size, hidden],
+        ),
+    ];
+    for layer in 0..config.num_hidden_layers {
+        let prefix = format!("model.layers.{}", layer);
+        shapes.extend([
+            (
+                format!("{prefix}.self_attn.q_proj.weight"),
+                vec![hidden, hidden],
+            ),
+            (
+                format!("{prefix}.self_attn.k_proj.weight"),
+                vec![kv_dim, hidden],
+            ),
+            (
+                format!("{prefix}.self_attn.v_proj.weight"),
+                vec![kv_dim, hidden],
+            ),
+            (
+                format!("{prefix}.self_attn.o_proj.weight"),
+                vec![hidden, hidden],
+            ),
+            (
+                format!("{prefix}.mlp.gate_proj.weight"),
+                vec![intermediate, hidden],
+            ),
+            (
+                format!("{prefix}.mlp.up_proj.weight"),
+                vec![intermediate, hidden],
+            ),
+            (
+                format!("{prefix}.mlp.down_proj.weight"),
+                vec![hidden, intermediate],
+            ),
+        ]);
+    }
+
+    let mut tensors = HashMap::new();
+    for (name, shape) in shapes {
+        tensors.insert(name, Tensor::randn(0f32, 0.2, shape, &Device::Cpu)?);
+    }
+
+    let mut norms = vec!["model.norm.weight".to_string()];
+    for layer in 0..config.num_hidden_layers {
+        norms.push(format!("model.layers.{}.input_layernorm.weight", layer));
+        norms.push(format!(
+            "model.layers.{}.post_attention_layernorm.weight",
+            layer
+        ));
+    }
+    for name in norms {
+        tensors.insert(name, Tensor::ones(hidden, DType::F32, &Device::Cpu)?);
+    }
+
+    Ok(tensors)
+}
+
+#[test]
+fn test_gguf_names() {
+    assert_eq!(
+        gguf_name("model.layers.3.self_attn.o_proj.weight").as_deref(),
+        Some("blk.3.attn_output.weight")
+    );
+    assert_eq!(
+        gguf_name("model.layers.0.post_attention_layernorm.weight").as_deref(),
+        Some("blk.0.ffn_norm.weight")
+    );
+    assert_eq!(
+        gguf_name("model.embed_tokens.weight").as_deref(),
+        Some("token_embd.weight")
+    );
+    assert_eq!(
+        gguf_name("model.layers.0.self_attn.rotary_emb.inv_freq"),
+        None
+    );
+
+    assert_eq!(parse_quantization(Some("q4_k")), Ok(Some(GgmlDType::Q4K)));
+    assert_eq!(parse_quantization(None), Ok(None));
+    assert!(parse_quantization(Some("q3")).is_err());
+}
+
+#[test]
+fn test_rope_permutation_interleaves_halves() -> candle_core::Result<()> {
+    // One head of dimension 4: rows [0, 1] are the first half, [2, 3] the second
+    let weight = Tensor::arange(0f32, 4., &Device::Cpu)?.reshape((4, 1))?;
+    let permuted: Vec<f32> = permute_for_rope(&weight, 1)?.flatten_all()?.to_vec1()?;
+
+    assert_eq!(permuted, vec![0., 2., 1., 3.]);
+    Ok(())
+}
+
+#[test]
+fn test_quantized_model_matches_float_model() -> Result<(), String> {
+    let error = |e: candle_core::Error| e.to_string();
+    let config: LlamaConfig = serde_json::from_str(CONFIG).map_err(|e| e.to_string())?;
+    let tensors = weights(&config).map_err(error)?;
+    let device = Device::Cpu;
+
+    let mut quantizer = Quantizer::new(&config, GgmlDType::Q8_0)?;
+    for (name, tensor) in &tensors {
+        quantizer.add(name, tensor.clone())?;
+    }
+    assert!(quantizer.size_in_bytes() > 0);
+    let mut quantized = quantizer.load(&device)?;
+
+    let llama_config = config.into_config(false);
+    let vb = VarBuilder::from_tensors(tensors, DType::F32, &device);
+    let llama = Llama::load(vb, &llama_config).map_err(error)?;
+    let mut cache = Cache::new(true, DType::F32, &llama_config, &device).map_err(error)?;
+
+    let tokens = [1u32, 5, 9, 3, 7];
+    let input = Tensor::new(&tokens[..], &device)
+        .and_then(|input| input.unsqueeze(0))
+        .map_err(error)?;
+
+    let expected: Vec<f32> = llama
+        .forward(&input, 0, &mut cache)
+        .and_then(|logits| logits.flatten_all())
+        .and_then(|logits| logits.to_vec1())
+        .map_err(error)?;
+    let actual: Vec<f32> = quantized
+        .forward(&input, 0)
+        .and_then(|logits| logits.flatten_all())
+        .and_then(|logits| logits.to_vec1())
+        .map_err(error)?;
+
+    // Q8_0 keeps the logits close, a wrong rotary layout would not
+    let dot: f32 = expected.iter().zip(&actual).map(|(a, b)| a * b).sum();
+    let norm = |values: &[f32]| values.iter().map(|v| v * v).sum::<f32>().sqrt();
+    let similarity = dot / (norm(&expected) * norm(&actual));
+    assert!(similarity > 0.99, "cosine similarity {}", similarity);
+
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use gh_pages_rust::cache::CacheEntry;
+use gh_pages_rust::hub::FileRevision;
+use gh_pages_rust::repository::{
+    files_for_architecture, is_weights_file, repository_key, FileUpdate, RepositoryManifest,
+    UpdateStatus,
+};
+
+#[test]
+fn test_llama_manifest_files() {
+    let files = files_for_architecture("LlamaForCausalLM").unwrap();
+    assert_eq!(
+        files,
+        vec!["model.safetensors", "tokenizer.json", "config.json"]
+    );
+    assert_eq!(files_for_architecture("llama"), Some(files.clone()));
+    assert_eq!(files_for_architecture("mamba"), None);
+
+    let weights: Vec<&String> = files.iter().filter(|f| is_weights_file(f)).collect();
+    assert_eq!(weights, vec!["model.safetensors"]);
+    assert!(!is_weights_file("model.safetensors.index.json"));
+}
+
+#[test]
+fn test_repository_keys_do_not_collide() {
+    assert_eq!(
+        repository_key("timinar/baby-llama-58m", "config.json"),
+        "timinar/baby-llama-58m/config.json"
+    );
+    assert_ne!(
+        repository_key("timinar/baby-llama-58m", "config.json"),
+        repository_key("timinar/baby-llama", "config.json")
+    );
+}
+
+#[test]
+fn test_manifest_round_trip() -> Result<(), serde_json::Error> {
+    let manifest = RepositoryManifest {
+        repository: "timinar/baby-llama-58m".to_string(),
+        revision: "main".to_string(),
+        files: files_for_architecture("llama").unwrap(),
+        completed_at: 1700000000000.0,
+    };
+
+    let json = serde_json::to_string(&manifest)?;
+    assert_eq!(serde_json::from_str::<RepositoryManifest>(&json)?, manifest);
+
+    Ok(())
+}
+
+#[test]
+fn test_update_status() {
+    let remote = |etag: &str| {
+        Some(FileRevision {
+            etag: Some(etag.to_string()),
+            commit: Some("5d3a1c9".to_string()),
+        })
+    };
+    let cached = CacheEntry::new("r/m/config.json", 10, "r/m", "main", 0.0);
+    let tracked = cached.clone().with_file_revision(remote("aaa").unwrap());
+
+    let status = |cached, remote| FileUpdate::new("config.json", cached, remote).status;
+
+    assert_eq!(status(Some(&tracked), remote("aaa")), UpdateStatus::Current);
+    assert_eq!(status(Some(&tracked), remote("bbb")), UpdateStatus::Changed);
+    assert_eq!(status(Some(&cached), remote("aaa")), UpdateStatus::Unknown);
+    assert_eq!(status(None, remote("aaa")), UpdateStatus::Missing);
+    assert_eq!(status(Some(&tracked), None), UpdateStatus::Removed);
+
+    assert!(UpdateStatus::Changed.needs_download());
+    assert!(!UpdateStatus::Removed.needs_download());
+}
+
+#[test]
+fn test_entries_without_revision_still_parse() -> Result<(), serde_json::Error> {
+    let json = r#"{"key":"r/m/config.json","size":10,"repository":"r/m","revision":"main","downloaded_at":0.0,"last_accessed":0.0}"#;
+    let entry: CacheEntry = serde_json::from_str(json)?;
+
+    assert_eq!(entry.etag, None);
+    assert_eq!(entry.commit, None);
+    assert_eq!(entry.dtype, None);
+
+    Ok(())
+}
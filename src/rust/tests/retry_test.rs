@@ -0,0 +1,44 @@
+use gh_pages_rust::retry::{parse_content_range_total, parse_retry_after, RetryPolicy};
+
+#[test]
+fn test_exponential_backoff_is_capped() {
+    let policy = RetryPolicy::new();
+
+    assert_eq!(policy.delay_ms(1, None), 500.0);
+    assert_eq!(policy.delay_ms(2, None), 1000.0);
+    assert_eq!(policy.delay_ms(3, None), 2000.0);
+    assert_eq!(policy.delay_ms(20, None), policy.max_delay_ms);
+}
+
+#[test]
+fn test_retry_after_extends_but_never_exceeds_max_delay() {
+    let policy = RetryPolicy::new();
+
+    assert_eq!(policy.delay_ms(1, Some(5000.0)), 5000.0);
+    assert_eq!(policy.delay_ms(3, Some(100.0)), 2000.0);
+    assert_eq!(policy.delay_ms(1, Some(3_600_000.0)), policy.max_delay_ms);
+}
+
+#[test]
+fn test_attempt_limit_and_statuses() {
+    let policy = RetryPolicy::new();
+
+    assert!(policy.allows_retry(1));
+    assert!(!policy.allows_retry(policy.max_attempts));
+    assert!(!RetryPolicy::none().allows_retry(1));
+
+    assert!(policy.is_retryable(503));
+    assert!(policy.is_retryable(429));
+    assert!(!policy.is_retryable(404));
+}
+
+#[test]
+fn test_header_parsing() {
+    assert_eq!(parse_retry_after("120"), Some(120_000.0));
+    assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), None);
+    assert_eq!(
+        parse_content_range_total("bytes 100-999/1000"),
+        Some(1000.0)
+    );
+    assert_eq!(parse_content_range_total("bytes 100-999/*"), None);
+}
@@ -0,0 +1,86 @@
+use gh_pages_rust::sampling::{check_samplers, Sampler, SamplerChain};
+
+const LOGITS: [f32; 6] = [3.0, 2.5, 1.0, 0.5, -1.0, f32::NEG_INFINITY];
+
+/// The tokens a chain picks over many draws.
+fn picked(samplers: Vec<Sampler>) -> Vec<u32> {
+    let mut chain = SamplerChain::new(samplers, 7);
+    let mut picked: Vec<u32> = (0..500).map(|_| chain.sample(&LOGITS).unwrap()).collect();
+    picked.sort();
+    picked.dedup();
+    picked
+}
+
+#[test]
+fn test_filters_narrow_candidates() {
+    assert_eq!(picked(vec![Sampler::TopK { k: 2 }]), vec![0, 1]);
+    assert_eq!(
+        picked(vec![Sampler::Temperature { temperature: 0. }]),
+        vec![0]
+    );
+    // exp(1.0 - 3.0) is about 0.14 of the most likely token
+    assert_eq!(picked(vec![Sampler::MinP { p: 0.1 }]), vec![0, 1, 2]);
+    assert_eq!(picked(vec![Sampler::MinP { p: 0.5 }]), vec![0, 1]);
+    // The highest probability is about 0.55, so the threshold is about 0.3
+    assert_eq!(picked(vec![Sampler::TopA { a: 1. }]), vec![0, 1]);
+    assert_eq!(picked(vec![Sampler::TailFree { z: 0.5 }]), vec![0, 1]);
+    assert_eq!(picked(vec![Sampler::Typical { p: 0.2 }]), vec![1]);
+
+    // Masked tokens are never picked, even without filters
+    assert!(!picked(Vec::new()).contains(&5));
+}
+
+#[test]
+fn test_samplers_apply_in_order() {
+    // High temperature flattens the distribution before min-p sees it
+    let flattened = picked(vec![
+        Sampler::Temperature { temperature: 10. },
+        Sampler::MinP { p: 0.5 },
+    ]);
+    assert_eq!(flattened, vec![0, 1, 2, 3, 4]);
+
+    let sharpened = picked(vec![
+        Sampler::MinP { p: 0.5 },
+        Sampler::Temperature { temperature: 10. },
+    ]);
+    assert_eq!(sharpened, vec![0, 1]);
+}
+
+#[test]
+fn test_mirostat_carries_its_bound() {
+    let mut chain = SamplerChain::new(vec![Sampler::MirostatV2 { tau: 1., eta: 0.5 }], 1);
+    assert_eq!(chain.mirostat_mu(), Some(2.));
+
+    let mut bounds = Vec::new();
+    for _ in 0..20 {
+        chain.sample(&LOGITS).unwrap();
+        bounds.push(chain.mirostat_mu().unwrap());
+    }
+    // The bound moves after every token and settles near tau's surprise
+    assert_ne!(bounds[0], 2.);
+    assert!(
+        bounds.iter().all(|mu| (0. ..4.).contains(mu)),
+        "{:?}",
+        bounds
+    );
+}
+
+#[test]
+fn test_sampler_checks() {
+    assert!(check_samplers(&[Sampler::TopK { k: 0 }]).is_err());
+    assert!(check_samplers(&[Sampler::TopP { p: -0.5 }]).is_err());
+    assert!(check_samplers(&[
+        Sampler::MirostatV2 { tau: 5., eta: 0.1 },
+        Sampler::TopK { k: 4 },
+    ])
+    .is_err());
+    assert!(check_samplers(&[
+        Sampler::TopK { k: 40 },
+        Sampler::MirostatV2 { tau: 5., eta: 0.1 },
+    ])
+    .is_ok());
+
+    let json = r#"[{"type": "min_p", "p": 0.05}, {"type": "temperature", "temperature": 0.8}]"#;
+    let samplers: Vec<Sampler> = serde_json::from_str(json).unwrap();
+    assert_eq!(samplers[0], Sampler::MinP { p: 0.05 });
+}
@@ -0,0 +1,78 @@
+use gh_pages_rust::tensor_header::{header_len, parse_header, summarize_header, HEADER_PREFIX_LEN};
+
+/// A safetensors file with an F32 `[2, 2]` and a BF16 `[3]` tensor.
+fn file() -> (Vec<u8>, usize) {
+    let header = br#"{"__metadata__":{"format":"pt"},"b":{"dtype":"BF16","shape":[3],"data_offsets":[16,22]},"a":{"dtype":"F32","shape":[2,2],"data_offsets":[0,16]}}"#;
+
+    let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+    bytes.extend_from_slice(header);
+    bytes.extend_from_slice(&[0; 22]);
+
+    (bytes, header.len())
+}
+
+#[test]
+fn test_parse_header() -> Result<(), String> {
+    let (bytes, len) = file();
+    assert_eq!(header_len(&bytes)?, len as u64);
+
+    let start = HEADER_PREFIX_LEN as usize;
+    let tensors = parse_header(&bytes[start..start + len], bytes.len() as u64)?;
+
+    let names: Vec<&str> = tensors.iter().map(|t| t.name.as_str()).collect();
+    assert_eq!(names, vec!["a", "b"]);
+    assert_eq!(tensors[0].shape, vec![2, 2]);
+    assert_eq!(tensors[1].dtype, "BF16");
+
+    Ok(())
+}
+
+#[test]
+fn test_truncated_file_is_rejected() {
+    let (bytes, len) = file();
+    let start = HEADER_PREFIX_LEN as usize;
+
+    assert!(parse_header(&bytes[start..start + len], bytes.len() as u64 - 1).is_err());
+    assert!(header_len(&bytes[..4]).is_err());
+}
+
+#[test]
+fn test_shape_must_match_offsets() {
+    let header = br#"{"a":{"dtype":"F32","shape":[3],"data_offsets":[0,16]}}"#;
+    let size = HEADER_PREFIX_LEN + header.len() as u64 + 16;
+
+    assert!(parse_header(header, size).is_err());
+}
+
+#[test]
+fn test_overflowing_shape_is_rejected() {
+    // 2^32 * 2^32 elements wraps to 0 in a u64, which would match the empty data range
+    let header = br#"{"a":{"dtype":"F32","shape":[4294967296,4294967296],"data_offsets":[0,0]}}"#;
+    let size = HEADER_PREFIX_LEN + header.len() as u64;
+
+    let error = parse_header(header, size).unwrap_err();
+    assert!(error.contains("does not match its shape and dtype"));
+
+    // Fits as an element count, but not once multiplied by the dtype size
+    let header = br#"{"a":{"dtype":"F32","shape":[4611686018427387904],"data_offsets":[0,0]}}"#;
+    let size = HEADER_PREFIX_LEN + header.len() as u64;
+    assert!(parse_header(header, size).is_err());
+}
+
+#[test]
+fn test_summary_counts_parameters_per_dtype() -> Result<(), String> {
+    let (bytes, len) = file();
+    let start = HEADER_PREFIX_LEN as usize;
+    let summary = summarize_header(&bytes[start..start + len], bytes.len() as u64)?;
+
+    assert_eq!(summary.parameter_count, 7);
+    assert_eq!(summary.dtypes.get("F32"), Some(&4));
+    assert_eq!(summary.dtypes.get("BF16"), Some(&3));
+    assert_eq!(summary.file_size, bytes.len() as u64);
+    assert_eq!(
+        summary.metadata.get("format").map(String::as_str),
+        Some("pt")
+    );
+
+    Ok(())
+}
@@ -0,0 +1,116 @@
+use candle_core::{DType, Device};
+use gh_pages_rust::loader::WeightsLoader;
+use gh_pages_rust::tensor_header::{header_len, parse_header, HEADER_PREFIX_LEN};
+use gh_pages_rust::transcode::{plan_transcode, transcode_tensor, TranscodePlan};
+
+const VALUES: [f32; 6] = [0.5, -1.0, 1.5, 2.0, -2.5, 3.0];
+
+/// A safetensors file with an F32 `[2, 3]` tensor and a U32 `[2]` one.
+fn file() -> Vec<u8> {
+    let header = br#"{"__metadata__":{"format":"pt"},"w":{"dtype":"F32","shape":[2,3],"data_offsets":[0,24]},"i":{"dtype":"U32","shape":[2],"data_offsets":[24,32]}}"#;
+
+    let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+    bytes.extend_from_slice(header);
+    for value in VALUES {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    for value in [7u32, 9] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    bytes
+}
+
+fn plan(bytes: &[u8], dtype: DType) -> Result<TranscodePlan, String> {
+    let start = HEADER_PREFIX_LEN as usize;
+    let len = header_len(bytes)? as usize;
+
+    plan_transcode(&bytes[start..start + len], bytes.len() as u64, dtype)
+}
+
+/// Writes the transcoded file the way the cache does, tensor by tensor.
+fn transcode(bytes: &[u8], plan: &TranscodePlan) -> Result<Vec<u8>, String> {
+    let data_start = HEADER_PREFIX_LEN as usize + header_len(bytes)? as usize;
+
+    let mut output = plan.header.clone();
+    for (source, target) in &plan.tensors {
+        let (begin, end) = source.data_offsets;
+        let tensor = &bytes[data_start + begin as usize..data_start + end as usize];
+        output.extend(transcode_tensor(source, target, tensor)?);
+    }
+
+    Ok(output)
+}
+
+#[test]
+fn test_float_tensors_change_dtype() -> Result<(), String> {
+    let bytes = file();
+    let plan = plan(&bytes, DType::F16)?;
+    assert!(plan.changes_dtypes());
+
+    // The data section starts aligned
+    assert_eq!(plan.header.len() % 8, 0);
+    assert_eq!(plan.file_size, plan.header.len() as u64 + 12 + 8);
+
+    let (_, w) = &plan.tensors[0];
+    assert_eq!(w.dtype, "F16");
+    assert_eq!(w.data_offsets, (0, 12));
+
+    let (_, i) = &plan.tensors[1];
+    assert_eq!(i.dtype, "U32");
+    assert_eq!(i.data_offsets, (12, 20));
+
+    Ok(())
+}
+
+#[test]
+fn test_transcoded_file_loads_with_the_same_values() -> Result<(), String> {
+    let bytes = file();
+    let plan = plan(&bytes, DType::F16)?;
+    let output = transcode(&bytes, &plan)?;
+    assert_eq!(output.len() as u64, plan.file_size);
+
+    let start = HEADER_PREFIX_LEN as usize;
+    let len = header_len(&output)? as usize;
+    let tensors = parse_header(&output[start..start + len], output.len() as u64)?;
+    assert_eq!(tensors.len(), 2);
+
+    let mut loader = WeightsLoader::for_device(output.len() as u64, DType::F32, &Device::Cpu);
+    loader.push_bytes(&output)?;
+    let tensors = loader.finish()?;
+
+    let w: Vec<f32> = tensors["w"].flatten_all().unwrap().to_vec1().unwrap();
+    assert_eq!(w, VALUES.to_vec());
+    assert_eq!(tensors["i"].to_vec1::<u32>().unwrap(), vec![7, 9]);
+
+    Ok(())
+}
+
+#[test]
+fn test_files_already_in_dtype_are_unchanged() -> Result<(), String> {
+    let bytes = file();
+    let unchanged = plan(&bytes, DType::F32)?;
+    assert!(!unchanged.changes_dtypes());
+
+    // Transcoding a transcoded file again has nothing left to do
+    let output = transcode(&bytes, &plan(&bytes, DType::BF16)?)?;
+    let again = plan(&output, DType::BF16)?;
+    assert!(!again.changes_dtypes());
+    assert_eq!(again.file_size, output.len() as u64);
+
+    Ok(())
+}
+
+#[test]
+fn test_files_beyond_array_size_are_rejected() -> Result<(), String> {
+    let mut plan = plan(&file(), DType::F16)?;
+    assert!(plan.check_size().is_ok());
+
+    plan.file_size = 4 * 1024 * 1024 * 1024;
+    assert!(plan
+        .check_size()
+        .unwrap_err()
+        .contains("more than an array can hold"));
+
+    Ok(())
+}